@@ -1,6 +1,8 @@
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 
 use crate::ast::*;
+use rand::rngs::StdRng;
 use rand::Rng;
 
 macro_rules! insert {
@@ -49,6 +51,59 @@ macro_rules! number_args {
     }};
 }
 
+pub struct Signature {
+    pub name: &'static str,
+    pub parameters: &'static [(&'static str, LiteralType)],
+    pub returns: LiteralType,
+}
+
+impl Signature {
+    /// The signature as a dialect that calls the function `name` would write it.
+    pub fn written_as(&self, name: &str) -> String {
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|(name, literal_type)| format!("{} : {}", name, literal_type.keyword()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({}) RETURNS {}", name, parameters, self.returns.keyword())
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.written_as(self.name))
+    }
+}
+
+/// Static signatures of the appendix functions, `LEN` included, for tooling that never runs them.
+pub static SIGNATURES: [Signature; 4] = [
+    Signature {
+        name: "STR_TO_NUM",
+        parameters: &[("x", LiteralType::String)],
+        returns: LiteralType::Integer,
+    },
+    Signature {
+        name: "NUM_TO_STR",
+        parameters: &[("x", LiteralType::Any)],
+        returns: LiteralType::String,
+    },
+    Signature {
+        name: "RANDOMBETWEEN",
+        parameters: &[("min", LiteralType::Integer), ("max", LiteralType::Integer)],
+        returns: LiteralType::Integer,
+    },
+    Signature {
+        name: "LEN",
+        parameters: &[("array", LiteralType::Any)],
+        returns: LiteralType::Integer,
+    },
+];
+
+pub fn signature(name: &str) -> Option<&'static Signature> {
+    SIGNATURES.iter().find(|signature| signature.name == name)
+}
+
 pub fn built_ins() -> HashMap<String, Function> {
     let mut functions = HashMap::new();
 
@@ -57,22 +112,23 @@ pub fn built_ins() -> HashMap<String, Function> {
         str_to_num,
         "STR_TO_NUM",
         num_to_str,
-        "NUM_TO_STR",
-        randombetween,
-        "RANDOMBETWEEN"
+        "NUM_TO_STR"
     );
+    functions.insert(String::from("RANDOMBETWEEN"), Function::Random(randombetween));
 
     functions
 }
 
-fn randombetween(args: Vec<Literal>) -> Result<Literal, Execution> {
+fn randombetween(args: Vec<Literal>, rng: &mut StdRng) -> Result<Literal, Execution> {
     let args: [Literal; 2] = number_args!(args, "RANDOMBETWEEN", 2);
     let (lower, upper) = expect_return!(args,
         0 => Integer,
         1 => Integer
     );
-    let mut rng = rand::thread_rng();
-    return Ok(Literal::Integer(rng.gen_range(*lower..*upper)));
+    if lower >= upper {
+        return Err(Execution::EmptyRange(String::from("RANDOMBETWEEN"), *lower, *upper));
+    }
+    Ok(Literal::Integer(rng.gen_range(*lower..*upper)))
 }
 
 fn str_to_num(args: Vec<Literal>) -> Result<Literal, Execution> {
@@ -96,9 +152,9 @@ fn num_to_str(args: Vec<Literal>) -> Result<Literal, Execution> {
     );
     let string = match typed_args {
         (Ok(int), _) => int.to_string(),
-        (_, Ok(real)) => real.to_string(),
+        (_, Ok(real)) => Literal::Real(*real).to_string(),
         _ => wrong_type!(Integer, args[0]),
     };
 
-    return Ok(Literal::String(string));
+    Ok(Literal::String(string))
 }
@@ -1,4 +1,9 @@
 use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum DataTypes {
@@ -12,6 +17,8 @@ pub enum LiteralType {
     Real,
     String,
     Boolean,
+    Char,
+    Date,
     Any,
 }
 
@@ -37,16 +44,13 @@ pub enum Ops {
 
 pub const PRODUCTS: [Ops; 4] = [Ops::Multiply, Ops::Divide, Ops::Div, Ops::Mod];
 
-pub const COMPARE: [Ops; 9] = [
+pub const COMPARE: [Ops; 6] = [
     Ops::GreaterThan,
     Ops::LessThan,
     Ops::GreaterThanEqual,
     Ops::LessThanEqual,
     Ops::Equal,
     Ops::NotEqual,
-    Ops::And,
-    Ops::Or,
-    Ops::Not,
 ];
 
 pub const SUMS: [Ops; 3] = [Ops::Minus, Ops::Plus, Ops::Concatenate];
@@ -73,6 +77,7 @@ pub type Span = std::ops::Range<usize>;
 pub type Spanned<T> = (T, Span);
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Default)]
 pub enum Token {
     Declare,
     Identifier(String),
@@ -84,8 +89,11 @@ pub enum Token {
     In,
     Colon,
     DataType(DataTypes),
+    #[default]
     NewLine,
+    Comment(String),
     String(String),
+    Char(char),
     Operator(Ops),
     OpenBracket,
     CloseBracket,
@@ -108,13 +116,20 @@ pub enum Token {
     Of,
     OpenSquare,
     CloseSquare,
+    Test,
+    EndTest,
+    Assert,
+    Procedure,
+    EndProcedure,
+    Function,
+    EndFunction,
+    Returns,
+    Call,
+    ByVal,
+    ByRef,
+    Import,
 }
 
-impl Default for Token {
-    fn default() -> Self {
-        Token::NewLine
-    }
-}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
@@ -122,16 +137,98 @@ pub enum Literal {
     Real(f64),
     String(String),
     Bool(bool),
+    Char(char),
+    Date(Date),
+}
+
+/// A day of the calendar, ordered from earliest to latest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// Reads a date written day first, as the guides do, like `25/12/2024` or `1/2/2025`. Only
+    /// days that exist are dates, so `29/02/2023` is not.
+    pub fn parse(text: &str) -> Option<Date> {
+        let parts: Vec<&str> = text.split('/').collect();
+        let [day, month, year] = parts.as_slice() else {
+            return None;
+        };
+        fn digits<T: std::str::FromStr>(part: &str, lengths: RangeInclusive<usize>) -> Option<T> {
+            let valid = lengths.contains(&part.len()) && part.chars().all(|char| char.is_ascii_digit());
+            valid.then(|| part.parse().ok()).flatten()
+        }
+        let date = Date {
+            day: digits(day, 1..=2)?,
+            month: digits(month, 1..=2)?,
+            year: digits(year, 4..=4)?,
+        };
+        let leap = date.year.is_multiple_of(4)
+            && (!date.year.is_multiple_of(100) || date.year.is_multiple_of(400));
+        let days = match date.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return None,
+        };
+        (1..=days).contains(&date.day).then_some(date)
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}/{:02}/{:04}", self.day, self.month, self.year)
+    }
 }
 
+/// The bounds of an array once they are known, both of them valid indexes.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Bounds {
-    pub lower: usize,
-    pub upper: usize,
+    pub lower: isize,
+    pub upper: isize,
+}
+
+/// The bounds of an array as declared, worked out each time the declaration runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArrayBounds {
+    pub lower: Spanned<Expression>,
+    pub upper: Spanned<Expression>,
+}
+
+impl ArrayBounds {
+    /// Bounds written as whole numbers, as they are in a parameter's array.
+    pub fn new(bounds: Bounds) -> ArrayBounds {
+        ArrayBounds {
+            lower: (Expression::Value(Literal::Integer(bounds.lower)), 0..0),
+            upper: (Expression::Value(Literal::Integer(bounds.upper)), 0..0),
+        }
+    }
+
+    /// The bounds, when both are whole numbers.
+    pub fn constant(&self) -> Option<Bounds> {
+        Some(Bounds {
+            lower: self.lower.0.integer()?,
+            upper: self.upper.0.integer()?,
+        })
+    }
 }
 
 impl Eq for Literal {}
 
+impl Literal {
+    /// This value as stored in a variable of `target` type, an INTEGER widening to a REAL.
+    pub fn widen(self, target: &LiteralType) -> Literal {
+        match (self, target) {
+            (Literal::Integer(value), LiteralType::Real) => Literal::Real(value as f64),
+            (literal, _) => literal,
+        }
+    }
+}
+
 macro_rules! to_value {
     ($x:tt, $type: ident, $name: expr) => {
         impl From<&Literal> for $type {
@@ -164,10 +261,15 @@ pub enum Expression {
 impl Display for Literal {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Literal::Integer(value) => f.write_str(&*format!("{}", value)),
-            Literal::Real(value) => f.write_str(&*format!("{}", value)),
-            Literal::String(value) => f.write_str(&*format!("{}", value)),
-            Literal::Bool(value) => f.write_str(&*format!("{}", value)),
+            Literal::Integer(value) => f.write_str(&format!("{}", value)),
+            // a whole real keeps its decimal point, as the mark schemes write `3.0`
+            Literal::Real(value) if value.fract() == 0.0 => write!(f, "{:.1}", value),
+            Literal::Real(value) => f.write_str(&format!("{}", value)),
+            Literal::String(value) => f.write_str(&value.to_string()),
+            Literal::Bool(true) => f.write_str("TRUE"),
+            Literal::Bool(false) => f.write_str("FALSE"),
+            Literal::Char(value) => write!(f, "{}", value),
+            Literal::Date(value) => write!(f, "{}", value),
         }
     }
 }
@@ -186,12 +288,6 @@ pub enum Variable {
     },
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Parameter {
-    pub name: String,
-    pub literal_type: LiteralType,
-}
-
 impl From<&LiteralType> for Variable {
     fn from(literal_type: &LiteralType) -> Self {
         Variable::Literal {
@@ -209,23 +305,33 @@ impl From<&Literal> for LiteralType {
             Literal::Real { .. } => LiteralType::Real,
             Literal::String { .. } => LiteralType::String,
             Literal::Bool { .. } => LiteralType::Boolean,
+            Literal::Char { .. } => LiteralType::Char,
+            Literal::Date { .. } => LiteralType::Date,
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Statement {
-    Declare(Declare),
-    Out(Vec<Spanned<Expression>>),
+    /// A `DECLARE` of one or more variables, all of the same type.
+    Declare(Vec<Declare>),
+    /// `OUTPUT` of the values, ending the line unless the list ends in a comma.
+    Out(Vec<Spanned<Expression>>, bool),
     Assign(Assign),
-    In(String),
+    /// `INPUT` into a variable, or into an array element when there is an index.
+    In(String, Option<Spanned<Expression>>),
     If(
         Spanned<Expression>,
         Vec<Spanned<Statement>>,
         Option<Vec<Spanned<Statement>>>,
     ),
+    /// A `PROCEDURE` or `FUNCTION` definition, which may only appear at the top level.
+    Subroutine(Rc<Subroutine>),
     ProcedureCall(String, Vec<Spanned<Expression>>),
     Return(Option<Spanned<Expression>>),
+    /// A named block only run by `test`, in a state of its own.
+    Test(String, Vec<Spanned<Statement>>),
+    Assert(Spanned<Expression>, Option<String>),
     For(
         String,
         Spanned<Expression>,
@@ -234,12 +340,52 @@ pub enum Statement {
     ),
     While(Spanned<Expression>, Vec<Spanned<Statement>>),
     Repeat(Vec<Spanned<Statement>>, Spanned<Expression>),
+    /// An `IMPORT` of the subroutines and variables in another file, which may only appear at the
+    /// top level and is replaced by them when the program is loaded from a file.
+    Import(String),
+}
+
+/// How an argument is handed to a parameter: as a copy, or as the caller's variable, which gets
+/// whatever the parameter holds when the subroutine finishes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Passing {
+    ByValue,
+    ByReference,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    pub passing: Passing,
+    pub literal_type: LiteralType,
+    /// Whether the parameter takes a whole array of `literal_type`, with whatever bounds the
+    /// argument has.
+    pub array: bool,
+}
+
+impl Parameter {
+    /// The type as written after the parameter's name.
+    pub fn data_type(&self) -> String {
+        match self.array {
+            true => format!("ARRAY OF {}", self.literal_type.keyword()),
+            false => String::from(self.literal_type.keyword()),
+        }
+    }
+}
+
+/// A `PROCEDURE`, or a `FUNCTION` when it `returns` a type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subroutine {
+    pub name: String,
+    pub parameters: Vec<Spanned<Parameter>>,
+    pub returns: Option<LiteralType>,
+    pub body: Vec<Spanned<Statement>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Declare {
     Literal(String, LiteralType),
-    Array(String, Bounds, LiteralType),
+    Array(String, ArrayBounds, LiteralType),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -261,14 +407,32 @@ pub enum Execution {
     CanNotParse(String),
     AssignToConstant(String),
     InvalidBounds(Bounds),
-    OutOfBounds(String, usize),
-    NegativeIndex(String, isize),
-    IndexNotAssigned(String, usize),
+    OutOfBounds(String, isize, Bounds),
+    IndexNotAssigned(String, isize),
+    StepLimit(u64),
+    TimeLimit(Duration),
+    CallDepthLimit(usize),
+    ArraySizeLimit(usize),
+    EndOfInput,
+    AssertionFailed(Option<String>),
+    ByReference(String),
+    NoValue(String),
+    DivisionByZero(String),
+    Overflow(Ops),
+    EmptyRange(String, isize, isize),
+    InvalidInput(String, LiteralType),
+    ArrayMismatch(String, String),
+    CanNotImport(String, String),
+    ImportCycle(Vec<String>),
+    NotImportable,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug)]
 pub enum Function {
     BuiltIn(fn(Vec<Literal>) -> Result<Literal, Execution>),
+    /// A built-in drawing on the interpreter's seeded random number generator.
+    Random(fn(Vec<Literal>, &mut StdRng) -> Result<Literal, Execution>),
+    User(Rc<Subroutine>),
 }
 
 impl From<&LiteralType> for DataTypes {
@@ -291,11 +455,37 @@ impl Display for LiteralType {
             Real => "real",
             String => "string",
             Boolean => "boolean",
+            Char => "char",
+            Date => "date",
             Any => "any"
         })
     }
 }
 
+impl LiteralType {
+    /// The type a value of this type takes when stored where `target` is expected, INTEGER widening
+    /// to REAL.
+    pub fn widen(self, target: &LiteralType) -> LiteralType {
+        match (self, target) {
+            (LiteralType::Integer, LiteralType::Real) => LiteralType::Real,
+            (literal_type, _) => literal_type,
+        }
+    }
+
+    pub fn keyword(&self) -> &'static str {
+        use LiteralType::*;
+        match self {
+            Integer => "INTEGER",
+            Real => "REAL",
+            String => "STRING",
+            Boolean => "BOOLEAN",
+            Char => "CHAR",
+            Date => "DATE",
+            Any => "ANY",
+        }
+    }
+}
+
 impl Display for DataTypes {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -309,7 +499,7 @@ impl Display for Ops{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self{
             Ops::Plus => "plus",
-            Ops::Minus => "minues",
+            Ops::Minus => "minus",
             Ops::Divide => "divide",
             Ops::Multiply => "multiply",
             Ops::Concatenate => "concatenate",
@@ -328,9 +518,174 @@ impl Display for Ops{
     }
 }
 
+impl Ops {
+    /// The operator as written in pseudocode.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Ops::Plus => "+",
+            Ops::Minus => "-",
+            Ops::Divide => "/",
+            Ops::Multiply => "*",
+            Ops::Concatenate => "&",
+            Ops::GreaterThan => ">",
+            Ops::LessThan => "<",
+            Ops::GreaterThanEqual => ">=",
+            Ops::LessThanEqual => "<=",
+            Ops::Equal => "=",
+            Ops::NotEqual => "<>",
+            Ops::Mod => "MOD",
+            Ops::Div => "DIV",
+            Ops::And => "AND",
+            Ops::Or => "OR",
+            Ops::Not => "NOT",
+        }
+    }
+}
+
+impl Literal {
+    /// The literal as written in a program.
+    pub fn source(&self) -> String {
+        match self {
+            Literal::String(value) => format!("\"{}\"", value),
+            Literal::Char(value) => format!("'{}'", value),
+            // there is no way to write a date in a program, only to read one
+            literal => literal.to_string(),
+        }
+    }
+}
+
+impl Expression {
+    /// Binding strength of each expression as the parser builds them, higher binding tighter.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::Operate(Ops::Or, ..) => 0,
+            Expression::Operate(Ops::And, ..) => 1,
+            Expression::Not(_) => 2,
+            Expression::Operate(op, ..) if COMPARE.contains(op) => 3,
+            Expression::Operate(op, ..) if SUMS.contains(op) => 4,
+            Expression::Operate(..) => 5,
+            _ => 6,
+        }
+    }
+
+    /// Prints the expression with brackets when the parser would otherwise group it differently
+    /// under a parent of binding strength `parent`.
+    fn operand(&self, parent: u8, right: bool) -> String {
+        let precedence = self.precedence();
+        if precedence < parent || (right && precedence == parent) {
+            format!("({})", self)
+        } else {
+            self.to_string()
+        }
+    }
+
+    /// The value of a whole number written out, such as `5` or `-5`.
+    pub fn integer(&self) -> Option<isize> {
+        match self {
+            Expression::Value(Literal::Integer(value)) => Some(*value),
+            Expression::Negative(inner) => inner.0.integer()?.checked_neg(),
+            _ => None,
+        }
+    }
+}
+
+/// The arguments of a call, separated by commas.
+pub fn arguments(args: &[Spanned<Expression>]) -> String {
+    args.iter()
+        .map(|(arg, _)| arg.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Expressions print as they would be written in a program.
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Value(value) => f.write_str(&value.source()),
+            Expression::Variable(identifier) => f.write_str(identifier),
+            Expression::ArrayIndex(identifier, index) => write!(f, "{}[{}]", identifier, index.0),
+            Expression::FunctionCall(name, args) => write!(f, "{}({})", name, arguments(args)),
+            Expression::Negative(inner) => write!(f, "-{}", inner.0.operand(6, false)),
+            Expression::Not(inner) => write!(f, "NOT {}", inner.0.operand(2, false)),
+            Expression::Operate(op, a, b) => {
+                let parent = self.precedence();
+                write!(
+                    f,
+                    "{} {} {}",
+                    a.0.operand(parent, false),
+                    op.symbol(),
+                    b.0.operand(parent, true)
+                )
+            }
+        }
+    }
+}
+
 impl Display for Bounds{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!("lower: {}, upper: {}", self.lower, self.upper))
+        write!(f, "{}:{}", self.lower, self.upper)
+    }
+}
+
+impl Display for ArrayBounds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.lower.0, self.upper.0)
+    }
+}
+
+impl Display for Declare {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DECLARE {} : {}", self.identifier(), self.data_type())
+    }
+}
+
+impl Display for Subroutine {
+    /// The definition's first line, `BYREF` and `BYVAL` written only where the passing changes.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let keyword = match self.returns {
+            Some(_) => "FUNCTION",
+            None => "PROCEDURE",
+        };
+        write!(f, "{} {}", keyword, self.name)?;
+        if !self.parameters.is_empty() {
+            let mut passing = Passing::ByValue;
+            let parameters: Vec<String> = self
+                .parameters
+                .iter()
+                .map(|(parameter, _)| {
+                    let prefix = match (passing, parameter.passing) {
+                        (Passing::ByValue, Passing::ByReference) => "BYREF ",
+                        (Passing::ByReference, Passing::ByValue) => "BYVAL ",
+                        _ => "",
+                    };
+                    passing = parameter.passing;
+                    format!("{}{} : {}", prefix, parameter.name, parameter.data_type())
+                })
+                .collect();
+            write!(f, "({})", parameters.join(", "))?;
+        }
+        if let Some(returns) = &self.returns {
+            write!(f, " RETURNS {}", returns.keyword())?;
+        }
+        Ok(())
+    }
+}
+
+impl Declare {
+    pub fn identifier(&self) -> &str {
+        match self {
+            Declare::Literal(identifier, _) | Declare::Array(identifier, ..) => identifier,
+        }
+    }
+
+    /// The type as written after the variable's name.
+    pub fn data_type(&self) -> String {
+        match self {
+            Declare::Literal(_, literal_type) => String::from(literal_type.keyword()),
+            Declare::Array(_, bounds, literal_type) => {
+                format!("ARRAY[{}] OF {}", bounds, literal_type.keyword())
+            }
+        }
     }
 }
 
@@ -347,18 +702,75 @@ impl Display for Execution{
             AlreadyDeclared(identifier) => format!("Variable {} is already declared", identifier),
             IncorrectNumberArguments(identifier, expected, received) => 
             format!("Incorrect numer of arguments for \"{}\", expected {} but received {}", identifier, expected, received),
-            CanNotCallReturn => format!("Can not call return outside of a function or procedure"),
+            CanNotCallReturn => "Can not call return outside of a function or procedure".to_string(),
             CanNotParse(string) => format!("Can not parse string {} as number", string),
             AssignToConstant(identifier) => format!("Can not assign value to constant \"{}\"", identifier),
-            InvalidBounds(bounds) => format!("Invalid bounds {}", bounds),
-            OutOfBounds(identifier, index) => format!("Index {} is out of bounds for array {}", identifier, index),
-            IndexNotAssigned(identifier, index) => format!("Index {} not assigned for array {}", identifier, index),
-            NegativeIndex(identifier, index) => format!("Index {} is out of bounds for array {}", identifier, index),
+            InvalidBounds(bounds) => format!("Invalid bounds {}, the lower bound is above the upper bound", bounds),
+            OutOfBounds(identifier, index, bounds) => format!(
+                "Index {} is out of bounds for array {}, which goes from {} to {}",
+                index, identifier, bounds.lower, bounds.upper
+            ),
+            IndexNotAssigned(identifier, index) => format!("Index {} not assigned for array {}", index, identifier),
+            StepLimit(steps) => format!("Program stopped after running {} statements", steps),
+            TimeLimit(time) => format!("Program stopped after running for {:?}", time),
+            CallDepthLimit(depth) => format!("Program stopped after nesting more than {} blocks or calls deep", depth),
+            ArraySizeLimit(size) => format!("Arrays can not hold more than {} elements in total", size),
+            EndOfInput => "No input left to read".to_string(),
+            AssertionFailed(Some(message)) => format!("Assertion failed: {}", message),
+            AssertionFailed(None) => "Assertion failed".to_string(),
+            ByReference(parameter) => format!("Parameter {} is passed by reference, so its argument must be a variable", parameter),
+            NoValue(name) => format!("{} does not return a value", name),
+            DivisionByZero(dividend) => format!("Can not divide {} by zero", dividend),
+            Overflow(op) => format!("The result of operator \"{}\" is too large to hold", op),
+            EmptyRange(name, min, max) => format!("{} needs its minimum below its maximum, not {} and {}", name, min, max),
+            InvalidInput(input, literal_type) => format!("\"{}\" is not a valid {}", input, literal_type),
+            ArrayMismatch(a, b) => format!("Arrays {} and {} do not have the same type and bounds", a, b),
+            CanNotImport(path, reason) => format!("Can not import \"{}\": {}", path, reason),
+            ImportCycle(files) => format!("Files import each other in a cycle: {}", files.join(" imports ")),
+            NotImportable => "An imported file can only have declarations, assignments to the variables it declares, subroutines and tests".to_string(),
         };
         write!(f, "{}", message)
     }
 }
 
+impl Execution {
+    /// The variant's name, which expected-error files refer to errors by.
+    pub fn kind(&self) -> &'static str {
+        use Execution::*;
+        match self {
+            NotFound(..) => "NotFound",
+            NotAssigned(..) => "NotAssigned",
+            IncorrectType(..) => "IncorrectType",
+            BinaryNotSupported(..) => "BinaryNotSupported",
+            UnaryNotSupported(..) => "UnaryNotSupported",
+            AlreadyDeclared(..) => "AlreadyDeclared",
+            IncorrectNumberArguments(..) => "IncorrectNumberArguments",
+            CanNotCallReturn => "CanNotCallReturn",
+            CanNotParse(..) => "CanNotParse",
+            AssignToConstant(..) => "AssignToConstant",
+            InvalidBounds(..) => "InvalidBounds",
+            OutOfBounds(..) => "OutOfBounds",
+            IndexNotAssigned(..) => "IndexNotAssigned",
+            StepLimit(..) => "StepLimit",
+            TimeLimit(..) => "TimeLimit",
+            CallDepthLimit(..) => "CallDepthLimit",
+            ArraySizeLimit(..) => "ArraySizeLimit",
+            EndOfInput => "EndOfInput",
+            AssertionFailed(..) => "AssertionFailed",
+            ByReference(..) => "ByReference",
+            NoValue(..) => "NoValue",
+            DivisionByZero(..) => "DivisionByZero",
+            Overflow(..) => "Overflow",
+            EmptyRange(..) => "EmptyRange",
+            InvalidInput(..) => "InvalidInput",
+            ArrayMismatch(..) => "ArrayMismatch",
+            CanNotImport(..) => "CanNotImport",
+            ImportCycle(..) => "ImportCycle",
+            NotImportable => "NotImportable",
+        }
+    }
+}
+
 pub fn match_literal<'a>(
     a: &'a LiteralType,
     b: &'a LiteralType,
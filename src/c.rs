@@ -0,0 +1,1467 @@
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::lower::*;
+
+/// C's keywords, the library functions the runtime includes, and the runtime's own names.
+/// Pseudocode identifiers that match one get an underscore after them.
+const RESERVED: [&str; 113] = [
+    "auto",
+    "break",
+    "case",
+    "char",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+    "bool",
+    "true",
+    "false",
+    "NULL",
+    "main",
+    "abs",
+    "atof",
+    "atoi",
+    "calloc",
+    "div",
+    "errno",
+    "exit",
+    "fflush",
+    "fmod",
+    "fputc",
+    "fputs",
+    "free",
+    "getchar",
+    "isdigit",
+    "isfinite",
+    "isinf",
+    "isnan",
+    "isspace",
+    "labs",
+    "malloc",
+    "memcpy",
+    "printf",
+    "puts",
+    "rand",
+    "random",
+    "realloc",
+    "remove",
+    "rename",
+    "snprintf",
+    "srand",
+    "stderr",
+    "stdin",
+    "stdout",
+    "strcmp",
+    "strcpy",
+    "strlen",
+    "strtod",
+    "strtoll",
+    "system",
+    "time",
+    "toupper",
+    "vfprintf",
+    "fail",
+    "check_assigned",
+    "check_index",
+    "VALUE",
+    "SET",
+    "concat",
+    "integer_to_string",
+    "real_to_string",
+    "read_line",
+    "parse_integer",
+    "trim",
+    "input_integer",
+    "input_real",
+    "input_boolean",
+    "input_char",
+    "char_to_string",
+    "random_between",
+    "integer_add",
+    "integer_subtract",
+    "integer_multiply",
+    "integer_negate",
+    "divide",
+    "modulo",
+    "divide_real",
+    "no_value",
+    "assert_that",
+    "IntegerArray",
+    "RealArray",
+    "StringArray",
+    "BooleanArray",
+    "CharArray",
+    "array",
+];
+
+const UNKNOWN: &str = "a value's type could not be worked out";
+const NO_DATES: &str = "C has no type for DATE";
+
+/// A piece of the runtime, written into the file when the generated code calls it.
+struct Helper {
+    name: String,
+    includes: &'static [&'static str],
+    /// The other helpers it calls.
+    uses: Vec<String>,
+    code: String,
+}
+
+impl Helper {
+    fn new(
+        name: &str,
+        includes: &'static [&'static str],
+        uses: &'static [&'static str],
+        code: &str,
+    ) -> Helper {
+        Helper {
+            name: String::from(name),
+            includes,
+            uses: uses.iter().map(|used| String::from(*used)).collect(),
+            code: String::from(code),
+        }
+    }
+}
+
+/// The runtime, each helper after those it uses. Errors are reported with the interpreter's
+/// messages before exiting.
+fn runtime() -> Vec<Helper> {
+    let mut helpers = vec![
+        Helper::new(
+            "fail",
+            &["stdarg.h", "stdio.h", "stdlib.h"],
+            &[],
+            r#"static _Noreturn void fail(const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("Error: ", stderr);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}"#,
+        ),
+        Helper::new(
+            "VALUE",
+            &[],
+            &["fail"],
+            r#"static void check_assigned(bool assigned, const char *name) {
+    if (!assigned) {
+        fail("variable %s not assigned", name);
+    }
+}
+
+/* Reads a variable, failing if nothing has been assigned to it. */
+#define VALUE(name) (check_assigned(name##_assigned, #name), name)"#,
+        ),
+        Helper::new(
+            "SET",
+            &[],
+            &[],
+            "#define SET(name, value) (name = (value), name##_assigned = true)",
+        ),
+        Helper::new(
+            "check_index",
+            &[],
+            &["fail"],
+            r#"static long long check_index(const char *name, long long lower, long long upper, long long index) {
+    if (index < lower || index > upper) {
+        fail("Index %lld is out of bounds for array %s, which goes from %lld to %lld", index, name, lower, upper);
+    }
+    return index - lower;
+}"#,
+        ),
+    ];
+    for literal_type in [
+        LiteralType::Integer,
+        LiteralType::Real,
+        LiteralType::String,
+        LiteralType::Boolean,
+        LiteralType::Char,
+    ] {
+        let (c_type, stem, array) = (
+            c_type(&literal_type),
+            stem(&literal_type),
+            array_type(&literal_type),
+        );
+        let replace = |code: &str| {
+            code.replace(
+                "TYPE *",
+                &declaration(&format!("{}*", declaration(c_type, "")), ""),
+            )
+            .replace("TYPE*", &declaration(c_type, ""))
+            .replace("TYPE", c_type)
+            .replace("ARRAY", array)
+            .replace("STEM", stem)
+        };
+        let code = replace(
+            r#"typedef struct {
+    const char *name;
+    long long lower;
+    long long upper;
+    TYPE *values;
+    bool *assigned;
+} ARRAY;
+
+static ARRAY new_STEM_array(const char *name, long long lower, long long upper) {
+    ARRAY array = {name, lower, upper, NULL, NULL};
+    if (lower > upper) {
+        fail("Invalid bounds %lld:%lld, the lower bound is above the upper bound", lower, upper);
+    }
+    array.values = calloc(upper - lower + 1, sizeof(TYPE));
+    array.assigned = calloc(upper - lower + 1, sizeof(bool));
+    if (array.values == NULL || array.assigned == NULL) {
+        fail("Not enough memory for array %s", name);
+    }
+    return array;
+}
+
+static TYPE*STEM_get(ARRAY array, long long index) {
+    long long offset = check_index(array.name, array.lower, array.upper, index);
+    if (!array.assigned[offset]) {
+        fail("Index %lld not assigned for array %s", index, array.name);
+    }
+    return array.values[offset];
+}
+
+static void STEM_set(ARRAY array, long long index, TYPE*value) {
+    long long offset = check_index(array.name, array.lower, array.upper, index);
+    array.values[offset] = value;
+    array.assigned[offset] = true;
+}"#,
+        );
+        let equal = match literal_type {
+            LiteralType::String => "strcmp(STEM_get(a, index), STEM_get(b, index)) != 0",
+            _ => "STEM_get(a, index) != STEM_get(b, index)",
+        };
+        let new = format!("new_{}_array", stem);
+        helpers.extend([
+            Helper {
+                name: new.clone(),
+                includes: &["stdlib.h"],
+                uses: vec![String::from("fail"), String::from("check_index")],
+                code,
+            },
+            Helper {
+                name: format!("{}_copy", stem),
+                includes: &["string.h"],
+                uses: vec![String::from("fail"), new.clone()],
+                code: replace(
+                    r#"static void STEM_copy(ARRAY to, ARRAY from) {
+    if (to.lower != from.lower || to.upper != from.upper) {
+        fail("Arrays %s and %s do not have the same type and bounds", to.name, from.name);
+    }
+    memcpy(to.values, from.values, (to.upper - to.lower + 1) * sizeof(TYPE));
+    memcpy(to.assigned, from.assigned, (to.upper - to.lower + 1) * sizeof(bool));
+}"#,
+                ),
+            },
+            Helper {
+                name: format!("{}_clone", stem),
+                includes: &[],
+                uses: vec![new.clone(), format!("{}_copy", stem)],
+                code: replace(
+                    r#"static ARRAY STEM_clone(ARRAY array) {
+    ARRAY clone = new_STEM_array(array.name, array.lower, array.upper);
+    STEM_copy(clone, array);
+    return clone;
+}"#,
+                ),
+            },
+            Helper {
+                name: format!("{}_equal", stem),
+                includes: match literal_type {
+                    LiteralType::String => &["string.h"],
+                    _ => &[],
+                },
+                uses: vec![String::from("fail"), new],
+                code: replace(
+                    &r#"static bool STEM_equal(ARRAY a, ARRAY b) {
+    if (a.lower != b.lower || a.upper != b.upper) {
+        fail("Arrays %s and %s do not have the same type and bounds", a.name, b.name);
+    }
+    for (long long index = a.lower; index <= a.upper; index++) {
+        if (EQUAL) {
+            return false;
+        }
+    }
+    return true;
+}"#
+                    .replace("EQUAL", equal),
+                ),
+            },
+        ]);
+    }
+    helpers.extend([
+        Helper::new(
+            "concat",
+            &["string.h"],
+            &["fail"],
+            r#"static const char *concat(const char *a, const char *b) {
+    size_t length = strlen(a);
+    char *result = malloc(length + strlen(b) + 1);
+    if (result == NULL) {
+        fail("Not enough memory to join strings");
+    }
+    strcpy(result, a);
+    strcpy(result + length, b);
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "integer_to_string",
+            &[],
+            &[],
+            r#"static const char *integer_to_string(long long value) {
+    char *result = malloc(24);
+    snprintf(result, 24, "%lld", value);
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "real_to_string",
+            &["math.h", "string.h"],
+            &[],
+            r#"/* Writes a real with the fewest digits that read back as the same value, never in exponent form
+   and always with a decimal point. */
+static const char *real_to_string(double value) {
+    char digits[32];
+    char *result = malloc(400);
+    int precision, exponent, length = 0, count = 0, index;
+    if (isnan(value)) {
+        return "NaN";
+    }
+    if (isinf(value)) {
+        return value < 0 ? "-inf" : "inf";
+    }
+    for (precision = 0; precision < 17; precision++) {
+        snprintf(digits, sizeof digits, "%.*e", precision, value);
+        if (strtod(digits, NULL) == value) {
+            break;
+        }
+    }
+    exponent = atoi(strchr(digits, 'e') + 1);
+    if (value < 0 || (value == 0 && digits[0] == '-')) {
+        result[length++] = '-';
+    }
+    /* Keep just the significant digits, then strip trailing zeros. */
+    for (index = 0; digits[index] != 'e'; index++) {
+        if (digits[index] >= '0' && digits[index] <= '9') {
+            digits[count++] = digits[index];
+        }
+    }
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+    if (exponent < 0) {
+        result[length++] = '0';
+        result[length++] = '.';
+        for (index = -1; index > exponent; index--) {
+            result[length++] = '0';
+        }
+        for (index = 0; index < count; index++) {
+            result[length++] = digits[index];
+        }
+    } else {
+        for (index = 0; index <= exponent || index < count; index++) {
+            if (index == exponent + 1) {
+                result[length++] = '.';
+            }
+            result[length++] = index < count ? digits[index] : '0';
+        }
+        if (count <= exponent + 1) {
+            result[length++] = '.';
+            result[length++] = '0';
+        }
+    }
+    result[length] = '\0';
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "read_line",
+            &[],
+            &["fail"],
+            r#"static const char *read_line(void) {
+    size_t length = 0, capacity = 64;
+    char *line = malloc(capacity);
+    int next;
+    /* Shows a prompt written without a line ending before waiting. */
+    fflush(stdout);
+    while ((next = getchar()) != EOF && next != '\n') {
+        if (length + 1 == capacity) {
+            capacity *= 2;
+            line = realloc(line, capacity);
+        }
+        line[length++] = (char) next;
+    }
+    if (next == EOF && length == 0) {
+        fail("No input left to read");
+    }
+    if (length > 0 && line[length - 1] == '\r') {
+        length--;
+    }
+    line[length] = '\0';
+    return line;
+}"#,
+        ),
+        Helper::new(
+            "parse_integer",
+            &["ctype.h", "errno.h"],
+            &["fail"],
+            r#"static long long parse_integer(const char *string) {
+    const char *digits = string + (*string == '+' || *string == '-');
+    char *end;
+    long long value;
+    errno = 0;
+    value = strtoll(string, &end, 10);
+    if (!isdigit((unsigned char) *digits) || *end != '\0' || errno == ERANGE) {
+        fail("Can not parse string %s as number", string);
+    }
+    return value;
+}"#,
+        ),
+        Helper::new(
+            "trim",
+            &["ctype.h", "string.h"],
+            &[],
+            r#"static const char *trim(const char *string) {
+    size_t length;
+    char *result;
+    while (isspace((unsigned char) *string)) {
+        string++;
+    }
+    length = strlen(string);
+    while (length > 0 && isspace((unsigned char) string[length - 1])) {
+        length--;
+    }
+    result = malloc(length + 1);
+    memcpy(result, string, length);
+    result[length] = '\0';
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "input_integer",
+            &["ctype.h", "errno.h"],
+            &["fail", "trim"],
+            r#"static long long input_integer(const char *line) {
+    const char *string = trim(line);
+    const char *digits = string + (*string == '+' || *string == '-');
+    char *end;
+    long long value;
+    errno = 0;
+    value = strtoll(string, &end, 10);
+    if (!isdigit((unsigned char) *digits) || *end != '\0' || errno == ERANGE) {
+        fail("\"%s\" is not a valid integer", line);
+    }
+    return value;
+}"#,
+        ),
+        Helper::new(
+            "input_real",
+            &["math.h"],
+            &["fail", "trim"],
+            r#"static double input_real(const char *line) {
+    const char *string = trim(line);
+    char *end;
+    double value = strtod(string, &end);
+    if (*string == '\0' || *end != '\0' || !isfinite(value)) {
+        fail("\"%s\" is not a valid real", line);
+    }
+    return value;
+}"#,
+        ),
+        Helper::new(
+            "input_boolean",
+            &["ctype.h"],
+            &["fail", "trim"],
+            r#"static bool input_boolean(const char *line) {
+    const char *string = trim(line);
+    const char *words[] = {"FALSE", "TRUE"};
+    int word, index;
+    for (word = 0; word < 2; word++) {
+        for (index = 0; toupper((unsigned char) string[index]) == words[word][index]; index++) {
+            if (string[index] == '\0') {
+                return word == 1;
+            }
+        }
+    }
+    fail("\"%s\" is not a valid boolean", line);
+}"#,
+        ),
+        Helper::new(
+            "input_char",
+            &[],
+            &["fail"],
+            r#"static char input_char(const char *line) {
+    if (line[0] == '\0' || line[1] != '\0') {
+        fail("\"%s\" is not a valid char", line);
+    }
+    return line[0];
+}"#,
+        ),
+        Helper::new(
+            "char_to_string",
+            &[],
+            &[],
+            r#"static const char *char_to_string(char value) {
+    char *result = malloc(2);
+    result[0] = value;
+    result[1] = '\0';
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "random_between",
+            &["time.h"],
+            &["fail"],
+            r#"static long long random_between(long long min, long long max) {
+    static bool seeded = false;
+    if (!seeded) {
+        srand((unsigned) time(NULL));
+        seeded = true;
+    }
+    if (min >= max) {
+        fail("RANDOMBETWEEN needs its minimum below its maximum, not %lld and %lld", min, max);
+    }
+    return min + (long long) ((double) rand() / ((double) RAND_MAX + 1) * (double) (max - min));
+}"#,
+        ),
+        Helper::new(
+            "integer_add",
+            &[],
+            &["fail"],
+            r#"static long long integer_add(long long a, long long b) {
+    long long result;
+    if (__builtin_add_overflow(a, b, &result)) {
+        fail("The result of operator \"plus\" is too large to hold");
+    }
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "integer_subtract",
+            &[],
+            &["fail"],
+            r#"static long long integer_subtract(long long a, long long b) {
+    long long result;
+    if (__builtin_sub_overflow(a, b, &result)) {
+        fail("The result of operator \"minus\" is too large to hold");
+    }
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "integer_multiply",
+            &[],
+            &["fail"],
+            r#"static long long integer_multiply(long long a, long long b) {
+    long long result;
+    if (__builtin_mul_overflow(a, b, &result)) {
+        fail("The result of operator \"multiply\" is too large to hold");
+    }
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "integer_negate",
+            &[],
+            &["fail"],
+            r#"static long long integer_negate(long long a) {
+    long long result;
+    if (__builtin_sub_overflow(0, a, &result)) {
+        fail("The result of operator \"minus\" is too large to hold");
+    }
+    return result;
+}"#,
+        ),
+        Helper::new(
+            "divide",
+            &[],
+            &["fail"],
+            r#"static long long divide(long long a, long long b) {
+    if (b == 0) {
+        fail("Can not divide %lld by zero", a);
+    }
+    return a / b;
+}"#,
+        ),
+        Helper::new(
+            "modulo",
+            &[],
+            &["fail"],
+            r#"static long long modulo(long long a, long long b) {
+    if (b == 0) {
+        fail("Can not divide %lld by zero", a);
+    }
+    return a % b;
+}"#,
+        ),
+        Helper::new(
+            "divide_real",
+            &[],
+            &["fail", "real_to_string"],
+            r#"static double divide_real(double a, double b) {
+    if (b == 0) {
+        fail("Can not divide %s by zero", real_to_string(a));
+    }
+    return a / b;
+}"#,
+        ),
+        Helper::new(
+            "no_value",
+            &[],
+            &["fail"],
+            r#"static _Noreturn void no_value(const char *name) {
+    fail("%s does not return a value", name);
+}"#,
+        ),
+        Helper::new(
+            "assert_that",
+            &[],
+            &["fail"],
+            r#"static void assert_that(bool condition, const char *message) {
+    if (!condition) {
+        fail(message == NULL ? "Assertion failed" : "Assertion failed: %s", message);
+    }
+}"#,
+        ),
+    ]);
+    helpers
+}
+
+/// Writes a lowered program as a single C file, with the runtime it needs at the top.
+///
+/// Values keep the pseudocode's semantics where C's would differ: arrays keep their bounds,
+/// and reading a variable or array element that was never assigned, indexing outside an array,
+/// overflowing an integer or dividing one by zero stops the program with the interpreter's
+/// message. Strings are
+/// never freed. Every type has to be known, so undeclared variables are reported instead of
+/// written, as are `BYREF` arguments that are not variables.
+pub fn c(program: &Program) -> Result<String, String> {
+    let mut c = C {
+        writer: Writer::new("    "),
+        program,
+        current: None,
+        checked: HashSet::new(),
+        references: HashSet::new(),
+        unknown: vec![],
+    };
+    if !program.subroutines.is_empty() {
+        c.writer.blank();
+        for routine in &program.subroutines {
+            let signature = c.signature(routine);
+            c.writer.line(&format!("{};", signature));
+        }
+    }
+    for routine in &program.subroutines {
+        c.writer.blank();
+        c.routine(routine, &name(&routine.name), true);
+    }
+    c.writer.blank();
+    c.writer.line("int main(void) {");
+    c.checked = scalars(program.variables.iter());
+    c.body(&program.main);
+    c.writer.indent();
+    c.writer.line("return 0;");
+    c.writer.dedent();
+    c.writer.line("}");
+    for test in &program.tests {
+        c.writer.blank();
+        c.routine(test, &test_name(&test.name), false);
+    }
+    for comment in &program.footer {
+        c.comment(comment);
+    }
+
+    if !c.unknown.is_empty() {
+        // An undeclared variable explains the expressions it is in.
+        if c.unknown.len() > 1 {
+            c.unknown.retain(|reason| reason != UNKNOWN);
+        }
+        return Err(c.unknown.join(", "));
+    }
+    // Variables only some subroutines could see are left out when nothing uses them.
+    let mut globals = String::new();
+    for variable in &program.variables {
+        if !mentions(&c.writer.output, &name(variable.identifier())) {
+            continue;
+        }
+        globals += &match variable {
+            Declared::Literal(identifier, literal_type) => format!(
+                "static {};\nstatic bool {}_assigned;\n",
+                declaration(c_type(literal_type), &name(identifier)),
+                name(identifier)
+            ),
+            Declared::Array(identifier, .., literal_type) => {
+                format!(
+                    "static {} {};\n",
+                    array_type(literal_type),
+                    name(identifier)
+                )
+            }
+        };
+    }
+    let code = c.writer.output;
+    let helpers = runtime();
+    let mut needed: Vec<bool> = helpers
+        .iter()
+        .map(|helper| invokes(&code, &helper.name))
+        .collect();
+    // Helpers come after those they use, so one pass from the end picks up every dependency.
+    for index in (0..helpers.len()).rev() {
+        if needed[index] {
+            for used in &helpers[index].uses {
+                if let Some(position) = helpers.iter().position(|helper| helper.name == *used) {
+                    needed[position] = true;
+                }
+            }
+        }
+    }
+    let helpers: Vec<&Helper> = helpers
+        .iter()
+        .zip(needed)
+        .filter_map(|(helper, needed)| needed.then_some(helper))
+        .collect();
+
+    let mut includes = vec!["stdbool.h", "stdio.h", "stdlib.h"];
+    for helper in &helpers {
+        for include in helper.includes {
+            if !includes.contains(include) {
+                includes.push(include);
+            }
+        }
+    }
+    for (function, include) in [("fmod", "math.h"), ("strcmp", "string.h")] {
+        if invokes(&code, function) && !includes.contains(&include) {
+            includes.push(include);
+        }
+    }
+    includes.sort_unstable();
+
+    let mut output = String::new();
+    for comment in &program.header {
+        output += &format!("//{}\n", comment);
+    }
+    for include in includes {
+        output += &format!("#include <{}>\n", include);
+    }
+    for helper in helpers {
+        output += &format!("\n{}\n", helper.code);
+    }
+    if !globals.is_empty() {
+        output += &format!("\n{}", globals);
+    }
+    if !code.starts_with('\n') {
+        output.push('\n');
+    }
+    output += &code;
+    Ok(output)
+}
+
+fn name(identifier: &str) -> String {
+    if RESERVED.contains(&identifier) {
+        format!("{}_", identifier)
+    } else {
+        String::from(identifier)
+    }
+}
+
+fn c_type(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer | LiteralType::Any => "long long",
+        LiteralType::Real => "double",
+        LiteralType::String => "const char *",
+        LiteralType::Boolean => "bool",
+        LiteralType::Char => "char",
+        // never written, since the program is rejected
+        LiteralType::Date => "void",
+    }
+}
+
+/// The word the runtime's array functions for a type are named with.
+fn stem(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer | LiteralType::Any => "integer",
+        LiteralType::Real => "real",
+        LiteralType::String => "string",
+        LiteralType::Boolean => "boolean",
+        LiteralType::Char => "char",
+        LiteralType::Date => "date",
+    }
+}
+
+fn array_type(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer | LiteralType::Any => "IntegerArray",
+        LiteralType::Real => "RealArray",
+        LiteralType::String => "StringArray",
+        LiteralType::Boolean => "BooleanArray",
+        LiteralType::Char => "CharArray",
+        LiteralType::Date => "DateArray",
+    }
+}
+
+fn default(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer | LiteralType::Any => "0",
+        LiteralType::Real => "0.0",
+        LiteralType::String => "\"\"",
+        LiteralType::Boolean => "false",
+        LiteralType::Char => "' '",
+        LiteralType::Date => "0",
+    }
+}
+
+/// `c_type` followed by `name`, without a space after a pointer's `*`.
+fn declaration(c_type: &str, name: &str) -> String {
+    if c_type.ends_with('*') {
+        format!("{}{}", c_type, name)
+    } else {
+        format!("{} {}", c_type, name)
+    }
+}
+
+/// The scalar variables among `declares`, whose reads are checked.
+fn scalars<'a>(declares: impl Iterator<Item = &'a Declared>) -> HashSet<String> {
+    declares
+        .filter_map(|declare| match declare {
+            Declared::Literal(identifier, _) => Some(String::from(identifier)),
+            Declared::Array(..) => None,
+        })
+        .collect()
+}
+
+/// `TEST "adds up"` becomes `test_adds_up`.
+fn test_name(name: &str) -> String {
+    name.split(|char: char| !char.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .fold(String::from("test"), |name, word| {
+            name + "_" + &word.to_lowercase()
+        })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn literal(literal: &Literal) -> String {
+    match literal {
+        Literal::String(value) => format!("\"{}\"", escape(value)),
+        Literal::Bool(value) => value.to_string(),
+        literal => literal.source(),
+    }
+}
+
+fn operator(op: &Ops) -> &'static str {
+    match op {
+        Ops::Plus | Ops::Concatenate => "+",
+        Ops::Minus => "-",
+        Ops::Divide | Ops::Div => "/",
+        Ops::Multiply => "*",
+        Ops::GreaterThan => ">",
+        Ops::LessThan => "<",
+        Ops::GreaterThanEqual => ">=",
+        Ops::LessThanEqual => "<=",
+        Ops::Equal => "==",
+        Ops::NotEqual => "!=",
+        Ops::Mod => "%",
+        Ops::And => "&&",
+        Ops::Or => "||",
+        Ops::Not => "!",
+    }
+}
+
+fn both(a: &Typed, b: &Typed, literal_type: LiteralType) -> bool {
+    a.literal_type == literal_type && b.literal_type == literal_type
+}
+
+struct C<'a> {
+    writer: Writer,
+    program: &'a Program,
+    /// The routine being written, if any.
+    current: Option<&'a Routine>,
+    /// Variables with an `_assigned` flag that reads are checked against.
+    checked: HashSet<String>,
+    /// The `BYREF` parameters of the procedure being written, which are pointers.
+    references: HashSet<String>,
+    /// Why the program could not be written, since C needs every type to be known and a
+    /// `BYREF` argument to point to.
+    unknown: Vec<String>,
+}
+
+impl<'a> C<'a> {
+    fn unknown(&mut self, reason: String) {
+        if !self.unknown.contains(&reason) {
+            self.unknown.push(reason);
+        }
+    }
+
+    fn c_type(&mut self, literal_type: &LiteralType) -> &'static str {
+        match literal_type {
+            LiteralType::Any => self.unknown(String::from(UNKNOWN)),
+            LiteralType::Date => self.unknown(String::from(NO_DATES)),
+            _ => {}
+        }
+        c_type(literal_type)
+    }
+
+    fn array_type(&mut self, literal_type: &LiteralType) -> &'static str {
+        self.c_type(literal_type);
+        array_type(literal_type)
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.writer.line(&format!("//{}", text));
+    }
+
+    /// Binding strength in C, higher binding tighter. Function calls and macros bind tightest.
+    fn precedence(&self, typed: &Typed) -> u8 {
+        match &typed.expression {
+            Expr::Variable(identifier) if self.references.contains(identifier) => 7,
+            Expr::Operate(Ops::Or, ..) => 1,
+            Expr::Operate(Ops::And, ..) => 2,
+            Expr::Operate(Ops::Equal | Ops::NotEqual, ..) => 3,
+            Expr::Operate(Ops::Concatenate, ..) => 8,
+            Expr::Operate(op, ..) if COMPARE.contains(op) => 4,
+            Expr::Operate(Ops::Div | Ops::Mod, a, b) if both(a, b, LiteralType::Integer) => 8,
+            Expr::Operate(Ops::Plus | Ops::Minus | Ops::Multiply, a, b)
+                if both(a, b, LiteralType::Integer) =>
+            {
+                8
+            }
+            Expr::Operate(Ops::Mod | Ops::Divide, ..) => 8,
+            Expr::Operate(Ops::Div, ..) => 7,
+            Expr::Operate(op, ..) if SUMS.contains(op) => 5,
+            Expr::Operate(..) => 6,
+            Expr::Length(..) => 5,
+            Expr::Negative(inner)
+                if inner.literal_type == LiteralType::Integer && typed.integer().is_none() =>
+            {
+                8
+            }
+            Expr::Negative(_) | Expr::Not(_) => 7,
+            _ => 8,
+        }
+    }
+
+    /// Prints `inner` under a parent binding with strength `outer`, bracketed where C would
+    /// otherwise group it differently. Comparisons are bracketed inside each other for clarity.
+    fn operand(&mut self, inner: &Typed, outer: u8, right: bool) -> String {
+        if needs_brackets(self.precedence(inner), outer, right, 3..5) {
+            format!("({})", self.expression(inner))
+        } else {
+            self.expression(inner)
+        }
+    }
+
+    fn arguments(&mut self, args: &[Typed]) -> String {
+        args.iter()
+            .map(|arg| self.expression(arg))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// A value as a string, for joining with `&`.
+    fn string(&mut self, typed: &Typed) -> String {
+        match typed.literal_type {
+            LiteralType::Integer => format!("integer_to_string({})", self.expression(typed)),
+            LiteralType::Real => format!("real_to_string({})", self.expression(typed)),
+            LiteralType::Boolean => {
+                format!("{} ? \"TRUE\" : \"FALSE\"", self.expression(typed))
+            }
+            LiteralType::Char => format!("char_to_string({})", self.expression(typed)),
+            _ => self.expression(typed),
+        }
+    }
+
+    fn expression(&mut self, typed: &Typed) -> String {
+        match (&typed.literal_type, &typed.expression) {
+            (LiteralType::Any, Expr::Variable(identifier)) => {
+                self.unknown(format!("{} is never declared", identifier))
+            }
+            (LiteralType::Any, _) => self.unknown(String::from(UNKNOWN)),
+            (LiteralType::Date, _) => self.unknown(String::from(NO_DATES)),
+            _ => {}
+        }
+        match &typed.expression {
+            Expr::Value(value) => literal(value),
+            Expr::Variable(identifier) if self.references.contains(identifier) => {
+                format!("*{}", name(identifier))
+            }
+            Expr::Variable(identifier) if self.checked.contains(identifier) => {
+                format!("VALUE({})", name(identifier))
+            }
+            Expr::Variable(identifier) => name(identifier),
+            Expr::Index(identifier, index, _) => format!(
+                "{}_get({}, {})",
+                stem(&typed.literal_type),
+                name(identifier),
+                self.expression(index)
+            ),
+            Expr::Length(identifier, _) => {
+                let identifier = name(identifier);
+                format!("{}.upper - {}.lower + 1", identifier, identifier)
+            }
+            Expr::ArraysEqual(a, b) => format!(
+                "{}_equal({}, {})",
+                stem(&a.literal_type),
+                self.expression(a),
+                self.expression(b)
+            ),
+            Expr::ArrayCopy(identifier) => {
+                format!("{}_clone({})", stem(&typed.literal_type), name(identifier))
+            }
+            Expr::BuiltIn(BuiltIn::StrToNum, args) => {
+                format!("parse_integer({})", self.arguments(args))
+            }
+            Expr::BuiltIn(BuiltIn::NumToStr, args) => match args.first() {
+                Some(arg) if arg.literal_type == LiteralType::Real => {
+                    format!("real_to_string({})", self.expression(arg))
+                }
+                _ => format!("integer_to_string({})", self.arguments(args)),
+            },
+            Expr::BuiltIn(BuiltIn::RandomBetween, args) => {
+                format!("random_between({})", self.arguments(args))
+            }
+            Expr::Call(function, args) => {
+                format!("{}({})", name(function), self.arguments(args))
+            }
+            // Integers are checked for overflow, as the interpreter does, rather than wrapping.
+            Expr::Negative(inner)
+                if inner.literal_type == LiteralType::Integer && typed.integer().is_none() =>
+            {
+                format!("integer_negate({})", self.expression(inner))
+            }
+            Expr::Negative(inner) => format!("-{}", self.operand(inner, 7, false)),
+            Expr::Not(inner) => format!("!{}", self.operand(inner, 7, false)),
+            // Strings are compared by value with `strcmp`.
+            Expr::Operate(op, a, b)
+                if COMPARE.contains(op) && a.literal_type == LiteralType::String =>
+            {
+                format!(
+                    "strcmp({}, {}) {} 0",
+                    self.expression(a),
+                    self.expression(b),
+                    operator(op)
+                )
+            }
+            Expr::Input(literal_type) => String::from(match literal_type {
+                LiteralType::Integer => "input_integer(read_line())",
+                LiteralType::Real => "input_real(read_line())",
+                LiteralType::Boolean => "input_boolean(read_line())",
+                LiteralType::Char => "input_char(read_line())",
+                LiteralType::String | LiteralType::Date | LiteralType::Any => "read_line()",
+            }),
+            Expr::Operate(Ops::Concatenate, a, b) => {
+                format!("concat({}, {})", self.string(a), self.string(b))
+            }
+            // The helper's parameters are doubles, so two integers are not divided as integers.
+            Expr::Operate(Ops::Divide, a, b) => {
+                format!("divide_real({}, {})", self.expression(a), self.expression(b))
+            }
+            Expr::Operate(Ops::Div, a, b) if both(a, b, LiteralType::Integer) => {
+                format!("divide({}, {})", self.expression(a), self.expression(b))
+            }
+            Expr::Operate(op @ (Ops::Plus | Ops::Minus | Ops::Multiply), a, b)
+                if both(a, b, LiteralType::Integer) =>
+            {
+                let helper = match op {
+                    Ops::Plus => "integer_add",
+                    Ops::Minus => "integer_subtract",
+                    _ => "integer_multiply",
+                };
+                format!("{}({}, {})", helper, self.expression(a), self.expression(b))
+            }
+            Expr::Operate(Ops::Mod, a, b) if both(a, b, LiteralType::Integer) => {
+                format!("modulo({}, {})", self.expression(a), self.expression(b))
+            }
+            Expr::Operate(Ops::Div, a, b) => format!(
+                "(long long) divide_real({}, {})",
+                self.expression(a),
+                self.expression(b)
+            ),
+            Expr::Operate(Ops::Mod, a, b) => {
+                format!("fmod({}, {})", self.expression(a), self.expression(b))
+            }
+            Expr::Operate(op, a, b) => {
+                let outer = self.precedence(typed);
+                format!(
+                    "{} {} {}",
+                    self.operand(a, outer, false),
+                    operator(op),
+                    self.operand(b, outer, true)
+                )
+            }
+        }
+    }
+
+    /// Assigns `value` to a variable, marking it assigned or writing through its pointer.
+    fn assign(&self, identifier: &str, value: &str) -> String {
+        if self.references.contains(identifier) {
+            format!("*{} = {}", name(identifier), value)
+        } else if self.checked.contains(identifier) {
+            format!("SET({}, {})", name(identifier), value)
+        } else {
+            format!("{} = {}", name(identifier), value)
+        }
+    }
+
+    fn signature(&mut self, routine: &Routine) -> String {
+        let by_reference = routine.by_reference();
+        let parameters = routine
+            .parameters
+            .iter()
+            .map(|parameter| {
+                let pointer = if by_reference.contains(&parameter) {
+                    "*"
+                } else {
+                    ""
+                };
+                let c_type = match parameter.array {
+                    true => self.array_type(&parameter.literal_type),
+                    false => self.c_type(&parameter.literal_type),
+                };
+                declaration(c_type, &format!("{}{}", pointer, name(&parameter.name)))
+            })
+            .collect::<Vec<_>>();
+        let parameters = match parameters.is_empty() {
+            true => String::from("void"),
+            false => parameters.join(", "),
+        };
+        let returns = match &routine.returns {
+            Some(returns) => self.c_type(returns),
+            None => "void",
+        };
+        format!(
+            "static {}({})",
+            declaration(returns, &name(&routine.name)),
+            parameters
+        )
+    }
+
+    fn routine(&mut self, routine: &'a Routine, name: &str, subroutine: bool) {
+        for comment in &routine.comments {
+            self.comment(comment);
+        }
+        let signature = match subroutine {
+            true => self.signature(routine),
+            false => format!("void {}(void)", name),
+        };
+        self.writer.line(&format!("{} {{", signature));
+        self.writer.indent();
+        if routine.returns.is_some()
+            && routine
+                .parameters
+                .iter()
+                .any(|parameter| parameter.passing == Passing::ByReference)
+        {
+            self.comment(" BYREF parameters are passed by value, since a function only hands back its result");
+        }
+        let locals = declares(&routine.body);
+        for declare in &locals {
+            match declare {
+                Declared::Literal(identifier, literal_type) => {
+                    let c_type = self.c_type(literal_type);
+                    self.writer.line(&format!(
+                        "{} = {};",
+                        declaration(c_type, &self::name(identifier)),
+                        default(literal_type)
+                    ));
+                    self.writer.line(&format!(
+                        "bool {}_assigned = false;",
+                        self::name(identifier)
+                    ));
+                }
+                Declared::Array(identifier, .., literal_type) => {
+                    let array = self.array_type(literal_type);
+                    self.writer
+                        .line(&format!("{} {};", array, self::name(identifier)));
+                }
+            }
+        }
+        let parameters: HashSet<String> = routine
+            .parameters
+            .iter()
+            .map(|parameter| parameter.name.clone())
+            .collect();
+        let mut checked = scalars(locals.into_iter());
+        if subroutine {
+            checked.extend(scalars(self.program.variables.iter()));
+        }
+        self.checked = &checked - &parameters;
+        self.references = routine
+            .by_reference()
+            .iter()
+            .map(|parameter| parameter.name.clone())
+            .collect();
+        self.current = Some(routine);
+        self.block(&routine.body);
+        if let (Some(_), false) = (&routine.returns, routine.ends_in_return()) {
+            self.writer.line(&format!(
+                "no_value({});",
+                literal(&Literal::String(routine.name.clone()))
+            ));
+        }
+        self.current = None;
+        self.references.clear();
+        self.writer.dedent();
+        self.writer.line("}");
+        if let Some(comment) = &routine.trailing {
+            self.writer.trailing(&format!("//{}", comment));
+        }
+    }
+
+    /// Writes a block indented inside braces, leaving the closing brace to the caller.
+    fn body(&mut self, block: &Block) {
+        self.writer.indent();
+        self.block(block);
+        self.writer.dedent();
+    }
+
+    fn block(&mut self, block: &Block) {
+        for line in block {
+            match line {
+                Line::Comment(text) => self.comment(text),
+                Line::Blank => self.writer.blank(),
+                // Variables are declared at the top of the file or the routine.
+                Line::Statement(Lowered::Declare(declares), trailing)
+                    if declares
+                        .iter()
+                        .all(|declare| matches!(declare, Declared::Literal(..))) =>
+                {
+                    if let Some(comment) = trailing {
+                        self.comment(comment);
+                    }
+                }
+                Line::Statement(statement, trailing) => {
+                    self.statement(statement);
+                    if let Some(comment) = trailing {
+                        self.writer.trailing(&format!("//{}", comment));
+                    }
+                }
+            }
+        }
+    }
+
+    /// An `OUTPUT` as one `printf`, with literal values written into the format.
+    fn output(&mut self, values: &[Typed], newline: bool) -> String {
+        let mut format = String::new();
+        let mut args = vec![];
+        for value in values {
+            let (specifier, arg) = match (&value.expression, &value.literal_type) {
+                (Expr::Value(literal), _) => {
+                    format.push_str(&literal.to_string().replace('%', "%%"));
+                    continue;
+                }
+                (_, LiteralType::Real) => ("%s", self.string(value)),
+                (_, LiteralType::String) => ("%s", self.expression(value)),
+                (_, LiteralType::Boolean) => ("%s", self.string(value)),
+                (_, LiteralType::Char) => ("%c", self.expression(value)),
+                _ => ("%lld", self.expression(value)),
+            };
+            format.push_str(specifier);
+            args.push(arg);
+        }
+        let newline = if newline { "\\n" } else { "" };
+        args.insert(0, format!("\"{}{}\"", escape(&format), newline));
+        format!("printf({});", args.join(", "))
+    }
+
+    /// A procedure call, passing variables for `BYREF` parameters by their address.
+    fn call(&mut self, function: &str, args: &[Typed]) -> Vec<String> {
+        // the name of each parameter passed by reference
+        let by_reference: Vec<Option<String>> = match self
+            .program
+            .subroutines
+            .iter()
+            .find(|routine| routine.name == function)
+        {
+            Some(routine) => {
+                let by_reference = routine.by_reference();
+                routine
+                    .parameters
+                    .iter()
+                    .map(|parameter| {
+                        by_reference
+                            .contains(&parameter)
+                            .then(|| parameter.name.clone())
+                    })
+                    .collect()
+            }
+            None => vec![],
+        };
+        let mut assigned = vec![];
+        let args: Vec<String> = args
+            .iter()
+            .enumerate()
+            .map(
+                |(index, arg)| match (by_reference.get(index), &arg.expression) {
+                    (Some(Some(_)), Expr::Variable(identifier))
+                        if self.references.contains(identifier) =>
+                    {
+                        name(identifier)
+                    }
+                    (Some(Some(_)), Expr::Variable(identifier)) => {
+                        if self.checked.contains(identifier) {
+                            assigned.push(format!("{}_assigned = true;", name(identifier)));
+                        }
+                        format!("&{}", name(identifier))
+                    }
+                    // There is nothing to point to, and the interpreter would stop here.
+                    (Some(Some(parameter)), _) => {
+                        self.unknown(Execution::ByReference(parameter.clone()).to_string());
+                        self.expression(arg)
+                    }
+                    _ => self.expression(arg),
+                },
+            )
+            .collect();
+        let mut lines = vec![format!("{}({});", name(function), args.join(", "))];
+        lines.extend(assigned);
+        lines
+    }
+
+    fn statement(&mut self, statement: &Lowered) {
+        match statement {
+            Lowered::Declare(declares) => {
+                for declare in declares {
+                    if let Declared::Array(identifier, lower, upper, literal_type) = declare {
+                        let line = format!(
+                            "{} = new_{}_array({}, {}, {});",
+                            name(identifier),
+                            stem(literal_type),
+                            literal(&Literal::String(name(identifier))),
+                            self.expression(lower),
+                            self.expression(upper)
+                        );
+                        self.writer.line(&line)
+                    }
+                }
+            }
+            Lowered::CopyArray(to, from) => {
+                let line = format!(
+                    "{}_copy({}, {});",
+                    stem(&from.literal_type),
+                    name(to),
+                    self.expression(from)
+                );
+                self.writer.line(&line)
+            }
+            Lowered::Assign(identifier, value) => {
+                let value = self.expression(value);
+                let line = self.assign(identifier, &value);
+                self.writer.line(&format!("{};", line))
+            }
+            Lowered::AssignIndex(identifier, index, value, _) => {
+                let line = format!(
+                    "{}_set({}, {}, {});",
+                    stem(&value.literal_type),
+                    name(identifier),
+                    self.expression(index),
+                    self.expression(value)
+                );
+                self.writer.line(&line)
+            }
+            Lowered::Output(values, newline) => {
+                let line = self.output(values, *newline);
+                self.writer.line(&line)
+            }
+            Lowered::If(branches, otherwise) => {
+                for (index, (conditional, block)) in branches.iter().enumerate() {
+                    let keyword = if index == 0 { "if" } else { "} else if" };
+                    let line = format!("{} ({}) {{", keyword, self.expression(conditional));
+                    self.writer.line(&line);
+                    self.body(block);
+                }
+                if let Some(block) = otherwise {
+                    self.writer.line("} else {");
+                    self.body(block);
+                }
+                self.writer.line("}");
+            }
+            Lowered::Call(function, args, _) => {
+                for line in self.call(function, args) {
+                    self.writer.line(&line);
+                }
+            }
+            Lowered::Return(Some(value)) => {
+                let line = format!("return {};", self.expression(value));
+                self.writer.line(&line)
+            }
+            Lowered::Return(None) if self.current.is_none() => self.writer.line("return 0;"),
+            Lowered::Return(None) => self.writer.line("return;"),
+            Lowered::Assert(conditional, message) => {
+                let message = message.as_ref().map_or(String::from("NULL"), |message| {
+                    literal(&Literal::String(message.clone()))
+                });
+                let line = format!(
+                    "assert_that({}, {});",
+                    self.expression(conditional),
+                    message
+                );
+                self.writer.line(&line)
+            }
+            Lowered::For(identifier, start, end, block) => {
+                let counter = match self.references.contains(identifier) {
+                    true => format!("(*{})", name(identifier)),
+                    false => name(identifier),
+                };
+                let start = self.expression(start);
+                let line = format!(
+                    "for ({}; {} <= {}; {}++) {{",
+                    self.assign(identifier, &start),
+                    counter,
+                    self.operand(end, 4, true),
+                    counter
+                );
+                self.writer.line(&line);
+                self.body(block);
+                self.writer.line("}");
+            }
+            Lowered::While(conditional, block) => {
+                let line = format!("while ({}) {{", self.expression(conditional));
+                self.writer.line(&line);
+                self.body(block);
+                self.writer.line("}");
+            }
+            Lowered::Repeat(block, conditional) => {
+                self.writer.line("do {");
+                self.body(block);
+                let condition = match &conditional.expression {
+                    Expr::Not(inner) => self.expression(inner),
+                    _ => format!("!{}", self.operand(conditional, 7, false)),
+                };
+                self.writer.line(&format!("}} while ({});", condition));
+            }
+        }
+    }
+}
@@ -0,0 +1,491 @@
+use crate::ast::*;
+use crate::dialect::Dialect;
+use crate::evaluate::{negate, not, operate};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Declaration {
+    pub declare: Declare,
+    pub span: Span,
+    /// The span of the subroutine the declaration is local to, `None` for the program's globals.
+    pub subroutine: Option<Span>,
+}
+
+/// Walks a parsed program without running it, recording every declaration and the type errors
+/// `evaluate` would raise if it reached them.
+#[derive(Debug, Default)]
+pub struct Checker {
+    pub declarations: Vec<Declaration>,
+    pub errors: Vec<Spanned<Execution>>,
+    scopes: Vec<HashMap<String, usize>>,
+    dialect: Dialect,
+    subroutines: HashMap<String, Rc<Subroutine>>,
+    /// The subroutine whose body is being checked, if any.
+    current: Option<Rc<Subroutine>>,
+    /// Where that subroutine is, for the declarations made inside it.
+    current_span: Option<Span>,
+    /// The declarations of `ARRAY OF` parameters, whose bounds are the argument's.
+    unbounded: HashSet<usize>,
+}
+
+fn sample(literal_type: &LiteralType) -> Option<Literal> {
+    match literal_type {
+        LiteralType::Integer => Some(Literal::Integer(1)),
+        LiteralType::Real => Some(Literal::Real(1.0)),
+        LiteralType::String => Some(Literal::String(String::from("a"))),
+        LiteralType::Boolean => Some(Literal::Bool(true)),
+        LiteralType::Char => Some(Literal::Char('a')),
+        LiteralType::Date => Date::parse("1/1/2000").map(Literal::Date),
+        LiteralType::Any => None,
+    }
+}
+
+pub fn check(statements: &Vec<Spanned<Statement>>, dialect: Dialect) -> Checker {
+    let mut checker = Checker {
+        dialect,
+        ..Checker::default()
+    };
+    for (statement, _) in statements {
+        if let Statement::Subroutine(subroutine) = statement {
+            checker
+                .subroutines
+                .insert(subroutine.name.clone(), Rc::clone(subroutine));
+        }
+    }
+    // Subroutine bodies are checked last, so that they see every global the program declares.
+    checker.scopes.push(HashMap::new());
+    for statement in statements {
+        checker.statement(statement);
+    }
+    for (statement, span) in statements {
+        if let Statement::Subroutine(subroutine) = statement {
+            checker.subroutine(subroutine, span);
+        }
+    }
+    checker.scopes.pop();
+    checker
+}
+
+impl Checker {
+    fn lookup(&self, identifier: &str) -> Option<&Declare> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(identifier))
+            .map(|index| &self.declarations[*index].declare)
+    }
+
+    fn declare(&mut self, declare: Declare, span: &Span) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(String::from(declare.identifier()), self.declarations.len());
+        self.declarations.push(Declaration {
+            declare,
+            span: span.clone(),
+            subroutine: self.current_span.clone(),
+        });
+    }
+
+    /// The bounds of an array, unless it is a parameter taking those of its argument or they are
+    /// only known once the program runs.
+    fn bounds(&self, identifier: &str) -> Option<Bounds> {
+        let index = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(identifier))?;
+        match &self.declarations[*index].declare {
+            Declare::Array(_, bounds, _) if !self.unbounded.contains(index) => bounds.constant(),
+            _ => None,
+        }
+    }
+
+    fn is_array(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Variable(identifier) => {
+                matches!(self.lookup(identifier), Some(Declare::Array(..)))
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks a whole array being stored in, or compared with, the array `identifier`.
+    fn whole_array(&mut self, identifier: &str, other: &Spanned<Expression>, span: &Span) {
+        let expected = self.array(identifier, span);
+        let Expression::Variable(from) = &other.0 else {
+            if let Some(found) = self.expression(other) {
+                self.error(
+                    Execution::IncorrectType(DataTypes::Array, found.into()),
+                    &other.1,
+                );
+            }
+            return;
+        };
+        let found = self.array(from, &other.1);
+        let mismatched = match (self.bounds(identifier), self.bounds(from)) {
+            (Some(a), Some(b)) => a != b,
+            _ => false,
+        };
+        if mismatched || matches!((&expected, &found), (Some(a), Some(b)) if a != b) {
+            self.error(
+                Execution::ArrayMismatch(String::from(identifier), from.clone()),
+                span,
+            );
+        }
+    }
+
+    fn error(&mut self, error: Execution, span: &Span) {
+        self.errors.push((error, span.clone()));
+    }
+
+    fn expect(&mut self, expected: LiteralType, found: Option<LiteralType>, span: &Span) {
+        if let Some(found) = found {
+            if found != expected {
+                self.error(
+                    Execution::IncorrectType(expected.into(), found.into()),
+                    span,
+                );
+            }
+        }
+    }
+
+    /// Like `expect`, but for a value being stored, which may widen from INTEGER to REAL.
+    fn store(&mut self, expected: LiteralType, found: Option<LiteralType>, span: &Span) {
+        let found = found.map(|found| found.widen(&expected));
+        self.expect(expected, found, span);
+    }
+
+    fn literal(&mut self, identifier: &str, span: &Span) -> Option<LiteralType> {
+        match self.lookup(identifier) {
+            Some(Declare::Literal(_, literal_type)) => Some(literal_type.clone()),
+            Some(Declare::Array(..)) => {
+                self.error(
+                    Execution::IncorrectType(LiteralType::Any.into(), DataTypes::Array),
+                    span,
+                );
+                None
+            }
+            None => {
+                self.error(Execution::NotFound(String::from(identifier)), span);
+                None
+            }
+        }
+    }
+
+    fn array(&mut self, identifier: &str, span: &Span) -> Option<LiteralType> {
+        match self.lookup(identifier) {
+            Some(Declare::Array(_, _, literal_type)) => Some(literal_type.clone()),
+            Some(Declare::Literal(_, literal_type)) => {
+                let literal_type = literal_type.into();
+                self.error(
+                    Execution::IncorrectType(DataTypes::Array, literal_type),
+                    span,
+                );
+                None
+            }
+            None => {
+                self.error(Execution::NotFound(String::from(identifier)), span);
+                None
+            }
+        }
+    }
+
+    pub fn expression(&mut self, expression: &Spanned<Expression>) -> Option<LiteralType> {
+        let (expression, span) = expression;
+        let result = match expression {
+            Expression::Value(value) => Ok(value.clone()),
+            Expression::Variable(identifier) => return self.literal(identifier, span),
+            Expression::ArrayIndex(identifier, index) => {
+                let index_type = self.expression(index);
+                self.expect(LiteralType::Integer, index_type, span);
+                return self.array(identifier, span);
+            }
+            Expression::Negative(inner) => negate(&sample(&self.expression(inner)?)?),
+            Expression::Not(inner) => not(&sample(&self.expression(inner)?)?),
+            Expression::Operate(Ops::Equal | Ops::NotEqual, a, b) if self.is_array(&a.0) => {
+                let Expression::Variable(identifier) = &a.0 else {
+                    unreachable!("only a variable names an array")
+                };
+                self.whole_array(identifier, b, span);
+                return Some(LiteralType::Boolean);
+            }
+            Expression::Operate(op, a, b) => {
+                let (a, b) = (self.expression(a), self.expression(b));
+                operate(op, &sample(&a?)?, &sample(&b?)?)
+            }
+            Expression::FunctionCall(name, args) if name == "LEN" => {
+                match args.first() {
+                    Some((Expression::Variable(identifier), span)) => {
+                        self.array(identifier, span);
+                    }
+                    Some(other) => {
+                        if let Some(found) = self.expression(other) {
+                            self.error(
+                                Execution::IncorrectType(DataTypes::Array, found.into()),
+                                &other.1,
+                            );
+                        }
+                    }
+                    None => self.error(
+                        Execution::IncorrectNumberArguments(name.clone(), 1, 0),
+                        span,
+                    ),
+                }
+                return Some(LiteralType::Integer);
+            }
+            Expression::FunctionCall(name, args) if self.subroutines.contains_key(name) => {
+                let subroutine = Rc::clone(&self.subroutines[name]);
+                self.arguments(&subroutine, args, span);
+                if subroutine.returns.is_none() {
+                    self.error(Execution::NoValue(name.clone()), span);
+                }
+                return subroutine.returns.clone();
+            }
+            Expression::FunctionCall(name, args) => {
+                let types: Vec<_> = args.iter().map(|arg| self.expression(arg)).collect();
+                let Some(signature) = self.dialect.signature(name) else {
+                    self.error(Execution::NotFound(name.clone()), span);
+                    return None;
+                };
+                if signature.parameters.len() != args.len() {
+                    self.error(
+                        Execution::IncorrectNumberArguments(
+                            name.clone(),
+                            signature.parameters.len(),
+                            args.len(),
+                        ),
+                        span,
+                    );
+                } else {
+                    for ((_, expected), (found, (_, span))) in
+                        signature.parameters.iter().zip(types.into_iter().zip(args))
+                    {
+                        if expected != &LiteralType::Any {
+                            self.expect(expected.clone(), found, span);
+                        }
+                    }
+                }
+                return Some(signature.returns.clone());
+            }
+        };
+        match result {
+            Ok(literal) => Some(LiteralType::from(&literal)),
+            Err(error) => {
+                self.error(error, span);
+                None
+            }
+        }
+    }
+
+    fn arguments(&mut self, subroutine: &Subroutine, args: &[Spanned<Expression>], span: &Span) {
+        if subroutine.parameters.len() != args.len() {
+            self.error(
+                Execution::IncorrectNumberArguments(
+                    subroutine.name.clone(),
+                    subroutine.parameters.len(),
+                    args.len(),
+                ),
+                span,
+            );
+        }
+        for ((parameter, _), arg) in subroutine.parameters.iter().zip(args) {
+            if parameter.array {
+                match &arg.0 {
+                    Expression::Variable(identifier) => {
+                        let found = self.array(identifier, &arg.1);
+                        self.expect(parameter.literal_type.clone(), found, &arg.1);
+                    }
+                    _ => {
+                        if let Some(found) = self.expression(arg) {
+                            self.error(
+                                Execution::IncorrectType(DataTypes::Array, found.into()),
+                                &arg.1,
+                            );
+                        }
+                    }
+                }
+                continue;
+            }
+            if parameter.passing == Passing::ByReference
+                && !matches!(arg.0, Expression::Variable(_))
+            {
+                self.error(Execution::ByReference(parameter.name.clone()), &arg.1);
+            }
+            let found = self.expression(arg);
+            match parameter.passing {
+                Passing::ByValue => self.store(parameter.literal_type.clone(), found, &arg.1),
+                Passing::ByReference => self.expect(parameter.literal_type.clone(), found, &arg.1),
+            }
+        }
+    }
+
+    /// Checks a subroutine's body with only the globals and its parameters and locals in scope.
+    fn subroutine(&mut self, subroutine: &Rc<Subroutine>, span: &Span) {
+        let globals = self.scopes.len().min(1);
+        let caller = self.scopes.split_off(globals);
+        self.scopes.push(HashMap::new());
+        self.current_span = Some(span.clone());
+        for (parameter, span) in &subroutine.parameters {
+            let (name, literal_type) = (parameter.name.clone(), parameter.literal_type.clone());
+            if parameter.array {
+                self.unbounded.insert(self.declarations.len());
+                self.declare(Declare::Array(name, ArrayBounds::new(Bounds { lower: 0, upper: 0 }), literal_type), span);
+            } else {
+                self.declare(Declare::Literal(name, literal_type), span);
+            }
+        }
+        self.current = Some(Rc::clone(subroutine));
+        self.block(&subroutine.body);
+        if subroutine.returns.is_some() && !returns(&subroutine.body) {
+            self.error(Execution::NoValue(subroutine.name.clone()), span);
+        }
+        self.current = None;
+        self.current_span = None;
+        self.scopes.pop();
+        self.scopes.extend(caller);
+    }
+
+    fn condition(&mut self, expression: &Spanned<Expression>, span: &Span) {
+        let found = self.expression(expression);
+        self.expect(LiteralType::Boolean, found, span);
+    }
+
+    /// Checks statements in the current scope, since blocks open no scope of their own.
+    pub fn block(&mut self, statements: &Vec<Spanned<Statement>>) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, (statement, span): &Spanned<Statement>) {
+        match statement {
+            Statement::Declare(declares) => {
+                for declare in declares {
+                    let identifier = declare.identifier();
+                    let scope = self.scopes.last().unwrap();
+                    if scope.contains_key(identifier) {
+                        self.error(Execution::AlreadyDeclared(String::from(identifier)), span);
+                        continue;
+                    }
+                    if let Declare::Array(_, bounds, _) = declare {
+                        for bound in [&bounds.lower, &bounds.upper] {
+                            let found = self.expression(bound);
+                            self.expect(LiteralType::Integer, found, &bound.1);
+                        }
+                        match bounds.constant() {
+                            Some(bounds) if bounds.lower > bounds.upper => {
+                                self.error(Execution::InvalidBounds(bounds), span)
+                            }
+                            _ => {}
+                        }
+                    }
+                    self.declare(declare.clone(), span);
+                }
+            }
+            Statement::Assign(Assign::Literal(identifier, expression))
+                if matches!(self.lookup(identifier), Some(Declare::Array(..))) =>
+            {
+                self.whole_array(identifier, expression, span);
+            }
+            Statement::Assign(Assign::Literal(identifier, expression)) => {
+                let found = self.expression(expression);
+                if let (Some(expected), Some(found)) = (self.literal(identifier, span), found) {
+                    if let Err(error) = match_literal(&found.widen(&expected), &expected) {
+                        self.error(error, span);
+                    }
+                }
+            }
+            Statement::Assign(Assign::Array(identifier, index, expression)) => {
+                let index_type = self.expression(index);
+                self.expect(LiteralType::Integer, index_type, span);
+                let found = self.expression(expression);
+                if let Some(expected) = self.array(identifier, span) {
+                    self.store(expected, found, span);
+                }
+            }
+            Statement::Out(expressions, _) => {
+                for expression in expressions {
+                    self.expression(expression);
+                }
+            }
+            // any literal type can be read into, `evaluate` parsing the text as that type
+            Statement::In(identifier, None) => {
+                self.literal(identifier, span);
+            }
+            Statement::In(identifier, Some(index)) => {
+                let index_type = self.expression(index);
+                self.expect(LiteralType::Integer, index_type, span);
+                self.array(identifier, span);
+            }
+            Statement::If(conditional, if_branch, else_branch) => {
+                self.condition(conditional, span);
+                self.block(if_branch);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch);
+                }
+            }
+            Statement::Subroutine(_) | Statement::Import(_) => {}
+            Statement::ProcedureCall(name, args) => match self.subroutines.get(name) {
+                Some(subroutine) => {
+                    let subroutine = Rc::clone(subroutine);
+                    self.arguments(&subroutine, args, span);
+                }
+                None => {
+                    for arg in args {
+                        self.expression(arg);
+                    }
+                    if self.dialect.signature(name).is_none() {
+                        self.error(Execution::NotFound(name.clone()), span);
+                    }
+                }
+            },
+            Statement::Return(expression) => {
+                let found = expression
+                    .as_ref()
+                    .and_then(|expression| self.expression(expression));
+                match self.current.as_ref().map(|subroutine| &subroutine.returns) {
+                    None => self.error(Execution::CanNotCallReturn, span),
+                    Some(Some(returns)) => {
+                        let returns = returns.clone();
+                        self.store(returns, found, span);
+                    }
+                    Some(None) => {}
+                }
+            }
+            Statement::For(identifier, start, end, statements) => {
+                let iterator = self.literal(identifier, span);
+                self.expect(LiteralType::Integer, iterator, span);
+                let start = self.expression(start);
+                self.expect(LiteralType::Integer, start, span);
+                let end = self.expression(end);
+                self.expect(LiteralType::Integer, end, span);
+                self.block(statements);
+            }
+            Statement::While(conditional, statements) => {
+                self.condition(conditional, span);
+                self.block(statements);
+            }
+            Statement::Repeat(statements, conditional) => {
+                self.block(statements);
+                self.condition(conditional, span);
+            }
+            Statement::Test(_, statements) => {
+                let program = std::mem::replace(&mut self.scopes, vec![HashMap::new()]);
+                self.block(statements);
+                self.scopes = program;
+            }
+            Statement::Assert(conditional, _) => self.condition(conditional, span),
+        }
+    }
+}
+
+/// Whether every way through `statements` ends in a `RETURN`.
+fn returns(statements: &[Spanned<Statement>]) -> bool {
+    statements.iter().any(|(statement, _)| match statement {
+        Statement::Return(_) => true,
+        Statement::If(_, if_branch, Some(else_branch)) => returns(if_branch) && returns(else_branch),
+        _ => false,
+    })
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crate::appendix::{built_ins, signature, Signature};
+use crate::ast::*;
+
+/// The pseudocode variant a program is written against. `Lenient` accepts everything this
+/// interpreter has always accepted; the others only accept what their exam board's guide uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    #[default]
+    Lenient,
+    Cambridge9618,
+    Igcse0478,
+    Aqa,
+    OcrErl,
+}
+
+/// Declarations are shared by every dialect, since the interpreter needs every variable's type.
+const DECLARATIONS: [(&str, Token); 9] = [
+    ("DECLARE", Token::Declare),
+    ("OF", Token::Of),
+    ("INTEGER", Token::DataType(DataTypes::Literal(LiteralType::Integer))),
+    ("REAL", Token::DataType(DataTypes::Literal(LiteralType::Real))),
+    ("STRING", Token::DataType(DataTypes::Literal(LiteralType::String))),
+    ("BOOLEAN", Token::DataType(DataTypes::Literal(LiteralType::Boolean))),
+    ("CHAR", Token::DataType(DataTypes::Literal(LiteralType::Char))),
+    ("DATE", Token::DataType(DataTypes::Literal(LiteralType::Date))),
+    ("ARRAY", Token::DataType(DataTypes::Array)),
+];
+
+/// Testing is this interpreter's own addition, so it is spelled the same everywhere.
+const TESTING: [(&str, Token); 3] = [
+    ("TEST", Token::Test),
+    ("ENDTEST", Token::EndTest),
+    ("ASSERT", Token::Assert),
+];
+
+/// So are modules.
+const MODULES: [(&str, Token); 1] = [("IMPORT", Token::Import)];
+
+const WORD_OPERATORS: [(&str, Token); 5] = [
+    ("MOD", Token::Operator(Ops::Mod)),
+    ("DIV", Token::Operator(Ops::Div)),
+    ("AND", Token::Operator(Ops::And)),
+    ("OR", Token::Operator(Ops::Or)),
+    ("NOT", Token::Operator(Ops::Not)),
+];
+
+const CAMBRIDGE: [(&str, Token); 16] = [
+    ("OUTPUT", Token::Out),
+    ("INPUT", Token::In),
+    ("RETURN", Token::Return),
+    ("IF", Token::If),
+    ("THEN", Token::Then),
+    ("ELSE", Token::Else),
+    ("ENDIF", Token::EndIf),
+    ("FOR", Token::For),
+    ("TO", Token::To),
+    ("NEXT", Token::Next),
+    ("WHILE", Token::While),
+    ("ENDWHILE", Token::EndWhile),
+    ("REPEAT", Token::Repeat),
+    ("UNTIL", Token::Until),
+    ("TRUE", Token::Boolean(true)),
+    ("FALSE", Token::Boolean(false)),
+];
+
+/// The Cambridge guides' subroutines. AQA and OCR write theirs without types, so they have none
+/// yet.
+const SUBROUTINES: [(&str, Token); 6] = [
+    ("PROCEDURE", Token::Procedure),
+    ("ENDPROCEDURE", Token::EndProcedure),
+    ("FUNCTION", Token::Function),
+    ("ENDFUNCTION", Token::EndFunction),
+    ("RETURNS", Token::Returns),
+    ("CALL", Token::Call),
+];
+
+const AQA: [(&str, Token); 16] = [
+    ("OUTPUT", Token::Out),
+    ("USERINPUT", Token::In),
+    ("RETURN", Token::Return),
+    ("IF", Token::If),
+    ("THEN", Token::Then),
+    ("ELSE", Token::Else),
+    ("ENDIF", Token::EndIf),
+    ("FOR", Token::For),
+    ("TO", Token::To),
+    ("ENDFOR", Token::EndFor),
+    ("WHILE", Token::While),
+    ("ENDWHILE", Token::EndWhile),
+    ("REPEAT", Token::Repeat),
+    ("UNTIL", Token::Until),
+    ("True", Token::Boolean(true)),
+    ("False", Token::Boolean(false)),
+];
+
+/// OCR's `do ... until` is this interpreter's `REPEAT ... UNTIL`.
+const OCR: [(&str, Token); 16] = [
+    ("print", Token::Out),
+    ("input", Token::In),
+    ("return", Token::Return),
+    ("if", Token::If),
+    ("then", Token::Then),
+    ("else", Token::Else),
+    ("endif", Token::EndIf),
+    ("for", Token::For),
+    ("to", Token::To),
+    ("next", Token::Next),
+    ("while", Token::While),
+    ("endwhile", Token::EndWhile),
+    ("do", Token::Repeat),
+    ("until", Token::Until),
+    ("true", Token::Boolean(true)),
+    ("false", Token::Boolean(false)),
+];
+
+const ARITHMETIC: [(&str, Token); 4] = [
+    ("+", Token::Operator(Ops::Plus)),
+    ("-", Token::Operator(Ops::Minus)),
+    ("/", Token::Operator(Ops::Divide)),
+    ("*", Token::Operator(Ops::Multiply)),
+];
+
+const CAMBRIDGE_SYMBOLS: [(&str, Token); 7] = [
+    ("&", Token::Operator(Ops::Concatenate)),
+    (">=", Token::Operator(Ops::GreaterThanEqual)),
+    ("<=", Token::Operator(Ops::LessThanEqual)),
+    ("<>", Token::Operator(Ops::NotEqual)),
+    (">", Token::Operator(Ops::GreaterThan)),
+    ("<", Token::Operator(Ops::LessThan)),
+    ("=", Token::Operator(Ops::Equal)),
+];
+
+const AQA_SYMBOLS: [(&str, Token); 6] = [
+    ("≥", Token::Operator(Ops::GreaterThanEqual)),
+    ("≤", Token::Operator(Ops::LessThanEqual)),
+    ("≠", Token::Operator(Ops::NotEqual)),
+    (">", Token::Operator(Ops::GreaterThan)),
+    ("<", Token::Operator(Ops::LessThan)),
+    ("=", Token::Operator(Ops::Equal)),
+];
+
+const OCR_SYMBOLS: [(&str, Token); 6] = [
+    (">=", Token::Operator(Ops::GreaterThanEqual)),
+    ("<=", Token::Operator(Ops::LessThanEqual)),
+    ("!=", Token::Operator(Ops::NotEqual)),
+    (">", Token::Operator(Ops::GreaterThan)),
+    ("<", Token::Operator(Ops::LessThan)),
+    ("==", Token::Operator(Ops::Equal)),
+];
+
+impl Dialect {
+    pub const ALL: [Dialect; 5] = [
+        Dialect::Lenient,
+        Dialect::Cambridge9618,
+        Dialect::Igcse0478,
+        Dialect::Aqa,
+        Dialect::OcrErl,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Lenient => "lenient",
+            Dialect::Cambridge9618 => "9618",
+            Dialect::Igcse0478 => "0478",
+            Dialect::Aqa => "aqa",
+            Dialect::OcrErl => "ocr",
+        }
+    }
+
+    pub fn find(name: &str) -> Option<Dialect> {
+        Dialect::ALL
+            .into_iter()
+            .find(|dialect| dialect.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Every word the lexer should read as a keyword rather than an identifier.
+    pub fn keywords(&self) -> HashMap<&'static str, Token> {
+        let statements: &[(&str, Token)] = match self {
+            Dialect::Lenient | Dialect::Cambridge9618 | Dialect::Igcse0478 => &CAMBRIDGE,
+            Dialect::Aqa => &AQA,
+            Dialect::OcrErl => &OCR,
+        };
+        let subroutines: &[(&str, Token)] = match self {
+            Dialect::Lenient | Dialect::Cambridge9618 | Dialect::Igcse0478 => &SUBROUTINES,
+            Dialect::Aqa | Dialect::OcrErl => &[],
+        };
+        let extra: &[(&str, Token)] = match self {
+            Dialect::Lenient => &[
+                ("ENDFOR", Token::EndFor),
+                ("DO", Token::Do),
+                ("BYVAL", Token::ByVal),
+                ("BYREF", Token::ByRef),
+            ],
+            Dialect::Cambridge9618 => &[("BYVAL", Token::ByVal), ("BYREF", Token::ByRef)],
+            Dialect::Igcse0478 => &[("DO", Token::Do)],
+            _ => &[],
+        };
+        [&DECLARATIONS[..], &TESTING, &MODULES, &WORD_OPERATORS, statements, subroutines, extra]
+            .concat()
+            .into_iter()
+            .collect()
+    }
+
+    /// Operator and assignment symbols, longest first so that `<=` wins over `<`.
+    pub fn symbols(&self) -> Vec<(&'static str, Token)> {
+        let (comparisons, arrows): (&[(&str, Token)], &[&str]) = match self {
+            Dialect::Lenient => (&CAMBRIDGE_SYMBOLS, &["<-", "←"]),
+            Dialect::Cambridge9618 | Dialect::Igcse0478 => (&CAMBRIDGE_SYMBOLS, &["←"]),
+            Dialect::Aqa => (&AQA_SYMBOLS, &["←"]),
+            Dialect::OcrErl => (&OCR_SYMBOLS, &["="]),
+        };
+        let mut symbols: Vec<(&str, Token)> = [&ARITHMETIC[..], comparisons].concat();
+        symbols.extend(arrows.iter().map(|arrow| (*arrow, Token::Arrow)));
+        symbols.sort_by_key(|(symbol, _)| std::cmp::Reverse(symbol.chars().count()));
+        symbols
+    }
+
+    /// Whether `NEXT` has to name the loop variable, as the guides that use it show.
+    pub fn next_needs_identifier(&self) -> bool {
+        !matches!(self, Dialect::Lenient | Dialect::Aqa)
+    }
+
+    /// Whether `WHILE` has to be followed by `DO`, as in the IGCSE guide.
+    pub fn while_needs_do(&self) -> bool {
+        matches!(self, Dialect::Igcse0478)
+    }
+
+    /// Whether input can be assigned like a value, as in AQA's `name ← USERINPUT`.
+    pub fn assigns_input(&self) -> bool {
+        matches!(self, Dialect::Lenient | Dialect::Aqa | Dialect::OcrErl)
+    }
+
+    /// The dialect's names for the appendix functions, paired with the names `built_ins` uses.
+    /// The IGCSE guide's library (`ROUND`, `RANDOM`, `LENGTH`, `LCASE`, `UCASE` and `SUBSTRING`)
+    /// has nothing in common with the appendix, so `0478` has no functions until those are
+    /// written.
+    pub fn library(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Dialect::Lenient => &[
+                ("STR_TO_NUM", "STR_TO_NUM"),
+                ("NUM_TO_STR", "NUM_TO_STR"),
+                ("RANDOMBETWEEN", "RANDOMBETWEEN"),
+            ],
+            Dialect::Cambridge9618 => &[("STR_TO_NUM", "STR_TO_NUM"), ("NUM_TO_STR", "NUM_TO_STR")],
+            Dialect::Igcse0478 => &[],
+            Dialect::Aqa => &[
+                ("STRING_TO_INT", "STR_TO_NUM"),
+                ("INT_TO_STRING", "NUM_TO_STR"),
+                ("RANDOM_INT", "RANDOMBETWEEN"),
+            ],
+            Dialect::OcrErl => &[
+                ("int", "STR_TO_NUM"),
+                ("str", "NUM_TO_STR"),
+                ("random", "RANDOMBETWEEN"),
+            ],
+        }
+    }
+
+    pub fn built_ins(&self) -> HashMap<String, Function> {
+        let mut all = built_ins();
+        self.library()
+            .iter()
+            .filter_map(|(name, canonical)| Some((String::from(*name), all.remove(*canonical)?)))
+            .collect()
+    }
+
+    /// The signature of a function the dialect provides, `LEN` included.
+    pub fn signature(&self, name: &str) -> Option<&'static Signature> {
+        if name == "LEN" {
+            return signature(name);
+        }
+        let (_, canonical) = self.library().iter().find(|(alias, _)| *alias == name)?;
+        signature(canonical)
+    }
+}
@@ -1,26 +1,246 @@
 use crate::ast::*;
+use crate::dialect::Dialect;
+use crate::resolve::{declarations, resolve};
+use rand::{rngs::StdRng, SeedableRng};
 use std::{
-    borrow::{BorrowMut, Borrow},
-    collections::HashMap,
-    io::stdin,
-    ops::{Add, Div, Mul, Rem, Sub, Deref},
+    cell::RefCell,
+    rc::Rc,
+    collections::{HashMap, HashSet, VecDeque},
+    ops::Deref,
+    time::{Duration, Instant},
 };
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug)]
 pub struct State {
     pub functions: HashMap<String, Function>,
     pub scopes: Vec<Scope>,
+    pub limits: Limits,
+    pub usage: Usage,
+    /// Shared by every random built-in, so that a run can be repeated from its seed.
+    pub rng: RefCell<StdRng>,
+    pub io: Io,
+    pub invalid_input: InputPolicy,
 }
-#[derive(Clone, Debug, PartialEq, Eq)]
+impl State {
+    /// A state with nothing declared yet and the dialect's built-ins, drawing random numbers from
+    /// `seed`.
+    pub fn new(dialect: Dialect, limits: Limits, seed: u64, io: Io) -> State {
+        State {
+            functions: dialect.built_ins(),
+            scopes: vec![],
+            limits,
+            usage: Usage::default(),
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+            io,
+            invalid_input: InputPolicy::default(),
+        }
+    }
+}
+
+/// The program's globals, or the locals of one subroutine call.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Scope {
     pub variables: HashMap<String, Variable>,
+    /// Arrays declared in this scope whose `DECLARE` has not run yet, so whose bounds are not
+    /// known. They hide a global of the same name all the same.
+    pub undeclared: HashSet<String>,
+}
+
+/// What `INPUT` does with a line that is not a valid value of the variable's type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputPolicy {
+    /// Stop the program with `InvalidInput`.
+    #[default]
+    Error,
+    /// Say why the line was not valid and read another.
+    Reprompt,
+}
+
+/// Where `INPUT` reads from and `OUTPUT` writes to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Io {
+    #[default]
+    Console,
+    /// Reads from a script of input lines and appends everything written to `output`, which the
+    /// caller keeps a handle on so that it survives the run failing.
+    Scripted {
+        input: VecDeque<String>,
+        output: Rc<RefCell<String>>,
+    },
+}
+
+impl Io {
+    /// Reads `input` a line at a time, keeping spaces as the console does.
+    pub fn scripted(input: &str, output: &Rc<RefCell<String>>) -> Io {
+        Io::Scripted {
+            input: input.lines().map(String::from).collect(),
+            output: Rc::clone(output),
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        match self {
+            Io::Console => {
+                print!("{}", text);
+                // a prompt without a line ending would otherwise wait in the buffer
+                std::io::Write::flush(&mut std::io::stdout()).ok();
+            }
+            Io::Scripted { output, .. } => RefCell::borrow_mut(output).push_str(text),
+        }
+    }
+
+    /// The next line of input without its line ending but with any other spaces, which can be a
+    /// `CHAR` or part of a `STRING`, or `None` once the input runs out.
+    fn read_line(&mut self) -> Option<String> {
+        match self {
+            Io::Console => read_console(),
+            Io::Scripted { input, .. } => input.pop_front(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_console() -> Option<String> {
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            let line = input.strip_suffix('\n').unwrap_or(&input);
+            Some(String::from(line.strip_suffix('\r').unwrap_or(line)))
+        }
+    }
+}
+
+/// A browser has no console to read from, so programs there are given their input as a script.
+#[cfg(target_arch = "wasm32")]
+fn read_console() -> Option<String> {
+    None
+}
+
+/// Caps on how much a program may do before it is stopped, `None` meaning unlimited.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Statements executed, loop bodies counting once per iteration.
+    pub steps: Option<u64>,
+    pub time: Option<Duration>,
+    /// How many subroutine calls may be running at once. Each takes stack, so anything over
+    /// `MAX_CALL_DEPTH` can only be run on a bigger stack than `with_stack` gives.
+    pub call_depth: Option<usize>,
+    /// Elements across every array alive at once.
+    pub array_size: Option<usize>,
+}
+
+impl Default for Limits {
+    /// Unlimited steps and time, but bounded depth and arrays so that a runaway program reports
+    /// an error instead of overflowing the stack or running out of memory.
+    fn default() -> Self {
+        Limits {
+            steps: None,
+            time: None,
+            call_depth: Some(200),
+            array_size: Some(10_000_000),
+        }
+    }
+}
+
+/// A time limit of `seconds`, which has to be a positive number small enough for a `Duration`.
+pub fn time_limit(seconds: f64) -> Result<Duration, String> {
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(time) if seconds > 0.0 => Ok(time),
+        _ => Err(format!("a time limit has to be a positive number of seconds, not {}", seconds)),
+    }
+}
+
+/// The stack `with_stack` runs programs on. Only the pages a run reaches are ever used.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Stack for one subroutine call, measured for a call made from six loops and `IF`s deep with a
+/// third to spare. An unoptimised build takes ten times what an optimised one does.
+const CALL_STACK: usize = if cfg!(debug_assertions) { 128 * 1024 } else { 16 * 1024 };
+
+/// The deepest `call_depth` that `STACK_SIZE` can hold.
+pub const MAX_CALL_DEPTH: usize = STACK_SIZE / CALL_STACK;
+
+impl Limits {
+    /// Whether a run on `with_stack` can be held to these limits instead of overflowing its stack.
+    pub fn fit_stack(&self) -> Result<(), String> {
+        match self.call_depth {
+            Some(call_depth) if call_depth <= MAX_CALL_DEPTH => Ok(()),
+            _ => Err(format!("the call depth can be at most {}", MAX_CALL_DEPTH)),
+        }
+    }
+}
+
+/// Runs `run` on a thread with `STACK_SIZE` of stack, which the main thread does not have.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn with_stack<T: Send>(run: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, run)
+            .expect("could not start a thread to run on");
+        thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// How much of each limit the program has used so far.
+#[derive(Clone, Debug, Default)]
+pub struct Usage {
+    pub steps: u64,
+    /// When the first statement ran, only read when there is a time limit since WebAssembly in a
+    /// browser has no clock to read.
+    pub started: Option<Instant>,
+    pub call_depth: usize,
+    pub array_size: usize,
+}
+
+fn step(state: &mut State) -> Result<(), Execution> {
+    state.usage.steps += 1;
+    if let Some(steps) = state.limits.steps {
+        if state.usage.steps > steps {
+            return Err(Execution::StepLimit(steps));
+        }
+    }
+    if let Some(time) = state.limits.time {
+        if state.usage.started.get_or_insert_with(Instant::now).elapsed() > time {
+            return Err(Execution::TimeLimit(time));
+        }
+    }
+    Ok(())
+}
+
+/// Removes the innermost scope, giving back the room its arrays took up.
+fn pop_scope(state: &mut State) -> Scope {
+    let scope = state.scopes.pop().unwrap_or_default();
+    for variable in scope.variables.values() {
+        if let Variable::Array { values, .. } = variable {
+            state.usage.array_size -= values.len();
+        }
+    }
+    scope
+}
+
+/// Counts `size` more array elements as alive, unless that would go over the limit.
+fn allocate(state: &mut State, size: usize) -> Result<(), Execution> {
+    if let Some(array_size) = state.limits.array_size {
+        if size > array_size - state.usage.array_size.min(array_size) {
+            return Err(Execution::ArraySizeLimit(array_size));
+        }
+    }
+    state.usage.array_size += size;
+    Ok(())
 }
 
 fn get_variable<'current>(
     state: &'current State,
     identifier: &str,
 ) -> Result<&'current Variable, Execution> {
-    for scope in &state.scopes {
+    for scope in state.scopes.iter().rev() {
+        if scope.undeclared.contains(identifier) {
+            return Err(Execution::NotAssigned(String::from(identifier)));
+        }
         if let Some(var) = scope.variables.get(identifier) {
             return Ok(var);
         }
@@ -32,7 +252,10 @@ fn get_literal<'current>(
     state: &'current State,
     identifier: &str,
 ) -> Result<&'current Literal, Execution> {
-    for scope in &state.scopes {
+    for scope in state.scopes.iter().rev() {
+        if scope.undeclared.contains(identifier) {
+            return Err(Execution::NotAssigned(String::from(identifier)));
+        }
         if let Some(var) = scope.variables.get(identifier) {
             match var {
                 Variable::Literal { value, .. } => {
@@ -53,28 +276,28 @@ fn get_literal<'current>(
 fn index_array<'current>(
     state: &'current State,
     identifier: &str,
-    index: usize,
+    index: isize,
 ) -> Result<&'current Literal, Execution> {
     let variable = get_variable(state, identifier)?;
     match variable {
         Variable::Literal { literal_type, .. } => {
-            return Err(Execution::IncorrectType(DataTypes::Array, literal_type.into()));
+            Err(Execution::IncorrectType(DataTypes::Array, literal_type.into()))
         }
         Variable::Array {
             literal_type: _,
             bounds,
             values,
         } => {
+            let out_of_bounds =
+                || Execution::OutOfBounds(String::from(identifier), index, bounds.clone());
             if index < bounds.lower || index > bounds.upper {
-                return Err(Execution::OutOfBounds(String::from(identifier), index));
+                return Err(out_of_bounds());
             }
-            let vec_index = index - bounds.lower;
-            if let Some(literal) = values.get(vec_index) {
-                literal
+            match values.get(offset(bounds, index)) {
+                Some(literal) => literal
                     .as_ref()
-                    .ok_or_else(|| Execution::IndexNotAssigned(String::from(identifier), index))
-            } else {
-                Err(Execution::OutOfBounds(String::from(identifier), index))
+                    .ok_or_else(|| Execution::IndexNotAssigned(String::from(identifier), index)),
+                None => Err(out_of_bounds()),
             }
         }
     }
@@ -84,22 +307,25 @@ fn get_mut_variable<'state>(
     state: &'state mut State,
     identifier: &str,
 ) -> Result<&'state mut Variable, Execution> {
-    for scope in state.scopes.iter_mut() {
+    for scope in state.scopes.iter_mut().rev() {
+        if scope.undeclared.contains(identifier) {
+            return Err(Execution::NotAssigned(String::from(identifier)));
+        }
         if let Some(var) = scope.variables.get_mut(identifier) {
             return Ok(var);
         }
     }
 
-    return Err(Execution::NotFound(String::from(identifier)));
+    Err(Execution::NotFound(String::from(identifier)))
 }
 
 fn assign_array(
-    mut state: State,
+    state: &mut State,
     identifier: &str,
-    index: usize,
+    index: isize,
     to_assign: Literal,
-) -> Result<State, Execution> {
-    let variable = get_mut_variable(&mut state, identifier)?;
+) -> Result<(), Execution> {
+    let variable = get_mut_variable(state, identifier)?;
 
     let (array_type, bounds, values) = match variable {
         Variable::Array {
@@ -110,6 +336,7 @@ fn assign_array(
         Variable::Literal { literal_type, .. } => return Err(Execution::IncorrectType(DataTypes::Array, literal_type.deref().into())),
     };
 
+    let to_assign = to_assign.widen(array_type);
     let assign_type = LiteralType::from(&to_assign);
 
     if &assign_type != array_type {
@@ -117,20 +344,19 @@ fn assign_array(
     }
 
     if index < bounds.lower || index > bounds.upper {
-        return Err(Execution::OutOfBounds(String::from(identifier), index));
+        return Err(Execution::OutOfBounds(String::from(identifier), index, bounds.clone()));
     }
-    values.remove(index - bounds.lower);
-    values.insert(index - bounds.lower, Some(to_assign));
+    values[offset(bounds, index)] = Some(to_assign);
 
-    Ok(state)
+    Ok(())
 }
 
 fn assign_literal(
-    mut state: State,
+    state: &mut State,
     identifier: &str,
     literal: Literal,
-) -> Result<State, Execution> {
-    let variable = get_mut_variable(&mut state, identifier)?;
+) -> Result<(), Execution> {
+    let variable = get_mut_variable(state, identifier)?;
 
     match variable {
         Variable::Literal {
@@ -141,14 +367,77 @@ fn assign_literal(
             if !*is_mutable {
                 return Err(Execution::AssignToConstant(String::from(identifier)));
             }
+            let literal = literal.widen(literal_type);
             match_literal(&LiteralType::from(&literal), &literal_type.clone())?;
             *value = Some(literal);
-            return Ok(state);
+            Ok(())
         }
-        Variable::Array { .. } => return Err(Execution::IncorrectType(LiteralType::Any.into(), DataTypes::Array)),
+        Variable::Array { .. } => Err(Execution::IncorrectType(LiteralType::Any.into(), DataTypes::Array)),
     }
 }
 
+/// Where element `index` of an array with `bounds` is kept, `index` being within them.
+fn offset(bounds: &Bounds, index: isize) -> usize {
+    index.abs_diff(bounds.lower)
+}
+
+/// The value of `expression`, which has to be an integer, as an index or bound is.
+fn index_value(
+    state: &mut State,
+    expression: &Spanned<Expression>,
+) -> Result<isize, Spanned<Execution>> {
+    match evaluate_expression(expression, state)? {
+        Literal::Integer(value) => Ok(value),
+        value => Err((
+            Execution::IncorrectType(LiteralType::Integer.into(), LiteralType::from(&value).into()),
+            expression.1.clone(),
+        )),
+    }
+}
+
+/// Reads the next line of input as a `literal_type`, asking again after one that is not valid if
+/// the state's policy says to.
+fn read_input(state: &mut State, literal_type: &LiteralType) -> Result<Literal, Execution> {
+    loop {
+        let line = state.io.read_line().ok_or(Execution::EndOfInput)?;
+        match parse_input(&line, literal_type) {
+            Err(error) if state.invalid_input == InputPolicy::Reprompt => {
+                state.io.write(&format!("{}, try again\n", error));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Parses a line of input the way a literal of `literal_type` is written, except that strings and
+/// characters need no quotes.
+pub fn parse_input(text: &str, literal_type: &LiteralType) -> Result<Literal, Execution> {
+    let trimmed = text.trim();
+    let literal = match literal_type {
+        LiteralType::Integer => trimmed.parse().ok().map(Literal::Integer),
+        LiteralType::Real => trimmed
+            .parse::<f64>()
+            .ok()
+            .filter(|real| real.is_finite())
+            .map(Literal::Real),
+        LiteralType::Boolean => match trimmed.to_uppercase().as_str() {
+            "TRUE" => Some(Literal::Bool(true)),
+            "FALSE" => Some(Literal::Bool(false)),
+            _ => None,
+        },
+        LiteralType::Char => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(char), None) => Some(Literal::Char(char)),
+                _ => None,
+            }
+        }
+        LiteralType::Date => Date::parse(trimmed).map(Literal::Date),
+        LiteralType::String | LiteralType::Any => Some(Literal::String(String::from(text))),
+    };
+    literal.ok_or_else(|| Execution::InvalidInput(String::from(text), literal_type.clone()))
+}
+
 macro_rules! eval {
     ($expression: expr, $context: expr) => {
         evaluate_expression($expression, $context)
@@ -161,29 +450,190 @@ macro_rules! span {
     };
 }
 
+/// Adds a declared variable to the innermost scope, working out an array's bounds there and then.
+/// `resolve` has ruled out declaring a name twice in one scope, so a variable that is already there
+/// was declared as the scope was entered, or by this same statement on an earlier loop iteration,
+/// and keeps its value.
+fn declare_variable(
+    state: &mut State,
+    declare: &Declare,
+    span: &Span,
+) -> Result<(), Spanned<Execution>> {
+    let identifier = declare.identifier();
+    if state
+        .scopes
+        .last()
+        .is_some_and(|scope| scope.variables.contains_key(identifier))
+    {
+        return Ok(());
+    }
+    let variable = match declare {
+        Declare::Literal(_, literal_type) => literal_type.into(),
+        Declare::Array(_, bounds, literal_type) => {
+            let bounds = Bounds {
+                lower: index_value(state, &bounds.lower)?,
+                upper: index_value(state, &bounds.upper)?,
+            };
+            if bounds.lower > bounds.upper {
+                return Err((Execution::InvalidBounds(bounds), span.clone()));
+            }
+            let size = bounds
+                .upper
+                .checked_sub(bounds.lower)
+                .and_then(|size| usize::try_from(size).ok())
+                .and_then(|size| size.checked_add(1))
+                .ok_or((Execution::Overflow(Ops::Minus), span.clone()))?;
+            allocate(state, size).map_err(span!(span))?;
+            Variable::Array {
+                literal_type: literal_type.clone(),
+                bounds,
+                values: vec![None; size],
+            }
+        }
+    };
+    let scope = state.scopes.last_mut().unwrap();
+    scope.undeclared.remove(identifier);
+    scope.variables.insert(String::from(identifier), variable);
+    Ok(())
+}
+
+/// Declares every variable belonging to the innermost scope as it is entered, so that one whose
+/// `DECLARE` is skipped, or has not run yet, is still found there rather than among the globals.
+/// An array only gets its bounds once its `DECLARE` runs.
+fn declare_scope(state: &mut State, statements: &[Spanned<Statement>]) {
+    let scope = state.scopes.last_mut().unwrap();
+    for (declare, _) in declarations(statements) {
+        let identifier = declare.identifier();
+        if scope.variables.contains_key(identifier) {
+            continue;
+        }
+        match declare {
+            Declare::Literal(_, literal_type) => {
+                scope.variables.insert(String::from(identifier), literal_type.into());
+            }
+            Declare::Array(..) => {
+                scope.undeclared.insert(String::from(identifier));
+            }
+        }
+    }
+}
+
+/// The array `expression` names, with its bounds and type, where a whole array is expected.
+fn array_named<'e>(
+    state: &mut State,
+    expression: &'e Spanned<Expression>,
+) -> Result<(&'e str, Bounds, LiteralType), Spanned<Execution>> {
+    let found = match &expression.0 {
+        Expression::Variable(identifier) => {
+            match get_variable(state, identifier).map_err(span!(expression.1))? {
+                Variable::Array {
+                    bounds,
+                    literal_type,
+                    ..
+                } => return Ok((identifier, bounds.clone(), literal_type.clone())),
+                Variable::Literal { literal_type, .. } => literal_type.clone(),
+            }
+        }
+        _ => LiteralType::from(&eval!(expression, state)?),
+    };
+    Err((
+        Execution::IncorrectType(DataTypes::Array, found.into()),
+        expression.1.clone(),
+    ))
+}
+
+fn is_array(state: &State, expression: &Expression) -> bool {
+    match expression {
+        Expression::Variable(identifier) => {
+            matches!(get_variable(state, identifier), Ok(Variable::Array { .. }))
+        }
+        _ => false,
+    }
+}
+
+/// The elements of an array, `None` where unassigned.
+type Elements<'s> = &'s [Option<Literal>];
+
+/// The elements of the arrays `a` and `b`, which must have the same type and bounds.
+fn array_pair<'s>(
+    state: &'s State,
+    a: &str,
+    b: &str,
+) -> Result<(Elements<'s>, Elements<'s>), Execution> {
+    match (get_variable(state, a)?, get_variable(state, b)?) {
+        (
+            Variable::Array {
+                literal_type,
+                bounds,
+                values,
+            },
+            Variable::Array {
+                literal_type: b_type,
+                bounds: b_bounds,
+                values: b_values,
+            },
+        ) if literal_type == b_type && bounds == b_bounds => Ok((values, b_values)),
+        _ => Err(Execution::ArrayMismatch(String::from(a), String::from(b))),
+    }
+}
+
+/// Assigns every element of the array `from` to the array `to`, unassigned ones included.
+fn copy_array(state: &mut State, to: &str, from: &str) -> Result<(), Execution> {
+    let values = array_pair(state, to, from)?.1.to_vec();
+    if let Variable::Array { values: to, .. } = get_mut_variable(state, to)? {
+        *to = values;
+    }
+    Ok(())
+}
+
+/// Whether two arrays hold the same values, reading an unassigned element being an error as
+/// anywhere else.
+fn arrays_equal(state: &State, a: &str, b: &str) -> Result<bool, Execution> {
+    let (a_values, b_values) = array_pair(state, a, b)?;
+    let Variable::Array { bounds, .. } = get_variable(state, a)? else {
+        return Err(Execution::ArrayMismatch(String::from(a), String::from(b)));
+    };
+    for (offset, (a_value, b_value)) in a_values.iter().zip(b_values).enumerate() {
+        match (a_value, b_value) {
+            (Some(a_value), Some(b_value)) if a_value != b_value => return Ok(false),
+            (Some(_), Some(_)) => {}
+            (None, _) => return Err(Execution::IndexNotAssigned(String::from(a), bounds.lower + offset as isize)),
+            (_, None) => return Err(Execution::IndexNotAssigned(String::from(b), bounds.lower + offset as isize)),
+        }
+    }
+    Ok(true)
+}
+
 fn evaluate_expression(
     expression: &Spanned<Expression>,
-    state: &State,
+    state: &mut State,
 ) -> Result<Literal, Spanned<Execution>> {
     let (expression, span) = expression;
     match expression {
         Expression::Value(value) => Ok(value.clone()),
-        Expression::Variable(identifier) => get_literal(state, &identifier)
-            .map(|literal| literal.clone())
+        Expression::Variable(identifier) => get_literal(state, identifier).cloned()
             .map_err(span!(span)),
         Expression::Negative(expression) => negate(&eval!(expression, state)?).map_err(span!(span)),
+        // arrays are compared element by element
+        Expression::Operate(op @ (Ops::Equal | Ops::NotEqual), a, b) if is_array(state, &a.0) => {
+            let (a, ..) = array_named(state, a)?;
+            let (b, ..) = array_named(state, b)?;
+            let equal = arrays_equal(state, a, b).map_err(span!(span))?;
+            Ok(Literal::Bool(equal == (*op == Ops::Equal)))
+        }
         Expression::Operate(op, a, b) => {
-            operate(op, &eval!(a, state)?, &eval!(b, state)?).map_err(span!(span))
+            let a = eval!(a, state)?;
+            // AND and OR stop at the left operand when it alone decides the result
+            match (op, &a) {
+                (Ops::And, Literal::Bool(false)) | (Ops::Or, Literal::Bool(true)) => Ok(a),
+                _ => operate(op, &a, &eval!(b, state)?).map_err(span!(span)),
+            }
         }
         Expression::Not(expression) => not(&eval!(expression, state)?).map_err(span!(span)),
         Expression::FunctionCall(name, args) => {
             if name == "LEN" {
-                let identifier = match args.get(0) {
-                    Some((Expression::Variable(identifier), _)) => identifier,
-                    Some(expression) => {
-                        let result_type: LiteralType = eval!(expression, state)?.borrow().into();
-                        return Err((Execution::IncorrectType(DataTypes::Array, result_type.into()), expression.1.clone()))
-                    },
+                let bounds = match args.first() {
+                    Some(arg) => array_named(state, arg)?.1,
                     None => {
                         return Err((
                             Execution::IncorrectNumberArguments(String::from("LEN"), 1, 0),
@@ -191,63 +641,179 @@ fn evaluate_expression(
                         ))
                     }
                 };
-                let array = get_variable(state, identifier).map_err(span!(span))?;
-                match array {
-                    Variable::Array { bounds, .. } => {
-                        return Ok(Literal::Integer(
-                            (bounds.upper - bounds.lower + 1).try_into().unwrap(),
+                return Ok(Literal::Integer(bounds.upper - bounds.lower + 1));
+            }
+
+            call_function(name, args, span, state)?
+                .ok_or_else(|| (Execution::NoValue(name.clone()), span.clone()))
+        }
+        Expression::ArrayIndex(identifier, expression) => {
+            let index = index_value(state, expression)?;
+            index_array(state, identifier, index).cloned()
+                .map_err(span!(span))
+        }
+    }
+}
+
+/// Calls a built-in or a subroutine by name, giving back its value if it has one.
+fn call_function(
+    name: &str,
+    args: &[Spanned<Expression>],
+    span: &Span,
+    state: &mut State,
+) -> Result<Option<Literal>, Spanned<Execution>> {
+    let function = state
+        .functions
+        .get(name)
+        .cloned()
+        .ok_or_else(|| (Execution::NotFound(String::from(name)), span.clone()))?;
+
+    let values = |state: &mut State| {
+        args.iter()
+            .map(|arg| eval!(arg, state))
+            .collect::<Result<Vec<Literal>, _>>()
+    };
+
+    match function {
+        Function::BuiltIn(built_in) => built_in(values(state)?),
+        Function::Random(built_in) => {
+            let values = values(state)?;
+            built_in(values, &mut state.rng.borrow_mut())
+        }
+        Function::User(subroutine) => return call(&subroutine, args, span, state),
+    }
+    .map(Some)
+    .map_err(span!(span))
+}
+
+/// Runs a subroutine's body with its parameters bound to `args`. The body sees the program's
+/// global variables and its own parameters and locals, which hide globals of the same name, but
+/// not the caller's locals. Arguments passed by reference are copied back to the caller's
+/// variables once the body finishes.
+fn call(
+    subroutine: &Subroutine,
+    args: &[Spanned<Expression>],
+    span: &Span,
+    state: &mut State,
+) -> Result<Option<Literal>, Spanned<Execution>> {
+    if args.len() != subroutine.parameters.len() {
+        return Err((
+            Execution::IncorrectNumberArguments(
+                subroutine.name.clone(),
+                subroutine.parameters.len(),
+                args.len(),
+            ),
+            span.clone(),
+        ));
+    }
+
+    let mut variables = HashMap::new();
+    for ((parameter, _), arg) in subroutine.parameters.iter().zip(args) {
+        // the callee gets its own copy of an array, which `BYREF` copies back afterwards
+        if parameter.array {
+            let (identifier, _, literal_type) = array_named(state, arg)?;
+            if literal_type != parameter.literal_type {
+                return Err((
+                    Execution::IncorrectType(parameter.literal_type.clone().into(), literal_type.into()),
+                    arg.1.clone(),
+                ));
+            }
+            let array = get_variable(state, identifier).map_err(span!(arg.1))?.clone();
+            if let Variable::Array { values, .. } = &array {
+                allocate(state, values.len()).map_err(span!(arg.1))?;
+            }
+            variables.insert(parameter.name.clone(), array);
+            continue;
+        }
+        let value = match (parameter.passing, &arg.0) {
+            (Passing::ByValue, _) => Some(eval!(arg, state)?),
+            (Passing::ByReference, Expression::Variable(identifier)) => {
+                match get_variable(state, identifier).map_err(span!(arg.1))? {
+                    Variable::Literal { value, .. } => value.clone(),
+                    Variable::Array { .. } => {
+                        return Err((
+                            Execution::IncorrectType(
+                                (&parameter.literal_type).into(),
+                                DataTypes::Array,
+                            ),
+                            arg.1.clone(),
                         ))
                     }
-                    Variable::Literal { literal_type, .. } => {
-                        return Err((Execution::IncorrectType(DataTypes::Array, literal_type.into()), span.clone()))
-                    }
                 }
             }
+            (Passing::ByReference, _) => {
+                return Err((Execution::ByReference(parameter.name.clone()), arg.1.clone()))
+            }
+        };
+        let value = match parameter.passing {
+            Passing::ByValue => value.map(|value| value.widen(&parameter.literal_type)),
+            Passing::ByReference => value,
+        };
+        if let Some(value) = &value {
+            match_literal(&parameter.literal_type, &value.into()).map_err(span!(arg.1))?;
+        }
+        variables.insert(
+            parameter.name.clone(),
+            Variable::Literal {
+                literal_type: parameter.literal_type.clone(),
+                value,
+                is_mutable: true,
+            },
+        );
+    }
 
-            let function = state
-                .functions
-                .get(name)
-                .ok_or_else(|| (Execution::NotFound(name.clone()), span.clone()))?;
-
-            let call = match function {
-                Function::BuiltIn(call) => call,
-            };
+    if let Some(call_depth) = state.limits.call_depth {
+        if state.usage.call_depth >= call_depth {
+            return Err((Execution::CallDepthLimit(call_depth), span.clone()));
+        }
+    }
 
-            let values: Vec<Literal> = args
-                .iter()
-                .map(|arg| eval!(arg, state))
-                .collect::<Result<_, _>>()?;
+    let globals = state.scopes.len().min(1);
+    let caller = state.scopes.split_off(globals);
+    state.scopes.push(Scope {
+        variables,
+        ..Scope::default()
+    });
+    declare_scope(state, &subroutine.body);
+    state.usage.call_depth += 1;
+    let flow = block(&subroutine.body, state, true);
+    state.usage.call_depth -= 1;
+    let flow = flow?;
+    let mut parameters = pop_scope(state).variables;
+    state.scopes.extend(caller);
 
-            return call(values).map_err(span!(span));
-        }
-        Expression::ArrayIndex(identifier, expression) => {
-            let index = evaluate_expression(&*expression, state)?;
-            let index = match index {
-                Literal::Integer(value) => value,
-                _ => {
-                    return Err((
-                        Execution::IncorrectType(LiteralType::Integer.into(), LiteralType::from(&index).into()),
-                        span.clone(),
-                    ))
+    for ((parameter, _), (arg, arg_span)) in subroutine.parameters.iter().zip(args) {
+        if let (Passing::ByReference, Expression::Variable(identifier)) = (parameter.passing, arg) {
+            match parameters.remove(&parameter.name) {
+                Some(Variable::Array { values, .. }) => {
+                    if let Variable::Array { values: to, .. } =
+                        get_mut_variable(state, identifier).map_err(span!(arg_span))?
+                    {
+                        *to = values;
+                    }
                 }
-            };
-            let index: usize = index.try_into().map_err(|_| {
-                (
-                    Execution::NegativeIndex(String::from(identifier), index),
-                    span.clone(),
-                )
-            })?;
-
-            index_array(state, identifier, index)
-                .map(|literal| literal.clone())
-                .map_err(span!(span))
+                Some(Variable::Literal {
+                    value: Some(value), ..
+                }) => assign_literal(state, identifier, value).map_err(span!(arg_span))?,
+                _ => {}
+            }
         }
     }
+
+    let value = match (flow, &subroutine.returns) {
+        (Flow::Return(Some(value)), Some(returns)) => Some(value.widen(returns)),
+        (Flow::Return(value), _) => value,
+        (Flow::Next, _) => None,
+    };
+    if let (Some(value), Some(returns)) = (&value, &subroutine.returns) {
+        match_literal(returns, &value.into()).map_err(span!(span))?;
+    }
+    Ok(value)
 }
 
-fn negate(value: &Literal) -> Result<Literal, Execution> {
+pub fn negate(value: &Literal) -> Result<Literal, Execution> {
     match *value {
-        Literal::Integer(value) => Ok(Literal::Integer(-value)),
+        Literal::Integer(value) => integer(&Ops::Minus, value.checked_neg()),
         Literal::Real(value) => Ok(Literal::Real(-value)),
         _ => Err(Execution::UnaryNotSupported(
             Ops::Minus,
@@ -256,7 +822,7 @@ fn negate(value: &Literal) -> Result<Literal, Execution> {
     }
 }
 
-fn not(value: &Literal) -> Result<Literal, Execution> {
+pub fn not(value: &Literal) -> Result<Literal, Execution> {
     match *value {
         Literal::Bool(value) => Ok(Literal::Bool(!value)),
         _ => Err(Execution::UnaryNotSupported(
@@ -273,14 +839,6 @@ macro_rules! bool {
 }
 
 macro_rules! ops {
-    ($left: expr, $right: expr, $not_found: ident, $($op: ident, $enum_a: ident, $enum_b: ident =>  $(($type: tt))? $enum_result: ident),+) => {
-        match ($left, $right) {
-            $((Literal::$enum_a(a), Literal::$enum_b(b)) => {
-                Ok(Literal::$enum_result((a.clone() $(as $type)?).$op(b.clone() $(as $type)?)))
-            },)+
-            _ => Err($not_found())
-        }
-    };
     ($left: expr, $right: expr, $not_found: ident, $($enum_a: ident $op: tt  $enum_b: ident),+) => {
         match ($left, $right) {
             $((Literal::$enum_a(a), Literal::$enum_b(b)) => {
@@ -291,7 +849,22 @@ macro_rules! ops {
     };
 }
 
-fn operate(operation: &Ops, a: &Literal, b: &Literal) -> Result<Literal, Execution> {
+/// An integer result, or `Overflow` when it did not fit.
+fn integer(operation: &Ops, result: Option<isize>) -> Result<Literal, Execution> {
+    result
+        .map(Literal::Integer)
+        .ok_or_else(|| Execution::Overflow(operation.clone()))
+}
+
+/// A real result, or `Overflow` when it grew past the largest real.
+fn real(operation: &Ops, result: f64) -> Result<Literal, Execution> {
+    match result.is_finite() {
+        true => Ok(Literal::Real(result)),
+        false => Err(Execution::Overflow(operation.clone())),
+    }
+}
+
+pub fn operate(operation: &Ops, a: &Literal, b: &Literal) -> Result<Literal, Execution> {
     let not_found = || {
         Execution::BinaryNotSupported(
             operation.clone(),
@@ -299,60 +872,94 @@ fn operate(operation: &Ops, a: &Literal, b: &Literal) -> Result<Literal, Executi
             LiteralType::from(b).into(),
         )
     };
+    let by_zero = || Execution::DivisionByZero(a.to_string());
     use Literal::*;
+    // an INTEGER meeting a REAL widens to one, so every arm below only pairs like with like
+    let widened;
+    let (a, b) = match (a, b) {
+        (Integer(value), Real(_)) => {
+            widened = Real(*value as f64);
+            (&widened, b)
+        }
+        (Real(_), Integer(value)) => {
+            widened = Real(*value as f64);
+            (a, &widened)
+        }
+        _ => (a, b),
+    };
     match operation {
-        Ops::Plus => ops!(
-            a, b, not_found,
-            add, Integer, Integer => Integer,
-            add, Real, Real => Real
-        ),
-        Ops::Minus => ops!(
-            a, b, not_found,
-            sub, Integer, Integer => Integer,
-            sub, Real, Real => Real
-        ),
-        Ops::Divide => ops!(
-            a, b, not_found,
-            div, Integer, Integer => (f64) Real,
-            div, Real, Real => Real
-        ),
-        Ops::Multiply => ops!(
-            a, b, not_found,
-            mul, Integer, Integer => Integer,
-            mul, Real, Real => Real
-        ),
-        Ops::Mod => ops!(
-            a, b, not_found,
-            rem, Integer, Integer => Integer
-        ),
-        Ops::Div => ops!(
-            a, b, not_found,
-            div, Integer, Integer => Integer,
-            div, Real, Real => (isize) Integer
-        ),
+        Ops::Plus => match (a, b) {
+            (Integer(a), Integer(b)) => integer(operation, a.checked_add(*b)),
+            (Real(a), Real(b)) => real(operation, a + b),
+            _ => Err(not_found()),
+        },
+        Ops::Minus => match (a, b) {
+            (Integer(a), Integer(b)) => integer(operation, a.checked_sub(*b)),
+            (Real(a), Real(b)) => real(operation, a - b),
+            _ => Err(not_found()),
+        },
+        Ops::Multiply => match (a, b) {
+            (Integer(a), Integer(b)) => integer(operation, a.checked_mul(*b)),
+            (Real(a), Real(b)) => real(operation, a * b),
+            _ => Err(not_found()),
+        },
+        // dividing by zero is an error for reals too, rather than an infinity nothing else can use
+        Ops::Divide => match (a, b) {
+            (Integer(_), Integer(0)) => Err(by_zero()),
+            (Real(_), Real(b)) if *b == 0.0 => Err(by_zero()),
+            (Integer(a), Integer(b)) => real(operation, *a as f64 / *b as f64),
+            (Real(a), Real(b)) => real(operation, a / b),
+            _ => Err(not_found()),
+        },
+        Ops::Mod => match (a, b) {
+            (Integer(_), Integer(0)) => Err(by_zero()),
+            (Integer(a), Integer(b)) => integer(operation, a.checked_rem(*b)),
+            _ => Err(not_found()),
+        },
+        Ops::Div => match (a, b) {
+            (Integer(_), Integer(0)) => Err(by_zero()),
+            (Real(_), Real(b)) if *b == 0.0 => Err(by_zero()),
+            (Integer(a), Integer(b)) => integer(operation, a.checked_div(*b)),
+            // the quotient is truncated, not the operands, so that 7 DIV 0.5 is 14
+            (Real(a), Real(b)) => {
+                let quotient = (a / b).trunc();
+                let fits = quotient >= isize::MIN as f64 && quotient < isize::MAX as f64;
+                integer(operation, fits.then_some(quotient as isize))
+            }
+            _ => Err(not_found()),
+        },
         Ops::GreaterThan => ops!(
             a,
             b,
             not_found,
             Integer > Integer,
+            Real > Real,
             Bool > Bool,
-            String > String
+            String > String,
+            Char > Char,
+            Date > Date
         ),
         Ops::LessThan => ops!(
             a,
             b,
             not_found,
             Integer < Integer,
+            Real < Real,
             Bool < Bool,
-            String < String
+            String < String,
+            Char < Char,
+            Date < Date
         ),
         Ops::Equal => ops!(
             a,
             b,
             not_found,
             Integer == Integer,
+            Real == Real,
             Bool == Bool,
-            String == String
+            String == String,
+            Char == Char,
+            Date == Date
         ),
         Ops::And => ops!(a, b, not_found, Bool && Bool),
         Ops::Or => ops!(a, b, not_found, Bool || Bool),
@@ -361,139 +968,171 @@ fn operate(operation: &Ops, a: &Literal, b: &Literal) -> Result<Literal, Executi
             b,
             not_found,
             Integer != Integer,
+            Real != Real,
             Bool != Bool,
-            String != String
+            String != String,
+            Char != Char,
+            Date != Date
+        ),
+        Ops::GreaterThanEqual => ops!(
+            a,
+            b,
+            not_found,
+            Integer >= Integer,
+            Real >= Real,
+            Bool >= Bool,
+            String >= String,
+            Char >= Char,
+            Date >= Date
+        ),
+        Ops::LessThanEqual => ops!(
+            a,
+            b,
+            not_found,
+            Integer <= Integer,
+            Real <= Real,
+            Bool <= Bool,
+            String <= String,
+            Char <= Char,
+            Date <= Date
         ),
-        Ops::GreaterThanEqual => ops!(a, b, not_found, Integer >= Integer),
-        Ops::LessThanEqual => ops!(a, b, not_found, Integer <= Integer),
         Ops::Concatenate => match (a, b) {
             (String(a), String(b)) => Ok(String(a.to_owned() + b)),
+            (String(a), Char(b)) => Ok(String(format!("{}{}", a, b))),
+            (Char(a), String(b)) => Ok(String(format!("{}{}", a, b))),
+            (Char(a), Char(b)) => Ok(String(format!("{}{}", a, b))),
             _ => Err(not_found()),
         },
         Ops::Not => Err(not_found()),
     }
 }
 
-pub fn evaluate<'a>(
+/// What the statements around a block do once it finishes.
+enum Flow {
+    Next,
+    /// A `RETURN` ran, so every block up to the subroutine's body stops.
+    Return(Option<Literal>),
+}
+
+macro_rules! propagate {
+    ($flow: expr) => {
+        if let Flow::Return(value) = $flow {
+            return Ok(Flow::Return(value));
+        }
+    };
+}
+
+pub fn evaluate(
     statements: &Vec<Spanned<Statement>>,
     mut state: State,
     as_function: bool,
 ) -> Result<State, Spanned<Execution>> {
-    state.scopes.push(Scope {
-        variables: HashMap::new(),
-    });
+    if let Some(error) = resolve(statements).into_iter().next() {
+        return Err(error);
+    }
+    if state.scopes.is_empty() {
+        state.scopes.push(Scope::default());
+    }
+    declare_scope(&mut state, statements);
+    block(statements, &mut state, as_function)?;
+    Ok(state)
+}
+
+/// Makes the subroutines defined in `statements` callable, before any statement runs so that a
+/// program can call a subroutine defined further down.
+pub fn define(statements: &[Spanned<Statement>], state: &mut State) {
+    for (statement, _) in statements {
+        if let Statement::Subroutine(subroutine) = statement {
+            state.functions.insert(
+                subroutine.name.clone(),
+                Function::User(Rc::clone(subroutine)),
+            );
+        }
+    }
+}
 
+fn block(
+    statements: &Vec<Spanned<Statement>>,
+    state: &mut State,
+    in_subroutine: bool,
+) -> Result<Flow, Spanned<Execution>> {
+    define(statements, state);
+    run(statements, state, in_subroutine)
+}
+
+fn run(
+    statements: &Vec<Spanned<Statement>>,
+    state: &mut State,
+    in_subroutine: bool,
+) -> Result<Flow, Spanned<Execution>> {
     for (statement, span) in statements {
+        step(state).map_err(span!(span))?;
         match statement {
-            Statement::Declare(Declare::Literal(identifier, literal_type)) => {
-                if get_variable(&state, identifier.as_str()).is_ok() {
-                    return Err((
-                        Execution::AlreadyDeclared(String::from(identifier)),
-                        span.clone(),
-                    ));
+            Statement::Declare(declares) => {
+                for declare in declares {
+                    declare_variable(state, declare, span)?;
                 }
-                let variables = state.scopes.last_mut().unwrap().variables.borrow_mut();
-
-                variables.insert(String::from(identifier), literal_type.into());
             }
 
-            Statement::Declare(Declare::Array(identifier, bounds, literal_type)) => {
-                if get_variable(&state, identifier.as_str()).is_ok() {
-                    return Err((
-                        Execution::AlreadyDeclared(String::from(identifier)),
-                        span.clone(),
-                    ));
-                }
-                if bounds.lower >= bounds.upper {
-                    return Err((Execution::InvalidBounds(bounds.clone()), span.clone()));
-                }
-
-                let variables = state.scopes.last_mut().unwrap().variables.borrow_mut();
-
-                let array = Variable::Array {
-                    literal_type: literal_type.clone(),
-                    bounds: bounds.clone(),
-                    values: vec![None; (bounds.upper - bounds.lower) + 1],
-                };
-
-                variables.insert(String::from(identifier), array);
+            Statement::Assign(Assign::Literal(identifier, expression))
+                if matches!(get_variable(state, identifier), Ok(Variable::Array { .. })) =>
+            {
+                let (from, ..) = array_named(state, expression)?;
+                copy_array(state, identifier, from).map_err(span!(span))?;
             }
 
             Statement::Assign(Assign::Literal(identifier, expression)) => {
-                let value = evaluate_expression(expression, &state)?;
+                let value = evaluate_expression(expression, state)?;
 
-                state = assign_literal(state, identifier.as_str(), value).map_err(span!(span))?;
+                assign_literal(state, identifier.as_str(), value).map_err(span!(span))?;
             }
 
             Statement::Assign(Assign::Array(identifier, index, expression)) => {
-                let index = evaluate_expression(index, &state)?;
-                let index = match index {
-                    Literal::Integer(value) => value,
-                    _ => {
-                        return Err((
-                            Execution::IncorrectType(
-                                LiteralType::Integer.into(),
-                                LiteralType::from(&index).into(),
-                            ),
-                            span.clone(),
-                        ))
-                    }
-                };
-                let index: usize = index.try_into().map_err(|_| {
-                    (
-                        Execution::NegativeIndex(String::from(identifier), index),
-                        span.clone(),
-                    )
-                })?;
-
-                let to_assign = evaluate_expression(expression, &state)?;
+                let index = index_value(state, index)?;
+                let to_assign = evaluate_expression(expression, state)?;
 
-                state = assign_array(state, identifier, index, to_assign).map_err(span!(span))?;
+                assign_array(state, identifier, index, to_assign).map_err(span!(span))?;
             }
 
-            Statement::Out(expressions) => {
+            Statement::Out(expressions, newline) => {
                 let values: Vec<Literal> = expressions
                     .iter()
-                    .map(|expression| evaluate_expression(expression, &state))
+                    .map(|expression| evaluate_expression(expression, state))
                     .collect::<Result<Vec<Literal>, Spanned<Execution>>>()?;
 
-                println!(
-                    "{}",
-                    values
-                        .iter()
-                        .map(|literal| format!("{}", literal))
-                        .collect::<Vec<_>>()
-                        .join("")
-                );
+                let mut text: String = values.iter().map(|literal| format!("{}", literal)).collect();
+                if *newline {
+                    text.push('\n');
+                }
+                state.io.write(&text);
             }
 
-            Statement::In(identifier) => {
-                let variable = get_variable(&state, identifier.as_str()).map_err(span!(span))?;
-
-                let literal_type = match variable {
-                    Variable::Literal { literal_type, .. } => literal_type,
-                    _ => return Err((Execution::IncorrectType(LiteralType::String.into(), DataTypes::Array), span.clone())),
+            Statement::In(identifier, index) => {
+                let literal_type = match get_variable(state, identifier).map_err(span!(span))? {
+                    Variable::Literal { literal_type, .. } if index.is_none() => literal_type.clone(),
+                    Variable::Array { literal_type, .. } if index.is_some() => literal_type.clone(),
+                    Variable::Literal { literal_type, .. } => {
+                        return Err((Execution::IncorrectType(DataTypes::Array, literal_type.into()), span.clone()))
+                    }
+                    Variable::Array { .. } => {
+                        return Err((Execution::IncorrectType(LiteralType::Any.into(), DataTypes::Array), span.clone()))
+                    }
+                };
+                let index = match index {
+                    Some(index) => Some(index_value(state, index)?),
+                    None => None,
                 };
 
-                if literal_type != &LiteralType::String {
-                    return Err((
-                        Execution::IncorrectType(LiteralType::String.into(), literal_type.into()),
-                        span.clone(),
-                    ));
+                let input = read_input(state, &literal_type).map_err(span!(span))?;
+                match index {
+                    Some(index) => assign_array(state, identifier, index, input),
+                    None => assign_literal(state, identifier, input),
                 }
-
-                let mut input = String::new();
-                stdin().read_line(&mut input).unwrap();
-                state = assign_literal(
-                    state,
-                    &identifier,
-                    Literal::String(input.trim().to_string()),
-                )
                 .map_err(span!(span))?;
             }
 
             Statement::If(conditional, if_branch, else_branch) => {
-                let result = evaluate_expression(conditional, &state)?;
+                let result = evaluate_expression(conditional, state)?;
                 let data_type = LiteralType::from(&result);
 
                 if data_type != LiteralType::Boolean {
@@ -506,37 +1145,26 @@ pub fn evaluate<'a>(
                 let condition = bool::from(&result);
 
                 if condition {
-                    state = evaluate(if_branch, state, false)?;
-                } else {
-                    if let Some(statements) = else_branch {
-                        state = evaluate(statements, state, false)?;
-                    }
+                    propagate!(block(if_branch, state, in_subroutine)?);
+                } else if let Some(statements) = else_branch {
+                    propagate!(block(statements, state, in_subroutine)?);
                 }
             }
 
             Statement::Return(return_value) => {
-                if !as_function {
+                if !in_subroutine {
                     return Err((Execution::CanNotCallReturn, span.clone()));
                 }
 
-                if let Some(expression) = return_value {
-                    let value = evaluate_expression(expression, &state)?;
-                    let mut scope = state.scopes.pop().unwrap();
-                    scope.variables.insert(
-                        String::from("RETURN"),
-                        Variable::Literal {
-                            literal_type: LiteralType::from(&value),
-                            value: Some(value),
-                            is_mutable: true,
-                        },
-                    );
-                }
-
-                return Ok(state);
+                let value = match return_value {
+                    Some(expression) => Some(evaluate_expression(expression, state)?),
+                    None => None,
+                };
+                return Ok(Flow::Return(value));
             }
 
             Statement::For(identifier, start, end, statements) => {
-                let iterator = get_variable(&state, identifier).map_err(span!(span))?;
+                let iterator = get_variable(state, identifier).map_err(span!(span))?;
                 match iterator {
                     Variable::Literal {
                         literal_type,
@@ -564,7 +1192,7 @@ pub fn evaluate<'a>(
                     }
                 }
 
-                let start_literal = evaluate_expression(start, &state)?;
+                let start_literal = evaluate_expression(start, state)?;
                 let start = match start_literal {
                     Literal::Integer(value) => value,
                     _ => {
@@ -577,7 +1205,7 @@ pub fn evaluate<'a>(
                         ))
                     }
                 };
-                let end_literal = evaluate_expression(end, &state)?;
+                let end_literal = evaluate_expression(end, state)?;
                 let end = match end_literal {
                     Literal::Integer(value) => value,
                     _ => {
@@ -594,15 +1222,15 @@ pub fn evaluate<'a>(
                 if start <= end {
                     let range = start..=end;
                     for n in range {
-                        state = assign_literal(state, identifier, Literal::Integer(n))
+                        assign_literal(state, identifier, Literal::Integer(n))
                             .map_err(span!(span))?;
-                        state = evaluate(statements, state, as_function)?;
+                        propagate!(block(statements, state, in_subroutine)?);
                     }
                 }
             }
 
             Statement::While(expression, statements) => loop {
-                let condition = evaluate_expression(expression, &state)?;
+                let condition = evaluate_expression(expression, state)?;
                 let continue_loop = match condition {
                     Literal::Bool(value) => value,
                     _ => {
@@ -616,15 +1244,15 @@ pub fn evaluate<'a>(
                     }
                 };
                 if continue_loop {
-                    state = evaluate(&statements, state, as_function)?;
+                    propagate!(block(statements, state, in_subroutine)?);
                 } else {
                     break;
                 }
             },
 
             Statement::Repeat(statements, expression) => loop {
-                state = evaluate(statements, state, as_function)?;
-                let condition = evaluate_expression(expression, &state)?;
+                propagate!(block(statements, state, in_subroutine)?);
+                let condition = evaluate_expression(expression, state)?;
                 match condition {
                     Literal::Bool(value) => {
                         if value {
@@ -643,11 +1271,38 @@ pub fn evaluate<'a>(
                 }
             },
 
-            Statement::ProcedureCall(_, _) => {
-                todo!()
+            Statement::ProcedureCall(name, args) => {
+                call_function(name, args, span, state)?;
+            }
+
+            Statement::Subroutine(_) | Statement::Test(..) => {}
+
+            // imports are replaced by what they import when a file is loaded, so one left here
+            // has nothing to read from
+            Statement::Import(path) => {
+                let reason = String::from("only a program read from a file can import");
+                return Err((Execution::CanNotImport(path.clone(), reason), span.clone()));
+            }
+
+            Statement::Assert(conditional, message) => {
+                let result = evaluate_expression(conditional, state)?;
+                match result {
+                    Literal::Bool(true) => {}
+                    Literal::Bool(false) => {
+                        return Err((Execution::AssertionFailed(message.clone()), span.clone()))
+                    }
+                    _ => {
+                        return Err((
+                            Execution::IncorrectType(
+                                LiteralType::Boolean.into(),
+                                LiteralType::from(&result).into(),
+                            ),
+                            span.clone(),
+                        ))
+                    }
+                }
             }
         }
     }
-    state.scopes.pop();
-    Ok(state)
+    Ok(Flow::Next)
 }
@@ -0,0 +1,225 @@
+use crate::ast::*;
+use crate::format::target;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Terminator,
+    Process,
+    Decision,
+    InputOutput,
+    Subroutine,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub shape: Shape,
+    pub label: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub label: Option<&'static str>,
+}
+
+/// A flowchart as nodes, indexed by their position, and the arrows between them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Flowchart {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// Arrows still waiting for the node that comes next: where each starts and its label.
+type Exits = Vec<(usize, Option<&'static str>)>;
+
+const YES: Option<&str> = Some("Yes");
+const NO: Option<&str> = Some("No");
+
+
+/// Draws a program from `START` to `STOP`. Declarations, subroutine definitions and `TEST` blocks
+/// are left out, since they do nothing where they are written.
+pub fn flowchart(statements: &[Spanned<Statement>]) -> Flowchart {
+    let mut chart = Flowchart::default();
+    let start = chart.node(Shape::Terminator, String::from("START"));
+    let exits = chart.block(statements, vec![(start, None)]);
+    let stop = chart.node(Shape::Terminator, String::from("STOP"));
+    chart.connect(exits, stop);
+    chart
+}
+
+impl Flowchart {
+    fn node(&mut self, shape: Shape, label: String) -> usize {
+        self.nodes.push(Node { shape, label });
+        self.nodes.len() - 1
+    }
+
+    fn connect(&mut self, exits: Exits, to: usize) {
+        for (from, label) in exits {
+            self.edges.push(Edge { from, to, label });
+        }
+    }
+
+    /// Adds a node that every pending arrow leads into, leaving it as the only one pending.
+    fn then(&mut self, exits: Exits, shape: Shape, label: String) -> Exits {
+        let node = self.node(shape, label);
+        self.connect(exits, node);
+        vec![(node, None)]
+    }
+
+    fn block(&mut self, statements: &[Spanned<Statement>], mut exits: Exits) -> Exits {
+        for (statement, _) in statements {
+            exits = self.statement(statement, exits);
+        }
+        exits
+    }
+
+    fn statement(&mut self, statement: &Statement, exits: Exits) -> Exits {
+        match statement {
+            Statement::Declare(_)
+            | Statement::Subroutine(_)
+            | Statement::Test(..)
+            | Statement::Import(_) => exits,
+            Statement::Assign(Assign::Literal(identifier, (value, _))) => self.then(
+                exits,
+                Shape::Process,
+                format!("{} ← {}", identifier, value),
+            ),
+            Statement::Assign(Assign::Array(identifier, (index, _), (value, _))) => self.then(
+                exits,
+                Shape::Process,
+                format!(
+                    "{}[{}] ← {}",
+                    identifier,
+                    index,
+                    value
+                ),
+            ),
+            Statement::Out(values, _) => self.then(
+                exits,
+                Shape::InputOutput,
+                format!("OUTPUT {}", arguments(values)),
+            ),
+            Statement::In(identifier, index) => self.then(
+                exits,
+                Shape::InputOutput,
+                format!("INPUT {}", target(identifier, index)),
+            ),
+            Statement::ProcedureCall(name, args) => self.then(
+                exits,
+                Shape::Subroutine,
+                format!("CALL {}({})", name, arguments(args)),
+            ),
+            Statement::Return(None) => self.then(exits, Shape::Process, String::from("RETURN")),
+            Statement::Return(Some((value, _))) => self.then(
+                exits,
+                Shape::Process,
+                format!("RETURN {}", value),
+            ),
+            Statement::Assert((conditional, _), _) => self.then(
+                exits,
+                Shape::Process,
+                format!("ASSERT {}", conditional),
+            ),
+            Statement::If((conditional, _), if_branch, else_branch) => {
+                let decision = self.node(Shape::Decision, format!("{}?", conditional));
+                self.connect(exits, decision);
+                let mut exits = self.block(if_branch, vec![(decision, YES)]);
+                match else_branch {
+                    Some(else_branch) => {
+                        exits.extend(self.block(else_branch, vec![(decision, NO)]))
+                    }
+                    None => exits.push((decision, NO)),
+                }
+                exits
+            }
+            Statement::While((conditional, _), statements) => {
+                let decision = self.node(Shape::Decision, format!("{}?", conditional));
+                self.connect(exits, decision);
+                let body = self.block(statements, vec![(decision, YES)]);
+                self.connect(body, decision);
+                vec![(decision, NO)]
+            }
+            Statement::Repeat(statements, (conditional, _)) => {
+                // The loop goes back to the body's first node, which is the test itself when the
+                // body only declares variables.
+                let first = self.nodes.len();
+                let body = self.block(statements, exits);
+                let decision = self.node(Shape::Decision, format!("{}?", conditional));
+                self.connect(body, decision);
+                self.connect(vec![(decision, NO)], first);
+                vec![(decision, YES)]
+            }
+            Statement::For(identifier, (start, _), (end, _), statements) => {
+                let exits = self.then(
+                    exits,
+                    Shape::Process,
+                    format!("{} ← {}", identifier, start),
+                );
+                let decision = self.node(
+                    Shape::Decision,
+                    format!("{} ≤ {}?", identifier, end),
+                );
+                self.connect(exits, decision);
+                let body = self.block(statements, vec![(decision, YES)]);
+                let step = self.then(
+                    body,
+                    Shape::Process,
+                    format!("{} ← {} + 1", identifier, identifier),
+                );
+                self.connect(step, decision);
+                vec![(decision, NO)]
+            }
+        }
+    }
+
+    pub fn dot(&self) -> String {
+        let escape = |label: &str| label.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut output = String::from("digraph flowchart {\n    node [fontname=\"Helvetica\"];\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let shape = match node.shape {
+                Shape::Terminator => "shape=ellipse",
+                Shape::Process => "shape=box",
+                Shape::Decision => "shape=diamond",
+                Shape::InputOutput => "shape=parallelogram",
+                Shape::Subroutine => "shape=box, peripheries=2",
+            };
+            output.push_str(&format!(
+                "    n{} [{}, label=\"{}\"];\n",
+                index,
+                shape,
+                escape(&node.label)
+            ));
+        }
+        for edge in &self.edges {
+            let label = edge
+                .label
+                .map_or(String::new(), |label| format!(" [label=\"{}\"]", label));
+            output.push_str(&format!("    n{} -> n{}{};\n", edge.from, edge.to, label));
+        }
+        output.push_str("}\n");
+        output
+    }
+
+    pub fn mermaid(&self) -> String {
+        let mut output = String::from("flowchart TD\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let label = format!("\"{}\"", node.label.replace('"', "#quot;"));
+            let node = match node.shape {
+                Shape::Terminator => format!("([{}])", label),
+                Shape::Process => format!("[{}]", label),
+                Shape::Decision => format!("{{{}}}", label),
+                Shape::InputOutput => format!("[/{}/]", label),
+                Shape::Subroutine => format!("[[{}]]", label),
+            };
+            output.push_str(&format!("    n{}{}\n", index, node));
+        }
+        for edge in &self.edges {
+            let label = edge
+                .label
+                .map_or(String::new(), |label| format!("|{}|", label));
+            output.push_str(&format!("    n{} -->{} n{}\n", edge.from, label, edge.to));
+        }
+        output
+    }
+}
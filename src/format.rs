@@ -0,0 +1,301 @@
+use crate::ast::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrow {
+    Unicode,
+    Ascii,
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub arrow: Arrow,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            arrow: Arrow::Unicode,
+        }
+    }
+}
+
+const INDENT: &str = "    ";
+/// The guide indents `THEN` and `ELSE` by half a level under their `IF`.
+const HALF_INDENT: &str = "  ";
+
+/// Pretty-prints a parsed program in the layout the guide uses, putting back the comments the
+/// lexer kept as trivia. `tokens` is the lexer output before `strip_trivia`.
+pub fn format(
+    source: &str,
+    tokens: &[Spanned<Token>],
+    statements: &[Spanned<Statement>],
+    options: &Options,
+) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut line_starts = vec![0];
+    line_starts.extend(
+        chars
+            .iter()
+            .enumerate()
+            .filter(|(_, char)| **char == '\n')
+            .map(|(index, _)| index + 1),
+    );
+    let comments = tokens
+        .iter()
+        .filter_map(|(token, span)| match token {
+            Token::Comment(text) => Some((text.clone(), span.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut formatter = Formatter {
+        chars,
+        line_starts,
+        tokens,
+        comments,
+        next_comment: 0,
+        output: String::new(),
+        at_block_start: true,
+        arrow: match options.arrow {
+            Arrow::Unicode => "←",
+            Arrow::Ascii => "<-",
+        },
+    };
+    formatter.block(statements, "", None);
+    formatter.comments_before(usize::MAX, "", "", 0);
+    formatter.output
+}
+
+struct Formatter<'a> {
+    chars: Vec<char>,
+    line_starts: Vec<usize>,
+    tokens: &'a [Spanned<Token>],
+    comments: Vec<Spanned<String>>,
+    next_comment: usize,
+    output: String,
+    at_block_start: bool,
+    arrow: &'static str,
+}
+
+/// What an `INPUT` reads into, a variable or an array element.
+pub fn target(identifier: &str, index: &Option<Spanned<Expression>>) -> String {
+    match index {
+        Some((index, _)) => format!("{}[{}]", identifier, index),
+        None => String::from(identifier),
+    }
+}
+
+impl<'a> Formatter<'a> {
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    fn column(&self, offset: usize) -> usize {
+        offset - self.line_starts[self.line(offset)]
+    }
+
+    /// Whether the source line above the one holding `offset` is blank.
+    fn follows_blank_line(&self, offset: usize) -> bool {
+        let line = self.line(offset);
+        line > 0
+            && self.chars[self.line_starts[line - 1]..self.line_starts[line]]
+                .iter()
+                .all(|char| char.is_whitespace())
+    }
+
+    fn write_line(&mut self, indent: &str, text: &str) {
+        self.output.push_str(indent);
+        self.output.push_str(text);
+        self.output.push('\n');
+        self.at_block_start = false;
+    }
+
+    fn blank_line(&mut self, offset: usize) {
+        if !self.at_block_start && !self.output.ends_with("\n\n") && self.follows_blank_line(offset)
+        {
+            self.output.push('\n');
+        }
+    }
+
+    /// Writes every pending comment that starts before `offset`. Comments written further left
+    /// than `column` belong to the enclosing block and get its `outer` indent.
+    fn comments_before(&mut self, offset: usize, indent: &str, outer: &str, column: usize) {
+        while let Some((text, span)) = self.comments.get(self.next_comment).cloned() {
+            if span.start >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            self.blank_line(span.start);
+            let indent = if self.column(span.start) <= column {
+                outer
+            } else {
+                indent
+            };
+            self.write_line(indent, &format!("//{}", text));
+        }
+    }
+
+    /// Appends a comment sitting on the same line as the end of a statement.
+    fn trailing_comment(&mut self, end: usize) {
+        if let Some((text, span)) = self.comments.get(self.next_comment).cloned() {
+            if self.line(span.start) == self.line(end.saturating_sub(1)) {
+                self.next_comment += 1;
+                self.output.pop();
+                self.output.push_str(&format!(" //{}\n", text));
+            }
+        }
+    }
+
+    fn keyword(&self, after: usize, matches: impl Fn(&Token) -> bool) -> usize {
+        self.tokens
+            .iter()
+            .find(|(token, span)| span.start >= after && matches(token))
+            .map_or(usize::MAX, |(_, span)| span.start)
+    }
+
+    /// Writes a block's statements at `indent`, along with the comments before them and, when
+    /// `end` gives the offset of the closing keyword, the comments after them.
+    fn block(
+        &mut self,
+        statements: &[Spanned<Statement>],
+        indent: &str,
+        end: Option<(usize, &str, usize)>,
+    ) {
+        self.at_block_start = true;
+        for (statement, span) in statements {
+            self.comments_before(span.start, indent, indent, 0);
+            self.blank_line(span.start);
+            self.statement(statement, span, indent);
+            self.trailing_comment(span.end);
+        }
+        if let Some((end, outer, column)) = end {
+            self.comments_before(end, indent, outer, column);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, span: &Span, indent: &str) {
+        let inner = format!("{}{}", indent, INDENT);
+        let half = format!("{}{}", indent, HALF_INDENT);
+        let column = self.column(span.start);
+        let last_end = |statements: &[Spanned<Statement>]| {
+            statements.last().map_or(span.start, |(_, span)| span.end)
+        };
+        match statement {
+            Statement::Declare(declares) => {
+                let identifiers: Vec<&str> = declares.iter().map(Declare::identifier).collect();
+                let data_type = declares.first().map(Declare::data_type).unwrap_or_default();
+                self.write_line(
+                    indent,
+                    &format!("DECLARE {} : {}", identifiers.join(", "), data_type),
+                )
+            }
+            Statement::Assign(Assign::Literal(identifier, (value, _))) => self.write_line(
+                indent,
+                &format!("{} {} {}", identifier, self.arrow, value),
+            ),
+            Statement::Assign(Assign::Array(identifier, (index, _), (value, _))) => self
+                .write_line(
+                    indent,
+                    &format!(
+                        "{}[{}] {} {}",
+                        identifier,
+                        index,
+                        self.arrow,
+                        value
+                    ),
+                ),
+            Statement::Out(expressions, newline) => {
+                let comma = if *newline { "" } else { "," };
+                let line = format!("OUTPUT {}{}", arguments(expressions), comma);
+                self.write_line(indent, &line)
+            }
+            Statement::In(identifier, index) => {
+                self.write_line(indent, &format!("INPUT {}", target(identifier, index)))
+            }
+            Statement::Subroutine(subroutine) => {
+                self.write_line(indent, &subroutine.to_string());
+                let (end_token, end_keyword) = match subroutine.returns {
+                    Some(_) => (Token::EndFunction, "ENDFUNCTION"),
+                    None => (Token::EndProcedure, "ENDPROCEDURE"),
+                };
+                let end = self.keyword(last_end(&subroutine.body), |token| token == &end_token);
+                self.block(&subroutine.body, &inner, Some((end, indent, column)));
+                self.write_line(indent, end_keyword);
+            }
+            Statement::ProcedureCall(name, args) if args.is_empty() => {
+                self.write_line(indent, &format!("CALL {}", name))
+            }
+            Statement::ProcedureCall(name, args) => {
+                self.write_line(indent, &format!("CALL {}({})", name, arguments(args)))
+            }
+            Statement::Return(None) => self.write_line(indent, "RETURN"),
+            Statement::Return(Some((value, _))) => {
+                self.write_line(indent, &format!("RETURN {}", value))
+            }
+            Statement::If((conditional, _), if_branch, else_branch) => {
+                self.write_line(indent, &format!("IF {}", conditional));
+                self.write_line(&half, "THEN");
+                let else_offset = self.keyword(last_end(if_branch), |token| token == &Token::Else);
+                let end_if = self.keyword(last_end(if_branch), |token| token == &Token::EndIf);
+                match else_branch {
+                    Some(else_branch) => {
+                        self.block(if_branch, &inner, Some((else_offset, &half, column)));
+                        self.write_line(&half, "ELSE");
+                        let end_if =
+                            self.keyword(last_end(else_branch), |token| token == &Token::EndIf);
+                        self.block(else_branch, &inner, Some((end_if, indent, column)));
+                    }
+                    None => self.block(if_branch, &inner, Some((end_if, indent, column))),
+                }
+                self.write_line(indent, "ENDIF");
+            }
+            Statement::For(identifier, (start, _), (end, _), statements) => {
+                self.write_line(
+                    indent,
+                    &format!(
+                        "FOR {} {} {} TO {}",
+                        identifier,
+                        self.arrow,
+                        start,
+                        end
+                    ),
+                );
+                let next = self.keyword(last_end(statements), |token| {
+                    token == &Token::Next || token == &Token::EndFor
+                });
+                self.block(statements, &inner, Some((next, indent, column)));
+                self.write_line(indent, &format!("NEXT {}", identifier));
+            }
+            Statement::While((conditional, _), statements) => {
+                self.write_line(indent, &format!("WHILE {} DO", conditional));
+                let end_while =
+                    self.keyword(last_end(statements), |token| token == &Token::EndWhile);
+                self.block(statements, &inner, Some((end_while, indent, column)));
+                self.write_line(indent, "ENDWHILE");
+            }
+            Statement::Repeat(statements, (conditional, _)) => {
+                self.write_line(indent, "REPEAT");
+                let until = self.keyword(last_end(statements), |token| token == &Token::Until);
+                self.block(statements, &inner, Some((until, indent, column)));
+                self.write_line(indent, &format!("UNTIL {}", conditional));
+            }
+            Statement::Import(path) => self.write_line(indent, &format!("IMPORT \"{}\"", path)),
+            Statement::Test(name, statements) => {
+                self.write_line(indent, &format!("TEST \"{}\"", name));
+                let end_test =
+                    self.keyword(last_end(statements), |token| token == &Token::EndTest);
+                self.block(statements, &inner, Some((end_test, indent, column)));
+                self.write_line(indent, "ENDTEST");
+            }
+            Statement::Assert((conditional, _), message) => {
+                let message = message
+                    .as_ref()
+                    .map_or(String::new(), |message| format!(", \"{}\"", message));
+                self.write_line(
+                    indent,
+                    &format!("ASSERT {}{}", conditional, message),
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use chumsky::{Parser, Stream};
+
+use crate::ast::*;
+use crate::error_message;
+use crate::evaluate::{define, evaluate, Io};
+use crate::import::{load, Sources};
+use crate::lexer::{lexer_for, strip_trivia};
+use crate::parser::parser_for;
+use crate::Settings;
+
+/// The error a case's `.err` file says it should stop with, written as the `Execution` variant's
+/// name and optionally the line, e.g. `NotAssigned 2`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectedError {
+    pub kind: String,
+    pub line: Option<usize>,
+}
+
+impl ExpectedError {
+    fn parse(text: &str) -> Result<ExpectedError, String> {
+        let mut words = text.split_whitespace();
+        let kind = words
+            .next()
+            .ok_or_else(|| String::from("expected-error file is empty"))?;
+        let line = words
+            .next()
+            .map(|line| {
+                line.parse()
+                    .map_err(|_| format!("expected a line number after {}, found {}", kind, line))
+            })
+            .transpose()?;
+        Ok(ExpectedError {
+            kind: String::from(kind),
+            line,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+/// The `.psps` files `path` names: itself if it is a file, or those directly inside it, sorted.
+pub fn cases(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut cases: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    cases.retain(|case| {
+        case.extension()
+            .is_some_and(|extension| extension == "psps")
+    });
+    cases.sort();
+    Ok(cases)
+}
+
+/// The contents of the file next to `path` with the given extension, if there is one.
+fn sibling(path: &Path, extension: &str) -> io::Result<Option<String>> {
+    match fs::read_to_string(path.with_extension(extension)) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// The 1-based line holding the character at `offset`.
+pub fn line_of(source: &str, offset: usize) -> usize {
+    source
+        .chars()
+        .take(offset)
+        .filter(|char| *char == '\n')
+        .count()
+        + 1
+}
+
+pub fn parse(source: &str, settings: &Settings) -> Result<Vec<Spanned<Statement>>, String> {
+    let describe = |line: usize, message: String| format!("line {}: {}", line, message);
+    let tokens = lexer_for(settings.dialect)
+        .parse(source)
+        .map_err(|errors| {
+            describe(
+                line_of(source, errors[0].span().start),
+                error_message(&errors[0]),
+            )
+        })?;
+    let end = source.chars().count();
+    parser_for(settings.dialect)
+        .parse(Stream::from_iter(
+            end..end + 1,
+            strip_trivia(tokens).into_iter(),
+        ))
+        .map_err(|errors| {
+            describe(
+                line_of(source, errors[0].span().start),
+                error_message(&errors[0]),
+            )
+        })
+}
+
+/// Lists the lines where `actual` differs from `expected`, or says which one is missing the line
+/// ending the other finishes with. `\r\n` and `\n` count as the same line ending.
+pub fn diff(expected: &str, actual: &str) -> String {
+    if expected == actual {
+        return String::new();
+    }
+    let (expected_text, actual_text) = (expected, actual);
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    if expected == actual {
+        let ending = |text: &str| match text.ends_with('\n') {
+            true => "a line ending",
+            false => "no line ending",
+        };
+        let (expected, actual) = (ending(expected_text), ending(actual_text));
+        return match expected == actual {
+            true => String::new(),
+            false => format!(
+                "  end of output\n    expected: {}\n    found:    {}",
+                expected, actual
+            ),
+        };
+    }
+    let shown = |line: Option<&&str>| {
+        line.map_or(String::from("<end of output>"), |line| {
+            format!("{:?}", line)
+        })
+    };
+    (0..expected.len().max(actual.len()))
+        .filter(|index| expected.get(*index) != actual.get(*index))
+        .map(|index| {
+            format!(
+                "  line {}\n    expected: {}\n    found:    {}",
+                index + 1,
+                shown(expected.get(index)),
+                shown(actual.get(index))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// What `test` found in one file.
+#[derive(Debug)]
+pub struct Report {
+    pub sources: Sources,
+    /// How the program as a whole fared against its golden files, `None` when it was not run.
+    pub outcome: Option<Outcome>,
+    pub tests: Vec<TestResult>,
+}
+
+/// A `TEST` block and the error it stopped with, if any.
+#[derive(Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub span: Span,
+    pub failure: Option<Spanned<Execution>>,
+}
+
+/// Runs each top-level `TEST` block in a state of its own, with no input and the program's own
+/// statements left out. Its subroutines can still be called.
+pub fn run_tests(program: &[Spanned<Statement>], settings: &Settings) -> Vec<TestResult> {
+    program
+        .iter()
+        .filter_map(|(statement, span)| match statement {
+            Statement::Test(name, statements) => Some((name, statements, span)),
+            _ => None,
+        })
+        .map(|(name, statements, span)| {
+            let output = Rc::new(RefCell::new(String::new()));
+            let mut state = settings.state(settings.seed.unwrap_or(0), Io::scripted("", &output));
+            define(program, &mut state);
+            TestResult {
+                name: name.clone(),
+                span: span.clone(),
+                failure: evaluate(statements, state, false).err(),
+            }
+        })
+        .collect()
+}
+
+/// Runs the `TEST` blocks in the program at `path`, and the program itself unless it has tests but
+/// no golden files. The program gets the lines of `path.in` as its input, then what it wrote is
+/// checked against `path.out` and how it stopped against `path.err`. Without either file it only
+/// has to finish without an error. Runs are seeded with 0 unless the settings give a seed.
+pub fn run_case(path: &Path, settings: &Settings) -> io::Result<Report> {
+    let source = fs::read_to_string(path)?;
+    let (sources, parsed) = load(path, &source, settings.dialect);
+    // the line within whichever file the span is in
+    let line = |span: &Span| {
+        let (_, text, span) = sources.locate(span);
+        line_of(text, span.start)
+    };
+    let failed = |sources: Sources, message: String| Report {
+        sources,
+        outcome: Some(Outcome::Failed(message)),
+        tests: vec![],
+    };
+
+    let input = sibling(path, "in")?;
+    let expected_output = sibling(path, "out")?;
+    let expected_error = match sibling(path, "err")?.as_deref().map(ExpectedError::parse) {
+        Some(Err(message)) => return Ok(failed(sources, message)),
+        Some(Ok(expected)) => Some(expected),
+        None => None,
+    };
+
+    let statements = match parsed {
+        Ok((_, statements)) => statements,
+        Err(errors) => {
+            let message = format!("line {}: {}", line(&errors[0].span()), error_message(&errors[0]));
+            return Ok(failed(sources, format!("does not parse, {}", message)));
+        }
+    };
+    let tests = run_tests(&statements, settings);
+    let golden = input.is_some() || expected_output.is_some() || expected_error.is_some();
+    if !golden && !tests.is_empty() {
+        return Ok(Report {
+            sources,
+            outcome: None,
+            tests,
+        });
+    }
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let io = Io::scripted(input.as_deref().unwrap_or_default(), &output);
+    let state = settings.state(settings.seed.unwrap_or(0), io);
+    let error = evaluate(&statements, state, false).err();
+    let output = output.take();
+
+    let mut problems = vec![];
+    match (&expected_error, &error) {
+        (None, Some((error, span))) => problems.push(format!(
+            "stopped with {} on line {}: {}",
+            error.kind(),
+            line(span),
+            error
+        )),
+        (Some(expected), None) => problems.push(format!(
+            "finished but was expected to stop with {}",
+            expected.kind
+        )),
+        (Some(expected), Some((error, span))) => {
+            let line = line(span);
+            if expected.kind != error.kind()
+                || expected.line.is_some_and(|expected| expected != line)
+            {
+                problems.push(format!(
+                    "stopped with {} on line {} but was expected to stop with {}{}",
+                    error.kind(),
+                    line,
+                    expected.kind,
+                    expected
+                        .line
+                        .map_or(String::new(), |line| format!(" on line {}", line))
+                ));
+            }
+        }
+        (None, None) => {}
+    }
+    if let Some(expected) = expected_output {
+        let differences = diff(&expected, &output);
+        if !differences.is_empty() {
+            problems.push(format!(
+                "output differs from {}\n{}",
+                path.with_extension("out").display(),
+                differences
+            ));
+        }
+    }
+
+    let outcome = if problems.is_empty() {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(problems.join("\n"))
+    };
+    Ok(Report {
+        sources,
+        outcome: Some(outcome),
+        tests,
+    })
+}
@@ -0,0 +1,364 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast::*;
+use crate::dialect::Dialect;
+use crate::evaluate::{evaluate, time_limit, InputPolicy, Io, Limits};
+use crate::golden::{diff, line_of, parse};
+use crate::Settings;
+
+/// How a homework is marked, read from a TOML file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rubric {
+    pub dialect: Option<String>,
+    pub seed: Option<u64>,
+    pub limits: RubricLimits,
+    pub tests: Vec<Case>,
+    pub checks: Vec<Check>,
+    /// What the fields above say to run submissions with, worked out once `parse` has checked them.
+    #[serde(skip)]
+    settings: Settings,
+}
+
+/// Limits for each run. Steps and time default to a million statements and five seconds, so that
+/// one stuck submission can not hold up the rest.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RubricLimits {
+    pub steps: Option<u64>,
+    /// In seconds.
+    pub timeout: Option<f64>,
+    pub call_depth: Option<usize>,
+    pub array_size: Option<usize>,
+}
+
+/// A run of the submission with `input`, earning `marks` if it writes `output`. Trailing spaces on
+/// each line are ignored.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    #[serde(default)]
+    pub input: String,
+    pub output: String,
+    pub marks: u32,
+}
+
+/// Earns `marks` if the submission uses what `requires` names, or avoids what `forbids` names.
+/// Either can be a statement keyword such as `REPEAT`, a word operator such as `MOD`, or the name
+/// of a function.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Check {
+    pub name: String,
+    pub requires: Option<String>,
+    pub forbids: Option<String>,
+    pub marks: u32,
+}
+
+/// The marks one test or check earned.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Mark {
+    pub name: String,
+    pub awarded: u32,
+    pub marks: u32,
+    /// Why marks were lost.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Grade {
+    pub student: String,
+    pub score: u32,
+    pub total: u32,
+    /// Set when the submission did not parse, in which case it earns nothing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub tests: Vec<Mark>,
+    pub checks: Vec<Mark>,
+}
+
+impl Rubric {
+    pub fn parse(text: &str) -> Result<Rubric, String> {
+        let mut rubric: Rubric = toml::from_str(text).map_err(|error| error.to_string())?;
+        for check in &rubric.checks {
+            if check.requires.is_some() == check.forbids.is_some() {
+                return Err(format!(
+                    "check \"{}\" needs exactly one of `requires` or `forbids`",
+                    check.name
+                ));
+            }
+        }
+        rubric.settings = rubric.read_settings()?;
+        Ok(rubric)
+    }
+
+    fn read_settings(&self) -> Result<Settings, String> {
+        let dialect = match &self.dialect {
+            Some(name) => Dialect::find(name).ok_or_else(|| format!("unknown dialect {}", name))?,
+            None => Dialect::default(),
+        };
+        let defaults = Limits::default();
+        let limits = Limits {
+            steps: Some(self.limits.steps.unwrap_or(1_000_000)),
+            time: Some(time_limit(self.limits.timeout.unwrap_or(5.0)).map_err(|error| format!("timeout is invalid, {}", error))?),
+            call_depth: self.limits.call_depth.or(defaults.call_depth),
+            array_size: self.limits.array_size.or(defaults.array_size),
+        };
+        limits.fit_stack().map_err(|error| format!("call_depth is too deep, {}", error))?;
+        Ok(Settings {
+            dialect,
+            limits,
+            seed: Some(self.seed.unwrap_or(0)),
+            invalid_input: InputPolicy::Error,
+        })
+    }
+
+    pub fn total(&self) -> u32 {
+        self.tests.iter().map(|test| test.marks).sum::<u32>()
+            + self.checks.iter().map(|check| check.marks).sum::<u32>()
+    }
+}
+
+fn normalise(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A statement's keyword, expressions and nested blocks.
+type Parts<'a> = (
+    Option<&'a str>,
+    Vec<&'a Spanned<Expression>>,
+    Vec<&'a [Spanned<Statement>]>,
+);
+
+fn add(found: &mut HashSet<String>, feature: &str) {
+    found.insert(feature.to_uppercase());
+}
+
+/// Names of the statement keywords, word operators and functions a program uses, in uppercase.
+fn features(statements: &[Spanned<Statement>], found: &mut HashSet<String>) {
+    for (statement, _) in statements {
+        let (keyword, expressions, blocks): Parts = match statement {
+            Statement::Declare(declares) => {
+                if matches!(declares.first(), Some(Declare::Array(..))) {
+                    add(found, "ARRAY");
+                }
+                (Some("DECLARE"), vec![], vec![])
+            }
+            Statement::Out(values, _) => (Some("OUTPUT"), values.iter().collect(), vec![]),
+            Statement::Assign(Assign::Literal(_, value)) => (None, vec![value], vec![]),
+            Statement::Assign(Assign::Array(_, index, value)) => (None, vec![index, value], vec![]),
+            Statement::In(_, index) => (Some("INPUT"), index.iter().collect(), vec![]),
+            Statement::If(conditional, if_branch, else_branch) => {
+                let mut blocks = vec![&if_branch[..]];
+                if let Some(else_branch) = else_branch {
+                    add(found, "ELSE");
+                    blocks.push(else_branch);
+                }
+                (Some("IF"), vec![conditional], blocks)
+            }
+            Statement::Subroutine(subroutine) => {
+                if subroutine
+                    .parameters
+                    .iter()
+                    .any(|(parameter, _)| parameter.passing == Passing::ByReference)
+                {
+                    add(found, "BYREF");
+                }
+                let keyword = match subroutine.returns {
+                    Some(_) => "FUNCTION",
+                    None => "PROCEDURE",
+                };
+                (Some(keyword), vec![], vec![&subroutine.body[..]])
+            }
+            Statement::ProcedureCall(name, args) => {
+                add(found, name);
+                (Some("CALL"), args.iter().collect(), vec![])
+            }
+            Statement::Return(value) => (Some("RETURN"), value.iter().collect(), vec![]),
+            Statement::For(_, start, end, block) => (Some("FOR"), vec![start, end], vec![block]),
+            Statement::While(conditional, block) => (Some("WHILE"), vec![conditional], vec![block]),
+            Statement::Repeat(block, conditional) => {
+                (Some("REPEAT"), vec![conditional], vec![block])
+            }
+            Statement::Test(..) => continue,
+            Statement::Import(_) => (Some("IMPORT"), vec![], vec![]),
+            Statement::Assert(conditional, _) => (Some("ASSERT"), vec![conditional], vec![]),
+        };
+        if let Some(keyword) = keyword {
+            add(found, keyword);
+        }
+        for expression in expressions {
+            expression_features(expression, found);
+        }
+        for block in blocks {
+            features(block, found);
+        }
+    }
+}
+
+fn expression_features((expression, _): &Spanned<Expression>, found: &mut HashSet<String>) {
+    match expression {
+        Expression::Value(_) | Expression::Variable(_) => {}
+        Expression::ArrayIndex(_, index) => expression_features(index, found),
+        Expression::FunctionCall(name, args) => {
+            add(found, name);
+            for arg in args {
+                expression_features(arg, found);
+            }
+        }
+        Expression::Negative(inner) => expression_features(inner, found),
+        Expression::Not(inner) => {
+            add(found, "NOT");
+            expression_features(inner, found);
+        }
+        Expression::Operate(op, a, b) => {
+            if matches!(op, Ops::Mod | Ops::Div | Ops::And | Ops::Or) {
+                add(found, op.symbol());
+            }
+            expression_features(a, found);
+            expression_features(b, found);
+        }
+    }
+}
+
+/// Marks one submission: every test is run on its own, under the rubric's limits.
+pub fn grade(rubric: &Rubric, student: &str, source: &str) -> Grade {
+    let settings = &rubric.settings;
+    let nothing = |name: &String, marks: u32, detail: &str| Mark {
+        name: name.clone(),
+        awarded: 0,
+        marks,
+        detail: Some(String::from(detail)),
+    };
+
+    let statements = match parse(source, settings) {
+        Ok(statements) => statements,
+        Err(message) => {
+            return Grade {
+                student: String::from(student),
+                score: 0,
+                total: rubric.total(),
+                error: Some(format!("does not parse, {}", message)),
+                tests: rubric
+                    .tests
+                    .iter()
+                    .map(|test| nothing(&test.name, test.marks, "did not parse"))
+                    .collect(),
+                checks: rubric
+                    .checks
+                    .iter()
+                    .map(|check| nothing(&check.name, check.marks, "did not parse"))
+                    .collect(),
+            }
+        }
+    };
+
+    let tests: Vec<Mark> = rubric
+        .tests
+        .iter()
+        .map(|test| {
+            let output = Rc::new(RefCell::new(String::new()));
+            let state = settings.state(
+                settings.seed.unwrap_or(0),
+                Io::scripted(&test.input, &output),
+            );
+            let error = evaluate(&statements, state, false).err();
+            let differences = diff(&normalise(&test.output), &normalise(&output.take()));
+            let detail = match error {
+                Some((error, span)) => {
+                    Some(format!("line {}: {}", line_of(source, span.start), error))
+                }
+                None if !differences.is_empty() => Some(format!("output differs\n{}", differences)),
+                None => None,
+            };
+            Mark {
+                name: test.name.clone(),
+                awarded: if detail.is_none() { test.marks } else { 0 },
+                marks: test.marks,
+                detail,
+            }
+        })
+        .collect();
+
+    let mut used = HashSet::new();
+    features(&statements, &mut used);
+    let checks: Vec<Mark> = rubric
+        .checks
+        .iter()
+        .map(|check| {
+            let detail = match (&check.requires, &check.forbids) {
+                (Some(required), _) if !used.contains(&required.to_uppercase()) => {
+                    Some(format!("does not use {}", required))
+                }
+                (_, Some(forbidden)) if used.contains(&forbidden.to_uppercase()) => {
+                    Some(format!("uses {}", forbidden))
+                }
+                _ => None,
+            };
+            Mark {
+                name: check.name.clone(),
+                awarded: if detail.is_none() { check.marks } else { 0 },
+                marks: check.marks,
+                detail,
+            }
+        })
+        .collect();
+
+    Grade {
+        student: String::from(student),
+        score: tests.iter().chain(&checks).map(|mark| mark.awarded).sum(),
+        total: rubric.total(),
+        error: None,
+        tests,
+        checks,
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/// One row per student, with a column for each test and check followed by the score and total.
+pub fn csv(rubric: &Rubric, grades: &[Grade]) -> String {
+    let mut header = vec![String::from("student")];
+    header.extend(rubric.tests.iter().map(|test| test.name.clone()));
+    header.extend(rubric.checks.iter().map(|check| check.name.clone()));
+    header.extend([String::from("score"), String::from("total")]);
+
+    let mut rows = vec![header];
+    for grade in grades {
+        let mut row = vec![grade.student.clone()];
+        row.extend(
+            grade
+                .tests
+                .iter()
+                .chain(&grade.checks)
+                .map(|mark| mark.awarded.to_string()),
+        );
+        row.extend([grade.score.to_string(), grade.total.to_string()]);
+        rows.push(row);
+    }
+
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .map(|field| csv_field(field))
+                .collect::<Vec<_>>()
+                .join(",")
+                + "\n"
+        })
+        .collect()
+}
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+use ariadne::{sources, Cache};
+use chumsky::{prelude::*, Stream};
+
+use crate::ast::*;
+use crate::dialect::Dialect;
+use crate::error_message;
+use crate::lexer::{lexer_for, strip_trivia};
+use crate::parser::parser_for;
+
+/// The files a program was loaded from. Each file's spans start past the end of the one before
+/// it, so a span on its own says which file it is in.
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    /// The name each file is shown by, its text and the offset its spans start at.
+    files: Vec<(String, String, usize)>,
+}
+
+impl Sources {
+    pub fn single(name: &str, text: &str) -> Sources {
+        let mut sources = Sources::default();
+        sources.add(name, text);
+        sources
+    }
+
+    /// Adds a file, returning the offset its spans start at. The offset one past a file's end is
+    /// left free for the end of input in that file.
+    fn add(&mut self, name: &str, text: &str) -> usize {
+        let start = self
+            .files
+            .last()
+            .map_or(0, |(_, text, start)| start + text.chars().count() + 1);
+        self.files.push((name.to_owned(), text.to_owned(), start));
+        start
+    }
+
+    /// The name and text of the file `span` is in, and where in that file it is.
+    pub fn locate(&self, span: &Span) -> (&str, &str, Span) {
+        let (name, text, start) = self
+            .files
+            .iter()
+            .rev()
+            .find(|(_, _, start)| *start <= span.start)
+            .unwrap_or(&self.files[0]);
+        (name, text, span.start - start..span.end - start)
+    }
+
+    pub fn cache(&self) -> impl Cache<String> + '_ {
+        sources(
+            self.files
+                .iter()
+                .map(|(name, text, _)| (name.clone(), text.as_str())),
+        )
+    }
+}
+
+/// The main file's tokens, still including the comments kept as trivia, and its statements with
+/// each `IMPORT` replaced by what it brings in.
+pub type Parsed = (Vec<Spanned<Token>>, Vec<Spanned<Statement>>);
+
+/// Lexes and parses `source`, read from `path`, along with every file it imports. An import's
+/// path is relative to the file importing it, and brings in that file's procedures, functions and
+/// variables, including those it imports itself. A file imported more than once is only read the
+/// first time.
+pub fn load(
+    path: &Path,
+    source: &str,
+    dialect: Dialect,
+) -> (Sources, Result<Parsed, Vec<Simple<String>>>) {
+    let mut loader = Loader {
+        dialect,
+        sources: Sources::default(),
+        loading: vec![],
+        loaded: HashSet::new(),
+        errors: vec![],
+    };
+    let name = path
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let parsed = loader.file(path, name, source);
+    let result = match parsed {
+        Some(parsed) if loader.errors.is_empty() => Ok(parsed),
+        _ => Err(loader.errors),
+    };
+    (loader.sources, result)
+}
+
+struct Loader {
+    dialect: Dialect,
+    sources: Sources,
+    /// The files being loaded, each imported by the one before it, with the names they are shown
+    /// by.
+    loading: Vec<(PathBuf, String)>,
+    loaded: HashSet<PathBuf>,
+    errors: Vec<Simple<String>>,
+}
+
+impl Loader {
+    fn fail<T: Hash + Eq + Debug>(&mut self, errors: Vec<Simple<T>>) {
+        self.errors.extend(
+            errors
+                .iter()
+                .map(|error| Simple::custom(error.span(), error_message(error))),
+        );
+    }
+
+    fn file(&mut self, path: PathBuf, name: String, source: &str) -> Option<Parsed> {
+        let start = self.sources.add(&name, source);
+        let end = start + source.chars().count();
+        let chars = source
+            .chars()
+            .enumerate()
+            .map(|(index, char)| (char, start + index..start + index + 1));
+        let tokens = match lexer_for(self.dialect).parse(Stream::from_iter(end..end + 1, chars)) {
+            Ok(tokens) => tokens,
+            Err(errors) => {
+                self.fail(errors);
+                return None;
+            }
+        };
+        let statements = match parser_for(self.dialect).parse(Stream::from_iter(
+            end..end + 1,
+            strip_trivia(tokens.clone()).into_iter(),
+        )) {
+            Ok(statements) => statements,
+            Err(errors) => {
+                self.fail(errors);
+                return None;
+            }
+        };
+
+        self.loading.push((path, name));
+        let statements = statements
+            .into_iter()
+            .flat_map(|statement| self.statement(statement))
+            .collect();
+        let (path, _) = self.loading.pop().unwrap();
+        self.loaded.insert(path);
+        Some((tokens, statements))
+    }
+
+    /// What stands in for `statement`: the subroutines of the file it imports, or itself when it
+    /// is not an import.
+    fn statement(&mut self, (statement, span): Spanned<Statement>) -> Vec<Spanned<Statement>> {
+        let Statement::Import(target) = &statement else {
+            return vec![(statement, span)];
+        };
+        let (importer, importer_name) = self.loading.last().cloned().unwrap();
+        let path = importer.parent().unwrap_or(Path::new("")).join(target);
+        let name = Path::new(&importer_name)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(target)
+            .to_string_lossy()
+            .into_owned();
+
+        let imported = fs::canonicalize(&path).and_then(|path| {
+            let source = fs::read_to_string(&path)?;
+            Ok((path, source))
+        });
+        let (path, source) = match imported {
+            Ok(imported) => imported,
+            Err(error) => {
+                let error = Execution::CanNotImport(target.clone(), error.to_string());
+                self.fail(vec![Simple::<String>::custom(span, error.to_string())]);
+                return vec![];
+            }
+        };
+        if let Some(index) = self
+            .loading
+            .iter()
+            .position(|(loading, _)| loading == &path)
+        {
+            let mut cycle: Vec<String> = self.loading[index..]
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect();
+            cycle.push(self.loading[index].1.clone());
+            let error = Execution::ImportCycle(cycle);
+            self.fail(vec![Simple::<String>::custom(span, error.to_string())]);
+            return vec![];
+        }
+        if self.loaded.contains(&path) {
+            return vec![];
+        }
+
+        match self.file(path, name, &source) {
+            Some((_, statements)) => self.imported(statements),
+            None => vec![],
+        }
+    }
+
+    /// What an imported file brings in: its subroutines, its declarations and the assignments
+    /// giving the variables it declares their first values. Its tests are left for when the file
+    /// is run itself, and any other statement is reported, since it would never run.
+    fn imported(&mut self, statements: Vec<Spanned<Statement>>) -> Vec<Spanned<Statement>> {
+        let mut declared = HashSet::new();
+        let mut imported = vec![];
+        for (statement, span) in statements {
+            match &statement {
+                Statement::Subroutine(_) => {}
+                Statement::Declare(declares) => {
+                    declared.extend(declares.iter().map(|declare| declare.identifier().to_owned()))
+                }
+                Statement::Assign(Assign::Literal(identifier, _) | Assign::Array(identifier, ..))
+                    if declared.contains(identifier) => {}
+                Statement::Test(..) => continue,
+                _ => {
+                    let error = Execution::NotImportable;
+                    self.fail(vec![Simple::<String>::custom(span, error.to_string())]);
+                    continue;
+                }
+            }
+            imported.push((statement, span));
+        }
+        imported
+    }
+}
@@ -0,0 +1,687 @@
+use crate::ast::*;
+use crate::lower::*;
+
+/// Java's keywords and the names the generated class relies on. Pseudocode identifiers that match
+/// one get an underscore after them.
+const RESERVED: [&str; 65] = [
+    "abstract",
+    "assert",
+    "boolean",
+    "break",
+    "byte",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extends",
+    "final",
+    "finally",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "implements",
+    "import",
+    "instanceof",
+    "int",
+    "interface",
+    "long",
+    "native",
+    "new",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "short",
+    "static",
+    "strictfp",
+    "super",
+    "switch",
+    "synchronized",
+    "this",
+    "throw",
+    "throws",
+    "transient",
+    "try",
+    "void",
+    "volatile",
+    "while",
+    "true",
+    "false",
+    "null",
+    "var",
+    "String",
+    "System",
+    "Integer",
+    "Double",
+    "Object",
+    "LocalDate",
+    "DateTimeFormatter",
+    "input",
+    "random",
+    "main",
+    "java",
+];
+
+/// Writes a lowered program as a Java class named `class`.
+///
+/// The main program's variables become static fields so its subroutines can reach them, and
+/// arrays are sized to their upper bound so they are indexed as in the pseudocode. A procedure
+/// with `BYREF` parameters returns them, in an array when there are several, and the caller
+/// assigns them back. `TEST` blocks become methods checked with `assert`, which Java only runs
+/// with `-ea`.
+pub fn java(class: &str, program: &Program) -> String {
+    let mut java = Java {
+        writer: Writer::new("    "),
+        current: None,
+    };
+    let writer = &mut java.writer;
+    for comment in &program.header {
+        writer.line(&format!("//{}", comment));
+    }
+    if program.uses_random {
+        writer.line("import java.util.Random;");
+    }
+    if program.uses_dates {
+        writer.line("import java.time.LocalDate;");
+        writer.line("import java.time.format.DateTimeFormatter;");
+    }
+    if program.uses_input {
+        writer.line("import java.util.Scanner;");
+    }
+    writer.blank();
+    writer.line(&format!("public class {} {{", class));
+    writer.indent();
+    if program.uses_input {
+        writer.line("static Scanner input = new Scanner(System.in);");
+    }
+    if program.uses_dates {
+        writer.line("static DateTimeFormatter DATE = DateTimeFormatter.ofPattern(\"d/M/uuuu\");");
+    }
+    if program.uses_random {
+        writer.line("static Random random = new Random();");
+    }
+    for variable in &program.variables {
+        let field = match variable {
+            Declared::Literal(identifier, literal_type) => {
+                format!("static {} {};", java_type(literal_type), name(identifier))
+            }
+            // bounds worked out as the program runs are allocated where they are declared
+            Declared::Array(identifier, .., literal_type) if variable.bounds().is_none() => {
+                format!("static {}[] {};", java_type(literal_type), name(identifier))
+            }
+            Declared::Array(identifier, .., literal_type) => format!(
+                "static {}[] {} = {};",
+                java_type(literal_type),
+                name(identifier),
+                allocate(variable)
+            ),
+        };
+        java.writer.line(&field);
+    }
+
+    for routine in &program.subroutines {
+        java.writer.blank();
+        java.routine(routine, &name(&routine.name));
+    }
+    java.writer.blank();
+    java.writer.line("public static void main(String[] args) {");
+    java.body(&program.main);
+    java.writer.line("}");
+    for test in &program.tests {
+        java.writer.blank();
+        java.routine(test, &test_name(&test.name));
+    }
+    for comment in &program.footer {
+        java.writer.line(&format!("//{}", comment));
+    }
+    java.writer.dedent();
+    java.writer.line("}");
+    java.writer.output
+}
+
+fn name(identifier: &str) -> String {
+    if RESERVED.contains(&identifier) {
+        format!("{}_", identifier)
+    } else {
+        String::from(identifier)
+    }
+}
+
+fn java_type(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer => "int",
+        LiteralType::Real => "double",
+        LiteralType::String => "String",
+        LiteralType::Boolean => "boolean",
+        LiteralType::Char => "char",
+        LiteralType::Date => "LocalDate",
+        LiteralType::Any => "Object",
+    }
+}
+
+/// A new array for a declared one, as long as Java's arrays starting at 0 need to index it by its
+/// upper bound.
+fn allocate(declare: &Declared) -> String {
+    match declare {
+        Declared::Array(_, _, upper, literal_type) => format!(
+            "new {}[{}]",
+            java_type(literal_type),
+            expression(&offset(upper, declare.shift() + 1).expression)
+        ),
+        Declared::Literal(..) => String::new(),
+    }
+}
+
+fn default(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer => "0",
+        LiteralType::Real => "0.0",
+        LiteralType::String => "\"\"",
+        LiteralType::Boolean => "false",
+        LiteralType::Char => "' '",
+        LiteralType::Date | LiteralType::Any => "null",
+    }
+}
+
+/// The type of an array holding values of each of `types`: theirs when they all have the same
+/// one, `Object` otherwise.
+fn element_type<'a>(mut types: impl Iterator<Item = &'a LiteralType>) -> &'static str {
+    let first = types.next().unwrap_or(&LiteralType::Any);
+    if types.all(|literal_type| literal_type == first) {
+        java_type(first)
+    } else {
+        "Object"
+    }
+}
+
+/// `TEST "adds up"` becomes `testAddsUp`.
+fn test_name(name: &str) -> String {
+    name.split(|char: char| !char.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| {
+                    first
+                        .to_uppercase()
+                        .chain(chars.flat_map(char::to_lowercase))
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .fold(String::from("test"), |name, word: String| name + &word)
+}
+
+/// Binding strength in Java, higher binding tighter. Method calls bind tightest of all.
+fn precedence(expression: &Expr) -> u8 {
+    match expression {
+        Expr::Operate(Ops::Or, ..) => 1,
+        Expr::Operate(Ops::And, ..) => 2,
+        Expr::Operate(Ops::Equal, a, _) if by_method(&a.literal_type) => 8,
+        Expr::Operate(Ops::NotEqual, a, _) if by_method(&a.literal_type) => 7,
+        Expr::Operate(Ops::Equal | Ops::NotEqual, ..) => 3,
+        Expr::Operate(op, ..) if COMPARE.contains(op) => 4,
+        Expr::Operate(op, ..) if SUMS.contains(op) => 5,
+        Expr::Operate(..) => 6,
+        Expr::Length(_, lower) if lower.integer() != Some(0) => 5,
+        Expr::ArraysEqual(..) => 8,
+        Expr::Negative(_) | Expr::Not(_) => 7,
+        _ => 8,
+    }
+}
+
+/// Whether values of the type are objects, which `==` and `<` would not compare by value.
+fn by_method(literal_type: &LiteralType) -> bool {
+    matches!(literal_type, LiteralType::String | LiteralType::Date)
+}
+
+fn operator(op: &Ops) -> &'static str {
+    match op {
+        Ops::Plus | Ops::Concatenate => "+",
+        Ops::Minus => "-",
+        Ops::Divide | Ops::Div => "/",
+        Ops::Multiply => "*",
+        Ops::GreaterThan => ">",
+        Ops::LessThan => "<",
+        Ops::GreaterThanEqual => ">=",
+        Ops::LessThanEqual => "<=",
+        Ops::Equal => "==",
+        Ops::NotEqual => "!=",
+        Ops::Mod => "%",
+        Ops::And => "&&",
+        Ops::Or => "||",
+        Ops::Not => "!",
+    }
+}
+
+fn literal(literal: &Literal) -> String {
+    match literal {
+        Literal::String(value) => format!("\"{}\"", value.replace('\\', "\\\\")),
+        Literal::Bool(value) => value.to_string(),
+        literal => literal.source(),
+    }
+}
+
+fn arguments(args: &[Typed]) -> String {
+    args.iter()
+        .map(|arg| expression(&arg.expression))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Prints `inner` under a parent binding with strength `outer`, bracketed where Java would
+/// otherwise group it differently. Comparisons are bracketed inside each other for clarity.
+fn operand(inner: &Expr, outer: u8, right: bool) -> String {
+    if needs_brackets(precedence(inner), outer, right, 3..5) {
+        format!("({})", expression(inner))
+    } else {
+        expression(inner)
+    }
+}
+
+fn expression(expression: &Expr) -> String {
+    match expression {
+        Expr::Value(value) => literal(value),
+        Expr::Variable(identifier) => name(identifier),
+        Expr::Index(identifier, index, shift) => {
+            format!(
+                "{}[{}]",
+                name(identifier),
+                self::expression(&offset(index, *shift).expression)
+            )
+        }
+        Expr::Length(identifier, lower) => match lower.integer() {
+            Some(0) => format!("{}.length", name(identifier)),
+            _ => format!(
+                "{}.length - {}",
+                name(identifier),
+                operand(&lower.expression, 5, true)
+            ),
+        },
+        Expr::ArraysEqual(a, b) => format!(
+            "java.util.Arrays.equals({}, {})",
+            self::expression(&a.expression),
+            self::expression(&b.expression)
+        ),
+        Expr::ArrayCopy(identifier) => format!("{}.clone()", name(identifier)),
+        Expr::BuiltIn(BuiltIn::StrToNum, args) => format!("Integer.parseInt({})", arguments(args)),
+        Expr::BuiltIn(BuiltIn::NumToStr, args) => format!("String.valueOf({})", arguments(args)),
+        Expr::BuiltIn(BuiltIn::RandomBetween, args) => {
+            format!("random.nextInt({})", arguments(args))
+        }
+        Expr::Call(function, args) => format!("{}({})", name(function), arguments(args)),
+        Expr::Negative(inner) => format!("-{}", operand(&inner.expression, 7, false)),
+        Expr::Not(inner) => format!("!{}", operand(&inner.expression, 7, false)),
+        // Strings and dates are compared by value with `equals` and ordered with `compareTo`.
+        Expr::Operate(op @ (Ops::Equal | Ops::NotEqual), a, b) if by_method(&a.literal_type) => {
+            let not = if *op == Ops::NotEqual { "!" } else { "" };
+            format!(
+                "{}{}.equals({})",
+                not,
+                operand(&a.expression, 8, false),
+                self::expression(&b.expression)
+            )
+        }
+        Expr::Operate(op, a, b) if COMPARE.contains(op) && by_method(&a.literal_type) => {
+            format!(
+                "{}.compareTo({}) {} 0",
+                operand(&a.expression, 8, false),
+                self::expression(&b.expression),
+                operator(op)
+            )
+        }
+        // `+` on two chars would add their codes.
+        Expr::Operate(Ops::Concatenate, a, b)
+            if a.literal_type == LiteralType::Char && b.literal_type == LiteralType::Char =>
+        {
+            format!(
+                "String.valueOf({}) + {}",
+                self::expression(&a.expression),
+                operand(&b.expression, 5, true)
+            )
+        }
+        // `/` on two integers would divide them as integers.
+        Expr::Operate(Ops::Divide, a, b)
+            if a.literal_type == LiteralType::Integer && b.literal_type == LiteralType::Integer =>
+        {
+            format!(
+                "(double) {} / {}",
+                operand(&a.expression, 7, false),
+                operand(&b.expression, 6, true)
+            )
+        }
+        // `/` on a real would keep the fraction.
+        Expr::Operate(Ops::Div, a, b)
+            if a.literal_type != LiteralType::Integer || b.literal_type != LiteralType::Integer =>
+        {
+            format!(
+                "(int) ({} / {})",
+                operand(&a.expression, 6, false),
+                operand(&b.expression, 6, true)
+            )
+        }
+        Expr::Input(literal_type) => String::from(match literal_type {
+            LiteralType::Integer => "Integer.parseInt(input.nextLine())",
+            LiteralType::Real => "Double.parseDouble(input.nextLine())",
+            LiteralType::Boolean => "Boolean.parseBoolean(input.nextLine())",
+            LiteralType::Char => "input.nextLine().charAt(0)",
+            LiteralType::Date => "LocalDate.parse(input.nextLine(), DATE)",
+            LiteralType::String | LiteralType::Any => "input.nextLine()",
+        }),
+        Expr::Operate(op, a, b) => {
+            let outer = precedence(expression);
+            format!(
+                "{} {} {}",
+                operand(&a.expression, outer, false),
+                operator(op),
+                operand(&b.expression, outer, true)
+            )
+        }
+    }
+}
+
+struct Java<'a> {
+    writer: Writer,
+    /// The routine being written, if any.
+    current: Option<&'a Routine>,
+}
+
+impl<'a> Java<'a> {
+    fn comment(&mut self, text: &str) {
+        self.writer.line(&format!("//{}", text));
+    }
+
+    /// What a procedure returns: nothing, its one `BYREF` parameter, or several in an array.
+    fn returns(routine: &Routine) -> String {
+        let by_reference = routine.by_reference();
+        match (&routine.returns, &by_reference[..]) {
+            (Some(returns), _) => String::from(java_type(returns)),
+            (None, []) => String::from("void"),
+            (None, [parameter]) => String::from(java_type(&parameter.literal_type)),
+            (None, parameters) => format!(
+                "{}[]",
+                element_type(parameters.iter().map(|parameter| &parameter.literal_type))
+            ),
+        }
+    }
+
+    fn routine(&mut self, routine: &'a Routine, name: &str) {
+        for comment in &routine.comments {
+            self.comment(comment);
+        }
+        let parameters = routine
+            .parameters
+            .iter()
+            .map(|parameter| {
+                format!(
+                    "{}{} {}",
+                    java_type(&parameter.literal_type),
+                    if parameter.array { "[]" } else { "" },
+                    self::name(&parameter.name)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.writer.line(&format!(
+            "static {} {}({}) {{",
+            Java::returns(routine),
+            name,
+            parameters
+        ));
+        self.writer.indent();
+        if routine.returns.is_some()
+            && routine
+                .parameters
+                .iter()
+                .any(|parameter| parameter.passing == Passing::ByReference)
+        {
+            self.comment(" BYREF parameters are passed by value, since a function only hands back its result");
+        }
+        let locals = declares(&routine.body).into_iter().cloned().collect();
+        self.statement(&Lowered::Declare(locals));
+        self.current = Some(routine);
+        self.block(&routine.body);
+        if !routine.by_reference().is_empty() && !routine.ends_in_return() {
+            self.statement(&Lowered::Return(None));
+        }
+        self.current = None;
+        self.writer.dedent();
+        self.writer.line("}");
+        if let Some(comment) = &routine.trailing {
+            self.writer.trailing(&format!("//{}", comment));
+        }
+    }
+
+    /// Writes a block indented inside braces, leaving the closing brace to the caller.
+    fn body(&mut self, block: &Block) {
+        self.writer.indent();
+        self.block(block);
+        self.writer.dedent();
+    }
+
+    fn block(&mut self, block: &Block) {
+        for line in block {
+            match line {
+                Line::Comment(text) => self.comment(text),
+                Line::Blank => self.writer.blank(),
+                // The main program's variables are already fields, and routines declare theirs
+                // up front since pseudocode blocks do not scope them. Arrays whose bounds are
+                // worked out as the program runs are only allocated here.
+                Line::Statement(Lowered::Declare(declares), trailing) => {
+                    for declare in declares {
+                        if let (Declared::Array(identifier, ..), None) = (declare, declare.bounds()) {
+                            let line = format!("{} = {};", name(identifier), allocate(declare));
+                            self.writer.line(&line);
+                        }
+                    }
+                    if let Some(comment) = trailing {
+                        self.comment(comment);
+                    }
+                }
+                Line::Statement(statement, trailing) => {
+                    self.statement(statement);
+                    if let Some(comment) = trailing {
+                        self.writer.trailing(&format!("//{}", comment));
+                    }
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Lowered) {
+        match statement {
+            Lowered::Declare(declares) => {
+                for declare in declares {
+                    self.writer.line(&match declare {
+                        Declared::Literal(identifier, literal_type) => format!(
+                            "{} {} = {};",
+                            java_type(literal_type),
+                            name(identifier),
+                            default(literal_type)
+                        ),
+                        Declared::Array(identifier, .., literal_type) => format!(
+                            "{}[] {} = {};",
+                            java_type(literal_type),
+                            name(identifier),
+                            match declare.bounds() {
+                                Some(_) => allocate(declare),
+                                None => String::from("null"),
+                            }
+                        ),
+                    })
+                }
+            }
+            Lowered::CopyArray(to, from) => self.writer.line(&format!(
+                "System.arraycopy({}, 0, {}, 0, {}.length);",
+                expression(&from.expression),
+                name(to),
+                name(to)
+            )),
+            Lowered::Assign(identifier, value) => self.writer.line(&format!(
+                "{} = {};",
+                name(identifier),
+                expression(&value.expression)
+            )),
+            Lowered::AssignIndex(identifier, index, value, shift) => self.writer.line(&format!(
+                "{}[{}] = {};",
+                name(identifier),
+                expression(&offset(index, *shift).expression),
+                expression(&value.expression)
+            )),
+            Lowered::Output(values, newline) => {
+                let mut parts: Vec<String> = values
+                    .iter()
+                    .map(|value| match (&value.literal_type, values.len()) {
+                        // Java would write `true` and `false`.
+                        (LiteralType::Boolean, 1) => {
+                            format!("{} ? \"TRUE\" : \"FALSE\"", expression(&value.expression))
+                        }
+                        (LiteralType::Boolean, _) => {
+                            format!("({} ? \"TRUE\" : \"FALSE\")", expression(&value.expression))
+                        }
+                        (_, 1) => expression(&value.expression),
+                        _ => operand(&value.expression, 5, true),
+                    })
+                    .collect();
+                // Starting from a string makes `+` join the values rather than add them.
+                if values.len() > 1
+                    && values[..2].iter().all(|value| {
+                        !matches!(
+                            value.literal_type,
+                            LiteralType::String | LiteralType::Boolean
+                        )
+                    })
+                {
+                    parts.insert(0, String::from("\"\""));
+                }
+                let print = if *newline { "println" } else { "print" };
+                self.writer
+                    .line(&format!("System.out.{}({});", print, parts.join(" + ")))
+            }
+            Lowered::If(branches, otherwise) => {
+                for (index, (conditional, block)) in branches.iter().enumerate() {
+                    let keyword = if index == 0 { "if" } else { "} else if" };
+                    self.writer.line(&format!(
+                        "{} ({}) {{",
+                        keyword,
+                        expression(&conditional.expression)
+                    ));
+                    self.body(block);
+                }
+                if let Some(block) = otherwise {
+                    self.writer.line("} else {");
+                    self.body(block);
+                }
+                self.writer.line("}");
+            }
+            Lowered::Call(function, args, assigned) => {
+                let call = format!("{}({})", name(function), arguments(args));
+                match &assigned[..] {
+                    [] => self.writer.line(&format!("{};", call)),
+                    [variable] => self.writer.line(&format!("{} = {};", name(variable), call)),
+                    assigned => {
+                        let types: Vec<&LiteralType> = assigned
+                            .iter()
+                            .map(|variable| {
+                                args.iter()
+                                    .find(|arg| arg.expression == Expr::Variable(variable.clone()))
+                                    .map_or(&LiteralType::Any, |arg| &arg.literal_type)
+                            })
+                            .collect();
+                        let element = element_type(types.iter().copied());
+                        self.writer.line("{");
+                        self.writer.indent();
+                        self.writer
+                            .line(&format!("{}[] results = {};", element, call));
+                        for (index, (variable, literal_type)) in
+                            assigned.iter().zip(types).enumerate()
+                        {
+                            let cast = if element == "Object" {
+                                format!("({}) ", java_type(literal_type))
+                            } else {
+                                String::new()
+                            };
+                            self.writer.line(&format!(
+                                "{} = {}results[{}];",
+                                name(variable),
+                                cast,
+                                index
+                            ));
+                        }
+                        self.writer.dedent();
+                        self.writer.line("}");
+                    }
+                }
+            }
+            Lowered::Return(Some(value)) => self
+                .writer
+                .line(&format!("return {};", expression(&value.expression))),
+            Lowered::Return(None) => {
+                let by_reference = self.current.map(Routine::by_reference).unwrap_or_default();
+                let names: Vec<String> = by_reference
+                    .iter()
+                    .map(|parameter| name(&parameter.name))
+                    .collect();
+                match &names[..] {
+                    [] => self.writer.line("return;"),
+                    [parameter] => self.writer.line(&format!("return {};", parameter)),
+                    parameters => self.writer.line(&format!(
+                        "return new {}[] {{{}}};",
+                        element_type(by_reference.iter().map(|parameter| &parameter.literal_type)),
+                        parameters.join(", ")
+                    )),
+                }
+            }
+            Lowered::Assert(conditional, message) => {
+                let message = message.as_ref().map_or(String::new(), |message| {
+                    format!(" : {}", literal(&Literal::String(message.clone())))
+                });
+                self.writer.line(&format!(
+                    "assert {}{};",
+                    expression(&conditional.expression),
+                    message
+                ))
+            }
+            Lowered::For(identifier, start, end, block) => {
+                let identifier = name(identifier);
+                self.writer.line(&format!(
+                    "for ({} = {}; {} <= {}; {}++) {{",
+                    identifier,
+                    expression(&start.expression),
+                    identifier,
+                    operand(&end.expression, 4, true),
+                    identifier
+                ));
+                self.body(block);
+                self.writer.line("}");
+            }
+            Lowered::While(conditional, block) => {
+                self.writer.line(&format!(
+                    "while ({}) {{",
+                    expression(&conditional.expression)
+                ));
+                self.body(block);
+                self.writer.line("}");
+            }
+            Lowered::Repeat(block, conditional) => {
+                self.writer.line("do {");
+                self.body(block);
+                let condition = match &conditional.expression {
+                    Expr::Not(inner) => expression(&inner.expression),
+                    conditional => format!("!{}", operand(conditional, 7, false)),
+                };
+                self.writer.line(&format!("}} while ({});", condition));
+            }
+        }
+    }
+}
@@ -1,18 +1,17 @@
 use crate::ast::*;
+use crate::dialect::Dialect;
 use chumsky::prelude::*;
-use chumsky::text::{newline, whitespace};
+use chumsky::text::newline;
 
-fn indent<E: chumsky::Error<char>>() -> impl Parser<char, (), Error = E> + Copy + Clone {
+fn indent<E: chumsky::Error<char>>() -> impl Parser<char, (), Error = E> + Copy {
     choice((just(' '), just('\t'))).ignored()
 }
 
-macro_rules! just_to {
-    ($token: ident, $enum: ident, $($string: expr => $name: ident),+) => {(
-        $(just($string).to(Token::$token($enum::$name))),+
-    )}
+pub fn lexer() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char>> {
+    lexer_for(Dialect::Lenient)
 }
 
-pub fn lexer<'a>() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char>> {
+pub fn lexer_for(dialect: Dialect) -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char>> {
     let number = text::digits(10)
         .then(just('.').ignore_then(text::digits(10)).or_not())
         .map(|(whole, integral): (String, Option<String>)| {
@@ -26,53 +25,24 @@ pub fn lexer<'a>() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char
         .boxed()
         .labelled("number");
 
-    let boolean = text::keyword("TRUE")
-        .to(Token::Boolean(true))
-        .or(text::keyword("FALSE").to(Token::Boolean(false)))
-        .labelled("boolean value");
-
     let open_bracket = just('(').to(Token::OpenBracket);
     let close_bracket = just(')').to(Token::CloseBracket);
 
     let open_square = just('[').to(Token::OpenSquare);
     let close_square = just(']').to(Token::CloseSquare);
 
-    let identifiers = text::ident().map(|name: String| {
-        use Token::*;
-        match name.as_str() {
-            "DECLARE" => Declare,
-            "OUTPUT" => Out,
-            "INPUT" => In,
-            "RETURN" => Return,
-            "IF" => If,
-            "ENDIF" => EndIf,
-            "THEN" => Then,
-            "ELSE" => Else,
-            "FOR" => For,
-            "ENDFOR" => EndFor,
-            "TO" => To,
-            "WHILE" => While,
-            "ENDWHILE" => EndWhile,
-            "DO" => Do,
-            "REPEAT" => Repeat,
-            "UNTIL" => Until,
-            "OF" => Of,
-            "NEXT" => Next,
-            "INTEGER" => DataType(DataTypes::Literal(LiteralType::Integer)),
-            "REAL" => DataType(DataTypes::Literal(LiteralType::Real)),
-            "STRING" => DataType(DataTypes::Literal(LiteralType::String)),
-            "BOOLEAN" => DataType(DataTypes::Literal(LiteralType::Boolean)),
-            "ARRAY" => DataType(DataTypes::Array),
-            _ => {
-                let chars: Vec<char> = name.chars().collect();
-                if chars.first().unwrap().is_alphabetic()
-                    && chars.iter().all(|char| char.is_alphanumeric())
-                {
-                    Token::Identifier(name)
-                } else {
-                    Token::BuiltIn(name)
-                }
-            }
+    let keywords = dialect.keywords();
+    let identifiers = text::ident().map(move |name: String| {
+        if let Some(keyword) = keywords.get(name.as_str()) {
+            return keyword.clone();
+        }
+        let chars: Vec<char> = name.chars().collect();
+        if chars.first().unwrap().is_alphabetic()
+            && chars.iter().all(|char| char.is_alphanumeric())
+        {
+            Token::Identifier(name)
+        } else {
+            Token::BuiltIn(name)
         }
     });
 
@@ -82,59 +52,43 @@ pub fn lexer<'a>() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char
         .delimited_by(just('\"'), just('\"'))
         .labelled("string literal");
 
-    let operators = choice::<_, Simple<char>>(just_to! {
-    Operator, Ops,
-        "+" => Plus,
-        "-" => Minus,
-        "/" => Divide,
-        "*" => Multiply,
-        "&" => Concatenate,
-        ">=" => GreaterThanEqual,
-        "<=" => LessThanEqual,
-        "<>" => NotEqual,
-        ">" => GreaterThan,
-        "<" => LessThan,
-        "=" => Equal,
-        "MOD" => Mod,
-        "DIV" => Div,
-        "AND" => And,
-        "OR" => Or,
-        "NOT" => Not
-    })
-    .labelled("operator")
-    .boxed();
+    let char = filter(|char: &char| char != &'\n' && char != &'\'')
+        .map(Token::Char)
+        .delimited_by(just('\''), just('\''))
+        .labelled("character literal");
 
-    let assign = just("<-").or(just("←")).to(Token::Arrow).labelled("arrow");
+    let symbols = dialect
+        .symbols()
+        .into_iter()
+        .map(|(symbol, token)| just(symbol).to(token).boxed())
+        .reduce(|a, b| a.or(b).boxed())
+        .unwrap()
+        .labelled("operator");
 
     let colon = just(':').to(Token::Colon).labelled("colon");
 
     let new_line = newline()
         .repeated()
         .at_least(1)
-        .ignored()
+        .to(Token::NewLine)
         .labelled("new line");
 
-    let comment = just("//").then(take_until(just('\n'))).padded_by(whitespace())
+    let comment = just("//")
+        .ignore_then(filter(|char: &char| char != &'\n' && char != &'\r').repeated())
+        .collect::<String>()
+        .map(|text| Token::Comment(String::from(text.trim_end())))
         .labelled("comment")
         .boxed();
 
     let comma = just(',').to(Token::Comma);
 
     let tokens = choice::<_, Simple<char>>((
-        comment
-            .clone()
-            .repeated()
-            .at_least(1)
-            .ignored()
-            .or(new_line)
-            .to(Token::NewLine)
-            .labelled("comment or new line")
-            .boxed(),
+        comment,
+        new_line,
         number,
         string,
-        boolean,
-        assign,
-        operators,
+        char,
+        symbols,
         identifiers,
         colon,
         open_bracket,
@@ -146,10 +100,18 @@ pub fn lexer<'a>() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char
     .map_with_span(|token, span| (token, span))
     .boxed();
 
-    let ignored = indent().or(comment.ignored()).repeated().ignored();
-
-    (tokens.repeated().at_least(1).padded_by(ignored))
+    tokens
+        .padded_by(indent().repeated())
         .repeated()
         .then_ignore(end())
-        .map(|tokens| tokens.into_iter().flatten().collect())
+}
+
+/// Drops the comments the lexer keeps as trivia, along with the blank lines they leave at the
+/// start of the file, so that the parser only sees significant tokens.
+pub fn strip_trivia(tokens: Vec<Spanned<Token>>) -> Vec<Spanned<Token>> {
+    tokens
+        .into_iter()
+        .filter(|(token, _)| !matches!(token, Token::Comment(_)))
+        .skip_while(|(token, _)| token == &Token::NewLine)
+        .collect()
 }
@@ -0,0 +1,30 @@
+//! The lexer, parser, checker and interpreter, free of the console so that they also build for
+//! `wasm32-unknown-unknown`. The command line tools live in the binary.
+
+pub mod appendix;
+pub mod ast;
+pub mod check;
+pub mod dialect;
+pub mod evaluate;
+pub mod lexer;
+pub mod parser;
+pub mod playground;
+pub mod resolve;
+pub mod snippet;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use chumsky::prelude::*;
+
+pub fn error_message<T: Hash + Eq + Debug>(error: &Simple<T>) -> String {
+    match error.reason() {
+        chumsky::error::SimpleReason::Unexpected => {
+            format!("found {:?} but expected {:?}", error.found(), error.expected().filter_map(|value| value.as_ref()).collect::<Vec<&T>>())
+        },
+        chumsky::error::SimpleReason::Unclosed { span: _, delimiter: _ } => String::from("unclosed"),
+        chumsky::error::SimpleReason::Custom(error) => error.clone(),
+    }
+}
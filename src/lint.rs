@@ -0,0 +1,338 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use crate::ast::*;
+use crate::dialect::Dialect;
+
+/// Things the interpreter accepts but the guide, and so the examiners, would mark down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rule {
+    LowercaseKeyword,
+    NextWithoutIdentifier,
+    InconsistentForTerminator,
+    UsedBeforeDeclare,
+    UnusedDeclaration,
+    IdentifierCase,
+    ReturnOutsideSubroutine,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::LowercaseKeyword,
+        Rule::NextWithoutIdentifier,
+        Rule::InconsistentForTerminator,
+        Rule::UsedBeforeDeclare,
+        Rule::UnusedDeclaration,
+        Rule::IdentifierCase,
+        Rule::ReturnOutsideSubroutine,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::LowercaseKeyword => "L001",
+            Rule::NextWithoutIdentifier => "L002",
+            Rule::InconsistentForTerminator => "L003",
+            Rule::UsedBeforeDeclare => "L004",
+            Rule::UnusedDeclaration => "L005",
+            Rule::IdentifierCase => "L006",
+            Rule::ReturnOutsideSubroutine => "L007",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::LowercaseKeyword => "lowercase-keyword",
+            Rule::NextWithoutIdentifier => "next-without-identifier",
+            Rule::InconsistentForTerminator => "inconsistent-for-terminator",
+            Rule::UsedBeforeDeclare => "used-before-declare",
+            Rule::UnusedDeclaration => "unused-declaration",
+            Rule::IdentifierCase => "identifier-case",
+            Rule::ReturnOutsideSubroutine => "return-outside-subroutine",
+        }
+    }
+
+    fn find(id_or_name: &str) -> Option<Rule> {
+        Rule::ALL
+            .into_iter()
+            .find(|rule| rule.id() == id_or_name || rule.name() == id_or_name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{} {}] {}",
+            self.rule.id(),
+            self.rule.name(),
+            self.message
+        )
+    }
+}
+
+/// Rules turned off for a file by a `// lint-disable: L005, identifier-case` comment.
+fn disabled(tokens: &[Spanned<Token>]) -> HashSet<Rule> {
+    tokens
+        .iter()
+        .filter_map(|(token, _)| match token {
+            Token::Comment(text) => text.trim().strip_prefix("lint-disable:"),
+            _ => None,
+        })
+        .flat_map(|rules| rules.split(',').filter_map(|rule| Rule::find(rule.trim())))
+        .collect()
+}
+
+fn is_camel_or_pascal_case(identifier: &str) -> bool {
+    let mut chars = identifier.chars();
+    chars.next().is_some_and(char::is_alphabetic)
+        && chars.all(char::is_alphanumeric)
+        && (identifier.len() == 1 || identifier.chars().any(char::is_lowercase))
+}
+
+/// Lints the token stream (before `strip_trivia`) and, when the file parsed, its statements.
+pub fn lint(
+    tokens: &[Spanned<Token>],
+    statements: Option<&[Spanned<Statement>]>,
+    dialect: Dialect,
+) -> Vec<Lint> {
+    let mut linter = Linter {
+        dialect,
+        ..Linter::default()
+    };
+    linter.tokens(tokens);
+    if let Some(statements) = statements {
+        linter.block(statements);
+        linter.declarations();
+    }
+
+    let disabled = disabled(tokens);
+    let mut lints: Vec<Lint> = linter
+        .lints
+        .into_iter()
+        .filter(|lint| !disabled.contains(&lint.rule))
+        .collect();
+    lints.sort_by_key(|lint| lint.span.start);
+    lints
+}
+
+#[derive(Default)]
+struct Linter {
+    lints: Vec<Lint>,
+    declarations: Vec<(String, Span)>,
+    references: HashMap<String, Vec<Span>>,
+    in_subroutine: bool,
+    /// Whose keywords identifiers are compared against.
+    dialect: Dialect,
+}
+
+impl Linter {
+    fn push(&mut self, rule: Rule, message: String, span: &Span) {
+        self.lints.push(Lint {
+            rule,
+            message,
+            span: span.clone(),
+        });
+    }
+
+    fn tokens(&mut self, tokens: &[Spanned<Token>]) {
+        let mut terminator = None;
+        let keywords = self.dialect.keywords();
+        for (index, (token, span)) in tokens.iter().enumerate() {
+            match token {
+                Token::Identifier(name) => {
+                    let keyword = keywords
+                        .keys()
+                        .find(|keyword| keyword.to_uppercase() == name.to_uppercase());
+                    if let Some(keyword) = keyword {
+                        let message =
+                            format!("keyword `{}` should be written as `{}`", name, keyword);
+                        self.push(Rule::LowercaseKeyword, message, span);
+                    }
+                }
+                Token::Next | Token::EndFor => {
+                    let keyword = if token == &Token::Next {
+                        "NEXT"
+                    } else {
+                        "ENDFOR"
+                    };
+                    match terminator {
+                        None => terminator = Some(keyword),
+                        Some(first) if first != keyword => {
+                            let message = format!(
+                                "`{}` used here but `{}` used earlier in the file",
+                                keyword, first
+                            );
+                            self.push(Rule::InconsistentForTerminator, message, span);
+                        }
+                        _ => {}
+                    }
+                    let named = matches!(tokens.get(index + 1), Some((Token::Identifier(_), _)));
+                    if token == &Token::Next && !named {
+                        let message =
+                            String::from("`NEXT` should be followed by the loop variable");
+                        self.push(Rule::NextWithoutIdentifier, message, span);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn reference(&mut self, identifier: &str, span: &Span) {
+        self.references
+            .entry(String::from(identifier))
+            .or_default()
+            .push(span.clone());
+    }
+
+    fn expression(&mut self, (expression, span): &Spanned<Expression>) {
+        match expression {
+            Expression::Value(_) => {}
+            Expression::Variable(identifier) => self.reference(identifier, span),
+            Expression::ArrayIndex(identifier, index) => {
+                self.reference(identifier, span);
+                self.expression(index);
+            }
+            Expression::FunctionCall(_, args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::Negative(inner) | Expression::Not(inner) => self.expression(inner),
+            Expression::Operate(_, a, b) => {
+                self.expression(a);
+                self.expression(b);
+            }
+        }
+    }
+
+    fn identifier_case(&mut self, identifier: &str, span: &Span) {
+        if !is_camel_or_pascal_case(identifier) {
+            let message = format!(
+                "identifier `{}` should be in camelCase or PascalCase",
+                identifier
+            );
+            self.push(Rule::IdentifierCase, message, span);
+        }
+    }
+
+    fn block(&mut self, statements: &[Spanned<Statement>]) {
+        for (statement, span) in statements {
+            match statement {
+                Statement::Declare(declares) => {
+                    for declare in declares {
+                        let identifier = declare.identifier();
+                        self.identifier_case(identifier, span);
+                        self.declarations
+                            .push((String::from(identifier), span.clone()));
+                        if let Declare::Array(_, bounds, _) = declare {
+                            self.expression(&bounds.lower);
+                            self.expression(&bounds.upper);
+                        }
+                    }
+                }
+                Statement::Subroutine(subroutine) => {
+                    self.identifier_case(&subroutine.name, span);
+                    for (parameter, span) in &subroutine.parameters {
+                        self.identifier_case(&parameter.name, span);
+                    }
+                    self.in_subroutine = true;
+                    self.block(&subroutine.body);
+                    self.in_subroutine = false;
+                }
+                Statement::Assign(Assign::Literal(identifier, value)) => {
+                    self.reference(identifier, span);
+                    self.expression(value);
+                }
+                Statement::Assign(Assign::Array(identifier, index, value)) => {
+                    self.reference(identifier, span);
+                    self.expression(index);
+                    self.expression(value);
+                }
+                Statement::Out(expressions, _) | Statement::ProcedureCall(_, expressions) => {
+                    for expression in expressions {
+                        self.expression(expression);
+                    }
+                }
+                Statement::In(identifier, index) => {
+                    self.reference(identifier, span);
+                    if let Some(index) = index {
+                        self.expression(index);
+                    }
+                }
+                Statement::If(conditional, if_branch, else_branch) => {
+                    self.expression(conditional);
+                    self.block(if_branch);
+                    if let Some(else_branch) = else_branch {
+                        self.block(else_branch);
+                    }
+                }
+                Statement::Return(value) => {
+                    if let Some(value) = value {
+                        self.expression(value);
+                    }
+                    if !self.in_subroutine {
+                        let message = String::from(
+                            "`RETURN` can only be used inside a function or procedure",
+                        );
+                        self.push(Rule::ReturnOutsideSubroutine, message, span);
+                    }
+                }
+                Statement::For(identifier, start, end, statements) => {
+                    self.reference(identifier, span);
+                    self.expression(start);
+                    self.expression(end);
+                    self.block(statements);
+                }
+                Statement::While(conditional, statements) => {
+                    self.expression(conditional);
+                    self.block(statements);
+                }
+                Statement::Repeat(statements, conditional) => {
+                    self.block(statements);
+                    self.expression(conditional);
+                }
+                Statement::Test(_, statements) => self.block(statements),
+                Statement::Assert(conditional, _) => self.expression(conditional),
+                Statement::Import(_) => {}
+            }
+        }
+    }
+
+    fn declarations(&mut self) {
+        let mut first_declared = HashMap::new();
+        for (identifier, span) in &self.declarations {
+            first_declared
+                .entry(identifier.clone())
+                .or_insert(span.start);
+        }
+
+        let mut lints = vec![];
+        for (identifier, span) in &self.declarations {
+            if !self.references.contains_key(identifier) {
+                let message = format!("`{}` is declared but never used", identifier);
+                lints.push((Rule::UnusedDeclaration, message, span.clone()));
+            }
+        }
+        for (identifier, spans) in &self.references {
+            let Some(declared) = first_declared.get(identifier) else {
+                continue;
+            };
+            for span in spans.iter().filter(|span| span.start < *declared) {
+                let message = format!("`{}` is used before it is declared", identifier);
+                lints.push((Rule::UsedBeforeDeclare, message, span.clone()));
+            }
+        }
+
+        for (rule, message, span) in lints {
+            self.push(rule, message, &span);
+        }
+    }
+}
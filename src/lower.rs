@@ -0,0 +1,1124 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::check::check;
+use crate::dialect::Dialect;
+use crate::resolve::declarations;
+
+/// An appendix function, whichever dialect's name it was called by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuiltIn {
+    StrToNum,
+    NumToStr,
+    RandomBetween,
+}
+
+/// An expression with the type it evaluates to, which statically typed targets need to pick
+/// between operators.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Typed {
+    pub expression: Expr,
+    pub literal_type: LiteralType,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Value(Literal),
+    Variable(String),
+    /// An element of an array, with the amount targets whose arrays start at 0 add to the index.
+    Index(String, Box<Typed>, isize),
+    /// `LEN` of an array, with the array's lower bound as targets whose arrays start at 0 keep it:
+    /// 0 once its elements are shifted. An `ARRAY OF` parameter takes that of the arrays passed to
+    /// it, or 0 when they differ.
+    Length(String, Box<Typed>),
+    /// Whether two arrays hold the same values.
+    ArraysEqual(Box<Typed>, Box<Typed>),
+    /// A copy of a whole array, passed to a `BYVAL` array parameter.
+    ArrayCopy(String),
+    BuiltIn(BuiltIn, Vec<Typed>),
+    Call(String, Vec<Typed>),
+    Negative(Box<Typed>),
+    Not(Box<Typed>),
+    Operate(Ops, Box<Typed>, Box<Typed>),
+    /// A line of input read as a value of the type.
+    Input(LiteralType),
+}
+
+impl Typed {
+    /// The value, when it is a whole number written out.
+    pub fn integer(&self) -> Option<isize> {
+        match &self.expression {
+            Expr::Value(Literal::Integer(value)) => Some(*value),
+            Expr::Negative(inner) => match inner.expression {
+                Expr::Value(Literal::Integer(value)) => value.checked_neg(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// A declaration, with an array's bounds lowered since they may be worked out as it runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Declared {
+    Literal(String, LiteralType),
+    /// An array with its lower and upper bounds.
+    Array(String, Typed, Typed, LiteralType),
+}
+
+impl Declared {
+    pub fn identifier(&self) -> &str {
+        match self {
+            Declared::Literal(identifier, _) | Declared::Array(identifier, ..) => identifier,
+        }
+    }
+
+    /// An array's bounds, when both are whole numbers.
+    pub fn bounds(&self) -> Option<Bounds> {
+        match self {
+            Declared::Array(_, lower, upper, _) => Some(Bounds {
+                lower: lower.integer()?,
+                upper: upper.integer()?,
+            }),
+            Declared::Literal(..) => None,
+        }
+    }
+
+    /// The amount targets whose arrays start at 0 add to an array's indexes.
+    pub fn shift(&self) -> isize {
+        match self {
+            Declared::Array(_, lower, ..) => lower.integer().map_or(0, shift),
+            Declared::Literal(..) => 0,
+        }
+    }
+}
+
+/// The amount targets whose arrays start at 0 add to the indexes of an array with lower bound
+/// `lower`, which is only needed when it is negative.
+pub fn shift(lower: isize) -> isize {
+    -lower.min(0)
+}
+
+/// `value` plus `amount`, worked out already when `value` is a whole number.
+pub fn offset(value: &Typed, amount: isize) -> Typed {
+    let expression = match value.integer() {
+        _ if amount == 0 => return value.clone(),
+        Some(value) => Expr::Value(Literal::Integer(value + amount)),
+        None => Expr::Operate(
+            Ops::Plus,
+            Box::new(value.clone()),
+            Box::new(Typed {
+                expression: Expr::Value(Literal::Integer(amount)),
+                literal_type: LiteralType::Integer,
+            }),
+        ),
+    };
+    Typed {
+        expression,
+        literal_type: LiteralType::Integer,
+    }
+}
+
+pub type Block = Vec<Line>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    Comment(String),
+    /// Where the source had a blank line.
+    Blank,
+    /// A statement and the comment on the same line as its end.
+    Statement(Lowered, Option<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lowered {
+    Declare(Vec<Declared>),
+    Assign(String, Typed),
+    /// Copies every value of an array into the one named, which has the same bounds.
+    CopyArray(String, Typed),
+    /// Assigns an element of an array, with the amount added to the index as in `Expr::Index`.
+    AssignIndex(String, Typed, Typed, isize),
+    /// Writes the values, then ends the line if told to.
+    Output(Vec<Typed>, bool),
+    /// Each condition with the block run when it is the first to hold, then the `ELSE` block.
+    If(Vec<(Typed, Block)>, Option<Block>),
+    /// Counts from the start to the end inclusive.
+    For(String, Typed, Typed, Block),
+    While(Typed, Block),
+    /// Runs the block at least once, stopping when the condition holds.
+    Repeat(Block, Typed),
+    /// A procedure call, with the variables its `BYREF` parameters write back to. The variables are
+    /// only given when every `BYREF` argument is one.
+    Call(String, Vec<Typed>, Vec<String>),
+    /// A procedure's `RETURN` never has a value.
+    Return(Option<Typed>),
+    Assert(Typed, Option<String>),
+}
+
+/// A subroutine, or a `TEST` block named by its description.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Routine {
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub returns: Option<LiteralType>,
+    /// Variables of the main program the routine assigns to.
+    pub globals: Vec<String>,
+    pub body: Block,
+    /// Comments between the routine and whatever came before it.
+    pub comments: Vec<String>,
+    /// The comment on the routine's last line.
+    pub trailing: Option<String>,
+}
+
+impl Routine {
+    /// The `BYREF` parameters of a procedure, which targets without references hand back. Arrays
+    /// are left out, since every target passes them by reference already.
+    pub fn by_reference(&self) -> Vec<&Parameter> {
+        match self.returns {
+            Some(_) => vec![],
+            None => self
+                .parameters
+                .iter()
+                .filter(|parameter| parameter.passing == Passing::ByReference && !parameter.array)
+                .collect(),
+        }
+    }
+
+    pub fn ends_in_return(&self) -> bool {
+        self.body
+            .iter()
+            .rev()
+            .find_map(|line| match line {
+                Line::Statement(statement, _) => Some(matches!(statement, Lowered::Return(_))),
+                _ => None,
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// A program in the shape the transpilers write it: subroutines first, since most targets want
+/// them outside the main program, then the main program, then the `TEST` blocks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    /// Comments above the first statement, which describe the whole file when subroutines are
+    /// moved in front of it.
+    pub header: Vec<String>,
+    /// The main program's declarations, which targets without globals make fields of.
+    pub variables: Vec<Declared>,
+    pub subroutines: Vec<Routine>,
+    pub main: Block,
+    pub tests: Vec<Routine>,
+    /// Comments after everything else.
+    pub footer: Vec<String>,
+    pub uses_arrays: bool,
+    pub uses_assert: bool,
+    /// Whether any variable is a `DATE`, which some targets need a library for.
+    pub uses_dates: bool,
+    pub uses_input: bool,
+    pub uses_random: bool,
+}
+
+/// Whether an operand binding with strength `inner` needs brackets under an operator binding with
+/// `outer`, higher binding tighter. Comparisons, whose strengths are in `comparisons`, are always
+/// bracketed inside each other since some targets chain or reject them.
+pub fn needs_brackets(inner: u8, outer: u8, right: bool, comparisons: Range<u8>) -> bool {
+    inner < outer
+        || (inner == outer && right)
+        || (comparisons.contains(&inner) && comparisons.contains(&outer))
+}
+
+/// A class or module name for a file, `bubbleSort.psps` giving `BubbleSort`.
+pub fn type_name(stem: &str) -> String {
+    let mut chars = stem
+        .chars()
+        .filter(|char| char.is_alphanumeric() || *char == '_');
+    let name: String = chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default();
+    if name.starts_with(|char: char| char.is_alphabetic()) {
+        name
+    } else {
+        format!("Program{}", name)
+    }
+}
+
+fn is_identifier(char: char) -> bool {
+    char.is_alphanumeric() || char == '_'
+}
+
+/// Whether `code` uses the identifier `word`, as opposed to a longer one containing it.
+pub fn mentions(code: &str, word: &str) -> bool {
+    code.match_indices(word).any(|(index, _)| {
+        !code[..index].chars().next_back().is_some_and(is_identifier)
+            && !code[index + word.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_identifier)
+    })
+}
+
+/// Whether `code` calls `name`, as opposed to a longer name ending in it.
+pub fn invokes(code: &str, name: &str) -> bool {
+    code.match_indices(&format!("{}(", name))
+        .any(|(index, _)| !code[..index].chars().next_back().is_some_and(is_identifier))
+}
+
+/// The `BYREF` arguments that are not variables, as the checker reports them. The interpreter
+/// rejects them, and no target could write back to them, so a program with any is not lowered.
+pub fn by_reference_errors(
+    statements: &Vec<Spanned<Statement>>,
+    dialect: Dialect,
+) -> Vec<Spanned<Execution>> {
+    check(statements, dialect)
+        .errors
+        .into_iter()
+        .filter(|(error, _)| matches!(error, Execution::ByReference(_)))
+        .collect()
+}
+
+/// Lowers a parsed program for the transpilers, keeping its comments. `tokens` is the lexer output
+/// before `strip_trivia`.
+pub fn lower(
+    source: &str,
+    tokens: &[Spanned<Token>],
+    statements: &[Spanned<Statement>],
+) -> Program {
+    let chars: Vec<char> = source.chars().collect();
+    let mut line_starts = vec![0];
+    line_starts.extend(
+        chars
+            .iter()
+            .enumerate()
+            .filter(|(_, char)| **char == '\n')
+            .map(|(index, _)| index + 1),
+    );
+    let comments: Vec<Spanned<String>> = tokens
+        .iter()
+        .filter_map(|(token, span)| match token {
+            Token::Comment(text) => Some((text.clone(), span.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut variables: Vec<Declare> = vec![];
+    for (declare, _) in declarations(statements) {
+        if !variables
+            .iter()
+            .any(|variable| variable.identifier() == declare.identifier())
+        {
+            variables.push(declare.clone());
+        }
+    }
+    let mut lowerer = Lowerer {
+        chars,
+        line_starts,
+        emitted: vec![false; comments.len()],
+        comments,
+        subroutines: statements
+            .iter()
+            .filter_map(|(statement, _)| match statement {
+                Statement::Subroutine(subroutine) => {
+                    Some((subroutine.name.clone(), Rc::clone(subroutine)))
+                }
+                _ => None,
+            })
+            .collect(),
+        scope: HashMap::new(),
+        current: None,
+        array_lowers: array_lowers(statements),
+        program: Program::default(),
+    };
+    let main_scope: HashMap<String, Declare> = variables
+        .iter()
+        .map(|declare| (String::from(declare.identifier()), declare.clone()))
+        .collect();
+    lowerer.scope = main_scope.clone();
+    lowerer.program.variables = variables
+        .iter()
+        .map(|declare| lowerer.declared(declare))
+        .collect();
+    lowerer.program(statements, &main_scope);
+    lowerer.program
+}
+
+/// Calls, each by the name called and its arguments.
+type Calls<'a> = Vec<(&'a str, &'a [Spanned<Expression>])>;
+
+/// Every call in `statements`, including those in nested blocks and expressions, but not those in
+/// subroutines or `TEST` blocks.
+fn calls<'a>(statements: &'a [Spanned<Statement>], found: &mut Calls<'a>) {
+    fn expression<'a>(value: &'a Expression, found: &mut Calls<'a>) {
+        match value {
+            Expression::FunctionCall(function, args) => {
+                found.push((function, args));
+                for (arg, _) in args {
+                    expression(arg, found);
+                }
+            }
+            Expression::ArrayIndex(_, inner)
+            | Expression::Negative(inner)
+            | Expression::Not(inner) => expression(&inner.0, found),
+            Expression::Operate(_, a, b) => {
+                expression(&a.0, found);
+                expression(&b.0, found);
+            }
+            Expression::Value(_) | Expression::Variable(_) => {}
+        }
+    }
+    for (statement, _) in statements {
+        match statement {
+            Statement::ProcedureCall(function, args) => {
+                found.push((function, args));
+                for (arg, _) in args {
+                    expression(arg, found);
+                }
+            }
+            Statement::Assign(Assign::Literal(_, value)) => expression(&value.0, found),
+            Statement::Assign(Assign::Array(_, index, value)) => {
+                expression(&index.0, found);
+                expression(&value.0, found);
+            }
+            Statement::Declare(declares) => {
+                for declare in declares {
+                    if let Declare::Array(_, bounds, _) = declare {
+                        expression(&bounds.lower.0, found);
+                        expression(&bounds.upper.0, found);
+                    }
+                }
+            }
+            Statement::Out(values, _) => {
+                for (value, _) in values {
+                    expression(value, found);
+                }
+            }
+            Statement::In(_, Some(index)) => expression(&index.0, found),
+            Statement::If(conditional, if_branch, else_branch) => {
+                expression(&conditional.0, found);
+                calls(if_branch, found);
+                if let Some(else_branch) = else_branch {
+                    calls(else_branch, found);
+                }
+            }
+            Statement::Return(Some(value)) | Statement::Assert(value, _) => {
+                expression(&value.0, found)
+            }
+            Statement::For(_, start, end, statements) => {
+                expression(&start.0, found);
+                expression(&end.0, found);
+                calls(statements, found);
+            }
+            Statement::While(conditional, statements)
+            | Statement::Repeat(statements, conditional) => {
+                expression(&conditional.0, found);
+                calls(statements, found);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The lower bound of the arrays passed to each `ARRAY OF` parameter, by subroutine and parameter,
+/// when every call agrees on it. Targets whose arrays start at 0 need it for `LEN`.
+fn array_lowers(statements: &[Spanned<Statement>]) -> HashMap<(String, String), isize> {
+    let subroutines: HashMap<&str, &Subroutine> = statements
+        .iter()
+        .filter_map(|(statement, _)| match statement {
+            Statement::Subroutine(subroutine) => Some((subroutine.name.as_str(), &**subroutine)),
+            _ => None,
+        })
+        .collect();
+    let globals = declarations(statements);
+    // The scopes calls are made from, each with the declarations it can see.
+    let mut scopes = vec![(statements, globals.clone())];
+    for (statement, _) in statements {
+        match statement {
+            Statement::Subroutine(subroutine) => {
+                let mut visible = globals.clone();
+                visible.extend(declarations(&subroutine.body));
+                // a parameter hides a global, and has no bounds of its own
+                visible.retain(|(declare, _)| {
+                    !subroutine
+                        .parameters
+                        .iter()
+                        .any(|(parameter, _)| parameter.name == declare.identifier())
+                });
+                scopes.push((&subroutine.body, visible));
+            }
+            Statement::Test(_, body) => scopes.push((body, declarations(body))),
+            _ => {}
+        }
+    }
+    let mut lowers: HashMap<(String, String), Option<isize>> = HashMap::new();
+    for (statements, visible) in scopes {
+        let mut found = vec![];
+        calls(statements, &mut found);
+        for (function, args) in found {
+            let Some(subroutine) = subroutines.get(function) else {
+                continue;
+            };
+            for ((parameter, _), (arg, _)) in subroutine.parameters.iter().zip(args) {
+                if !parameter.array {
+                    continue;
+                }
+                let lower = match arg {
+                    Expression::Variable(identifier) => visible
+                        .iter()
+                        .rev()
+                        .find_map(|(declare, _)| match declare {
+                            Declare::Array(name, bounds, _) if name == identifier => {
+                                bounds.lower.0.integer()
+                            }
+                            _ => None,
+                        }),
+                    _ => None,
+                };
+                let key = (function.to_string(), parameter.name.clone());
+                let agreed = match lowers.get(&key) {
+                    Some(known) if *known != lower => None,
+                    _ => lower,
+                };
+                lowers.insert(key, agreed);
+            }
+        }
+    }
+    lowers
+        .into_iter()
+        .filter_map(|(key, lower)| Some((key, lower?)))
+        .collect()
+}
+
+/// Every declaration in `block`, including those in nested blocks, since pseudocode variables
+/// belong to the whole routine rather than the block they are declared in.
+pub fn declares(block: &Block) -> Vec<&Declared> {
+    let mut found = vec![];
+    for line in block {
+        match line {
+            Line::Statement(Lowered::Declare(declared), _) => found.extend(declared),
+            Line::Statement(Lowered::If(branches, otherwise), _) => {
+                for (_, block) in branches {
+                    found.extend(declares(block));
+                }
+                if let Some(block) = otherwise {
+                    found.extend(declares(block));
+                }
+            }
+            Line::Statement(
+                Lowered::For(.., block) | Lowered::While(_, block) | Lowered::Repeat(block, _),
+                _,
+            ) => found.extend(declares(block)),
+            _ => {}
+        }
+    }
+    found
+}
+
+struct Lowerer {
+    chars: Vec<char>,
+    line_starts: Vec<usize>,
+    comments: Vec<Spanned<String>>,
+    emitted: Vec<bool>,
+    subroutines: HashMap<String, Rc<Subroutine>>,
+    /// The variables the routine being lowered can see.
+    scope: HashMap<String, Declare>,
+    /// The subroutine being lowered, if any.
+    current: Option<Rc<Subroutine>>,
+    /// The lower bound of the arrays passed to each `ARRAY OF` parameter, where known.
+    array_lowers: HashMap<(String, String), isize>,
+    program: Program,
+}
+
+impl Lowerer {
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    /// Whether the source line above the one holding `offset` is blank.
+    fn follows_blank_line(&self, offset: usize) -> bool {
+        let line = self.line(offset);
+        line > 0
+            && self.chars[self.line_starts[line - 1]..self.line_starts[line]]
+                .iter()
+                .all(|char| char.is_whitespace())
+    }
+
+    fn blank_line(&self, offset: usize, block: &mut Block) {
+        if !matches!(block.last(), None | Some(Line::Blank)) && self.follows_blank_line(offset) {
+            block.push(Line::Blank);
+        }
+    }
+
+    /// Takes the comments starting in `range` that have not been taken yet.
+    fn comments(&mut self, range: Range<usize>, block: &mut Block) {
+        for index in 0..self.comments.len() {
+            let (text, span) = self.comments[index].clone();
+            if !self.emitted[index] && range.contains(&span.start) {
+                self.emitted[index] = true;
+                self.blank_line(span.start, block);
+                block.push(Line::Comment(text));
+            }
+        }
+    }
+
+    fn comment_texts(&mut self, range: Range<usize>) -> Vec<String> {
+        let mut block = vec![];
+        self.comments(range, &mut block);
+        block
+            .into_iter()
+            .filter_map(|line| match line {
+                Line::Comment(text) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Takes a comment sitting on the same line as the end of a statement.
+    fn trailing_comment(&mut self, end: usize) -> Option<String> {
+        let line = self.line(end.saturating_sub(1));
+        let index = (0..self.comments.len()).find(|index| {
+            let span = &self.comments[*index].1;
+            !self.emitted[*index] && span.start >= end && self.line(span.start) == line
+        })?;
+        self.emitted[index] = true;
+        Some(self.comments[index].0.clone())
+    }
+
+    fn program(&mut self, statements: &[Spanned<Statement>], main_scope: &HashMap<String, Declare>) {
+        let has_subroutines = statements
+            .iter()
+            .any(|(statement, _)| matches!(statement, Statement::Subroutine(_)));
+        let first = match statements.first() {
+            Some((Statement::Subroutine(_), _)) | None => 0,
+            Some((_, span)) if has_subroutines => {
+                self.program.header = self.comment_texts(0..span.start);
+                span.start
+            }
+            Some(_) => 0,
+        };
+
+        // Each statement keeps the comments between it and the one before it in the source.
+        let mut previous = first;
+        for (statement, span) in statements {
+            if span.start > self.chars.len() {
+                // brought in by `IMPORT`, with its comments in the file it came from
+                match statement {
+                    Statement::Subroutine(subroutine) => {
+                        let routine = self.subroutine(subroutine, span, main_scope, vec![]);
+                        self.program.subroutines.push(routine);
+                    }
+                    statement => {
+                        self.scope = main_scope.clone();
+                        let lowered = self.statement(statement, span);
+                        self.program.main.push(Line::Statement(lowered, None));
+                    }
+                }
+                continue;
+            }
+            let leading = previous..span.start;
+            previous = span.end;
+            match statement {
+                Statement::Subroutine(subroutine) => {
+                    let comments = self.comment_texts(leading);
+                    let routine = self.subroutine(subroutine, span, main_scope, comments);
+                    self.program.subroutines.push(routine);
+                }
+                Statement::Test(name, body) => {
+                    let comments = self.comment_texts(leading);
+                    self.scope = HashMap::new();
+                    self.scope.extend(
+                        declarations(body)
+                            .into_iter()
+                            .map(|(declare, _)| (String::from(declare.identifier()), declare.clone())),
+                    );
+                    let body = self.block(body, span.start, span.end);
+                    let trailing = self.trailing_comment(span.end);
+                    self.program.tests.push(Routine {
+                        name: name.clone(),
+                        parameters: vec![],
+                        returns: None,
+                        globals: vec![],
+                        body,
+                        comments,
+                        trailing,
+                    });
+                }
+                statement => {
+                    self.scope = main_scope.clone();
+                    let mut main = std::mem::take(&mut self.program.main);
+                    self.comments(leading, &mut main);
+                    self.blank_line(span.start, &mut main);
+                    let lowered = self.statement(statement, span);
+                    let trailing = self.trailing_comment(span.end);
+                    main.push(Line::Statement(lowered, trailing));
+                    self.program.main = main;
+                }
+            }
+        }
+        self.program.footer = self.comment_texts(0..usize::MAX);
+    }
+
+    fn subroutine(
+        &mut self,
+        subroutine: &Rc<Subroutine>,
+        span: &Span,
+        main_scope: &HashMap<String, Declare>,
+        comments: Vec<String>,
+    ) -> Routine {
+        let locals: Vec<Declare> = declarations(&subroutine.body)
+            .into_iter()
+            .map(|(declare, _)| declare.clone())
+            .collect();
+        let parameters: Vec<Parameter> = subroutine
+            .parameters
+            .iter()
+            .map(|(parameter, _)| parameter.clone())
+            .collect();
+        self.scope = main_scope.clone();
+        for parameter in &parameters {
+            let (name, literal_type) = (parameter.name.clone(), parameter.literal_type.clone());
+            let declare = match parameter.array {
+                true => {
+                    let key = (subroutine.name.clone(), name.clone());
+                    let lower = self.array_lowers.get(&key).copied().unwrap_or(0);
+                    let bounds = ArrayBounds::new(Bounds { lower, upper: lower });
+                    Declare::Array(name, bounds, literal_type)
+                }
+                false => Declare::Literal(name, literal_type),
+            };
+            self.scope.insert(parameter.name.clone(), declare);
+        }
+        if parameters.iter().any(|parameter| parameter.array) {
+            self.program.uses_arrays = true;
+        }
+        self.scope.extend(
+            locals
+                .iter()
+                .map(|declare| (String::from(declare.identifier()), declare.clone())),
+        );
+
+        let mut assigned = HashSet::new();
+        self.assigned(&subroutine.body, &mut assigned);
+        let mut globals: Vec<String> = assigned
+            .into_iter()
+            .filter(|variable| {
+                main_scope.contains_key(variable)
+                    && !locals
+                        .iter()
+                        .any(|declare| declare.identifier() == variable)
+                    && !parameters
+                        .iter()
+                        .any(|parameter| &parameter.name == variable)
+            })
+            .collect();
+        globals.sort();
+
+        self.current = Some(Rc::clone(subroutine));
+        let body = self.block(&subroutine.body, span.start, span.end);
+        self.current = None;
+        let trailing = self.trailing_comment(span.end);
+        Routine {
+            name: subroutine.name.clone(),
+            parameters,
+            returns: subroutine.returns.clone(),
+            globals,
+            body,
+            comments,
+            trailing,
+        }
+    }
+
+    /// Lowers `statements` with the comments between `start` and `end`.
+    fn block(&mut self, statements: &[Spanned<Statement>], start: usize, end: usize) -> Block {
+        let mut block = vec![];
+        let mut cursor = start;
+        for (statement, span) in statements {
+            self.comments(cursor..span.start, &mut block);
+            self.blank_line(span.start, &mut block);
+            let lowered = self.statement(statement, span);
+            let trailing = self.trailing_comment(span.end);
+            block.push(Line::Statement(lowered, trailing));
+            cursor = span.end;
+        }
+        self.comments(cursor..end, &mut block);
+        block
+    }
+
+    /// The variables a call assigns to: its `BYREF` arguments, when each of them is a variable.
+    /// Arrays are changed in place, so are not assigned to.
+    fn assigned_by(&self, function: &str, args: &[Spanned<Expression>]) -> Option<Vec<String>> {
+        let subroutine = self.subroutines.get(function)?;
+        if subroutine.returns.is_some() {
+            return None;
+        }
+        subroutine
+            .parameters
+            .iter()
+            .zip(args)
+            .filter(|((parameter, _), _)| {
+                parameter.passing == Passing::ByReference && !parameter.array
+            })
+            .map(|(_, (arg, _))| match arg {
+                Expression::Variable(identifier) => Some(identifier.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Variables that `statements` assign a whole new value to.
+    fn assigned(&self, statements: &[Spanned<Statement>], names: &mut HashSet<String>) {
+        for (statement, _) in statements {
+            match statement {
+                Statement::Assign(Assign::Literal(identifier, _)) | Statement::In(identifier, None)
+                    if !self.is_array(identifier) =>
+                {
+                    names.insert(identifier.clone());
+                }
+                Statement::ProcedureCall(function, args) => {
+                    names.extend(self.assigned_by(function, args).unwrap_or_default());
+                }
+                Statement::If(_, if_branch, else_branch) => {
+                    self.assigned(if_branch, names);
+                    if let Some(else_branch) = else_branch {
+                        self.assigned(else_branch, names);
+                    }
+                }
+                Statement::For(identifier, .., statements) => {
+                    names.insert(identifier.clone());
+                    self.assigned(statements, names);
+                }
+                Statement::While(_, statements) | Statement::Repeat(statements, _) => {
+                    self.assigned(statements, names)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_array(&self, identifier: &str) -> bool {
+        matches!(self.scope.get(identifier), Some(Declare::Array(..)))
+    }
+
+    /// The amount targets whose arrays start at 0 add to the indexes of the array `identifier`.
+    fn shift(&self, identifier: &str) -> isize {
+        match self.scope.get(identifier) {
+            Some(Declare::Array(_, bounds, _)) => bounds.lower.0.integer().map_or(0, shift),
+            _ => 0,
+        }
+    }
+
+    fn declared(&mut self, declare: &Declare) -> Declared {
+        match declare {
+            Declare::Literal(identifier, literal_type) => {
+                Declared::Literal(identifier.clone(), literal_type.clone())
+            }
+            Declare::Array(identifier, bounds, literal_type) => Declared::Array(
+                identifier.clone(),
+                self.expression(&bounds.lower.0),
+                self.expression(&bounds.upper.0),
+                literal_type.clone(),
+            ),
+        }
+    }
+
+    /// Lowers the arguments to a call, copying arrays passed to a `BYVAL` array parameter.
+    fn arguments(&mut self, function: &str, args: &[Spanned<Expression>]) -> Vec<Typed> {
+        let mut lowered = self.expressions(args);
+        let Some(subroutine) = self.subroutines.get(function) else {
+            return lowered;
+        };
+        for ((parameter, _), arg) in subroutine.parameters.iter().zip(&mut lowered) {
+            if let (true, Passing::ByValue, Expr::Variable(identifier)) =
+                (parameter.array, &parameter.passing, &arg.expression)
+            {
+                arg.expression = Expr::ArrayCopy(identifier.clone());
+            }
+        }
+        lowered
+    }
+
+    fn type_of(&self, identifier: &str) -> LiteralType {
+        match self.scope.get(identifier) {
+            Some(Declare::Literal(_, literal_type) | Declare::Array(_, _, literal_type)) => {
+                literal_type.clone()
+            }
+            None => LiteralType::Any,
+        }
+    }
+
+    fn expressions(&mut self, expressions: &[Spanned<Expression>]) -> Vec<Typed> {
+        expressions
+            .iter()
+            .map(|(expression, _)| self.expression(expression))
+            .collect()
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Typed {
+        let (expression, literal_type) = match expression {
+            Expression::Value(value) => (Expr::Value(value.clone()), LiteralType::from(value)),
+            Expression::Variable(identifier) => {
+                (Expr::Variable(identifier.clone()), self.type_of(identifier))
+            }
+            Expression::ArrayIndex(identifier, index) => (
+                Expr::Index(
+                    identifier.clone(),
+                    Box::new(self.expression(&index.0)),
+                    self.shift(identifier),
+                ),
+                self.type_of(identifier),
+            ),
+            Expression::FunctionCall(function, args) => {
+                let canonical = Dialect::ALL
+                    .iter()
+                    .flat_map(|dialect| dialect.library())
+                    .find(|(alias, _)| alias == function)
+                    .map_or(function.as_str(), |(_, canonical)| canonical);
+                let built_in = match canonical {
+                    "STR_TO_NUM" => Some((BuiltIn::StrToNum, LiteralType::Integer)),
+                    "NUM_TO_STR" => Some((BuiltIn::NumToStr, LiteralType::String)),
+                    "RANDOMBETWEEN" => {
+                        self.program.uses_random = true;
+                        Some((BuiltIn::RandomBetween, LiteralType::Integer))
+                    }
+                    _ => None,
+                };
+                match (canonical, &args[..], built_in) {
+                    ("LEN", [(Expression::Variable(identifier), _)], _) => {
+                        let lower = match self.scope.get(identifier).cloned() {
+                            Some(Declare::Array(_, bounds, _)) if self.shift(identifier) == 0 => {
+                                self.expression(&bounds.lower.0)
+                            }
+                            _ => self.expression(&Expression::Value(Literal::Integer(0))),
+                        };
+                        (
+                            Expr::Length(identifier.clone(), Box::new(lower)),
+                            LiteralType::Integer,
+                        )
+                    }
+                    (_, _, Some((built_in, literal_type))) => (
+                        Expr::BuiltIn(built_in, self.expressions(args)),
+                        literal_type,
+                    ),
+                    _ => (
+                        Expr::Call(function.clone(), self.arguments(function, args)),
+                        self.subroutines
+                            .get(function)
+                            .and_then(|subroutine| subroutine.returns.clone())
+                            .unwrap_or(LiteralType::Any),
+                    ),
+                }
+            }
+            Expression::Negative(inner) => {
+                let inner = self.expression(&inner.0);
+                let literal_type = inner.literal_type.clone();
+                (Expr::Negative(Box::new(inner)), literal_type)
+            }
+            Expression::Not(inner) => (
+                Expr::Not(Box::new(self.expression(&inner.0))),
+                LiteralType::Boolean,
+            ),
+            Expression::Operate(op @ (Ops::Equal | Ops::NotEqual), a, b)
+                if matches!(&a.0, Expression::Variable(identifier) if self.is_array(identifier)) =>
+            {
+                let (a, b) = (self.expression(&a.0), self.expression(&b.0));
+                let equal = Typed {
+                    expression: Expr::ArraysEqual(Box::new(a), Box::new(b)),
+                    literal_type: LiteralType::Boolean,
+                };
+                match op {
+                    Ops::Equal => (equal.expression, LiteralType::Boolean),
+                    _ => (Expr::Not(Box::new(equal)), LiteralType::Boolean),
+                }
+            }
+            Expression::Operate(op, a, b) => {
+                let (a, b) = (self.expression(&a.0), self.expression(&b.0));
+                let literal_type = match op {
+                    Ops::And | Ops::Or => LiteralType::Boolean,
+                    op if COMPARE.contains(op) => LiteralType::Boolean,
+                    Ops::Concatenate => LiteralType::String,
+                    Ops::Divide => LiteralType::Real,
+                    Ops::Mod | Ops::Div => LiteralType::Integer,
+                    _ => match (&a.literal_type, &b.literal_type) {
+                        (LiteralType::Integer, LiteralType::Integer) => LiteralType::Integer,
+                        (LiteralType::Real, _) | (_, LiteralType::Real) => LiteralType::Real,
+                        (LiteralType::String, _) | (_, LiteralType::String) => LiteralType::String,
+                        _ => LiteralType::Any,
+                    },
+                };
+                (
+                    Expr::Operate(op.clone(), Box::new(a), Box::new(b)),
+                    literal_type,
+                )
+            }
+        };
+        Typed {
+            expression,
+            literal_type,
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, span: &Span) -> Lowered {
+        match statement {
+            Statement::Declare(declares) => {
+                for declare in declares {
+                    if matches!(declare, Declare::Array(..)) {
+                        self.program.uses_arrays = true;
+                    }
+                    if let Declare::Literal(_, LiteralType::Date) | Declare::Array(_, _, LiteralType::Date) = declare {
+                        self.program.uses_dates = true;
+                    }
+                }
+                Lowered::Declare(declares.iter().map(|declare| self.declared(declare)).collect())
+            }
+            Statement::Assign(Assign::Literal(identifier, (value, _))) if self.is_array(identifier) => {
+                Lowered::CopyArray(identifier.clone(), self.expression(value))
+            }
+            Statement::Assign(Assign::Literal(identifier, (value, _))) => {
+                Lowered::Assign(identifier.clone(), self.expression(value))
+            }
+            Statement::Assign(Assign::Array(identifier, (index, _), (value, _))) => {
+                Lowered::AssignIndex(
+                    identifier.clone(),
+                    self.expression(index),
+                    self.expression(value),
+                    self.shift(identifier),
+                )
+            }
+            Statement::Out(values, newline) => Lowered::Output(self.expressions(values), *newline),
+            Statement::In(identifier, index) => {
+                self.program.uses_input = true;
+                let input = Typed {
+                    expression: Expr::Input(self.type_of(identifier)),
+                    literal_type: self.type_of(identifier),
+                };
+                match index {
+                    Some((index, _)) => {
+                        let shift = self.shift(identifier);
+                        Lowered::AssignIndex(identifier.clone(), self.expression(index), input, shift)
+                    }
+                    None => Lowered::Assign(identifier.clone(), input),
+                }
+            }
+            Statement::If(conditional, if_branch, else_branch) => {
+                let (mut conditional, mut if_branch, mut else_branch, mut span) =
+                    (conditional, if_branch, else_branch, span);
+                let mut branches = vec![];
+                loop {
+                    let else_start = else_branch
+                        .as_ref()
+                        .and_then(|statements| statements.first())
+                        .map_or(span.end, |(_, span)| span.start);
+                    let lowered = self.expression(&conditional.0);
+                    branches.push((lowered, self.block(if_branch, span.start, else_start)));
+                    match else_branch.as_deref() {
+                        // An `IF` alone in an `ELSE` continues the chain.
+                        Some([(Statement::If(inner, inner_if, inner_else), inner_span)]) => {
+                            (conditional, if_branch, else_branch, span) =
+                                (inner, inner_if, inner_else, inner_span);
+                        }
+                        Some(statements) => {
+                            let otherwise = self.block(statements, else_start, span.end);
+                            return Lowered::If(branches, Some(otherwise));
+                        }
+                        None => return Lowered::If(branches, None),
+                    }
+                }
+            }
+            Statement::Subroutine(_) | Statement::Test(..) => {
+                unreachable!("subroutines and tests are only lowered at the top level")
+            }
+            Statement::Import(_) => {
+                unreachable!("imports are replaced by what they import when a file is loaded")
+            }
+            Statement::ProcedureCall(function, args) => {
+                let assigned = self.assigned_by(function, args).unwrap_or_default();
+                Lowered::Call(function.clone(), self.arguments(function, args), assigned)
+            }
+            Statement::Return(value) => {
+                let is_function = self
+                    .current
+                    .as_ref()
+                    .is_some_and(|subroutine| subroutine.returns.is_some());
+                Lowered::Return(
+                    value
+                        .as_ref()
+                        .filter(|_| is_function)
+                        .map(|(value, _)| self.expression(value)),
+                )
+            }
+            Statement::Assert((conditional, _), message) => {
+                self.program.uses_assert = true;
+                Lowered::Assert(self.expression(conditional), message.clone())
+            }
+            Statement::For(identifier, (start, _), (end, _), statements) => Lowered::For(
+                identifier.clone(),
+                self.expression(start),
+                self.expression(end),
+                self.block(statements, span.start, span.end),
+            ),
+            Statement::While((conditional, _), statements) => Lowered::While(
+                self.expression(conditional),
+                self.block(statements, span.start, span.end),
+            ),
+            Statement::Repeat(statements, (conditional, _)) => {
+                let block = self.block(statements, span.start, span.end);
+                Lowered::Repeat(block, self.expression(conditional))
+            }
+        }
+    }
+}
+
+/// Builds a target's source a line at a time.
+pub struct Writer {
+    pub output: String,
+    unit: &'static str,
+    depth: usize,
+    /// Whether nothing has been written since the last indent.
+    at_block_start: bool,
+}
+
+impl Writer {
+    /// Indents each level by `unit`.
+    pub fn new(unit: &'static str) -> Writer {
+        Writer {
+            output: String::new(),
+            unit,
+            depth: 0,
+            at_block_start: true,
+        }
+    }
+
+    pub fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.output.push_str(self.unit);
+        }
+        self.output.push_str(text);
+        self.output.push('\n');
+        self.at_block_start = false;
+    }
+
+    pub fn indent(&mut self) {
+        self.depth += 1;
+        self.at_block_start = true;
+    }
+
+    pub fn dedent(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Leaves a blank line, unless it would start the output or a block or follow another.
+    pub fn blank(&mut self) {
+        if !self.at_block_start && !self.output.ends_with("\n\n") {
+            self.output.push('\n');
+        }
+    }
+
+    /// Appends `comment` to the last line, two spaces after its code.
+    pub fn trailing(&mut self, comment: &str) {
+        self.output.pop();
+        self.output.push_str("  ");
+        self.output.push_str(comment);
+        self.output.push('\n');
+    }
+}
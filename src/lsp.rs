@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use chumsky::{prelude::*, Stream};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _,
+};
+use lsp_types::*;
+
+use crate::ast::*;
+use crate::check::{check, Declaration};
+use crate::dialect::Dialect;
+use crate::error_message;
+use crate::lexer::{lexer_for, strip_trivia};
+use crate::parser::parser_for;
+
+/// Everything the server knows about one open document, recomputed on every change.
+pub struct Analysis {
+    source: String,
+    dialect: Dialect,
+    diagnostics: Vec<(Span, String)>,
+    declarations: Vec<Declaration>,
+    statements: Vec<Spanned<Statement>>,
+    /// Whether the document parsed, so the declarations and statements are its own.
+    parsed: bool,
+}
+
+fn to_diagnostic<T: Hash + Eq + Debug>(error: Simple<T>) -> (Span, String) {
+    (error.span(), error_message(&error))
+}
+
+pub fn analyse(source: String, dialect: Dialect) -> Analysis {
+    let mut analysis = Analysis {
+        source,
+        dialect,
+        diagnostics: vec![],
+        declarations: vec![],
+        statements: vec![],
+        parsed: false,
+    };
+
+    let lexed = match lexer_for(dialect).parse(analysis.source.as_str()) {
+        Ok(lexed) => strip_trivia(lexed),
+        Err(errors) => {
+            analysis.diagnostics = errors.into_iter().map(to_diagnostic).collect();
+            return analysis;
+        }
+    };
+
+    let end = analysis.source.chars().count();
+    match parser_for(dialect).parse(Stream::from_iter(end..end + 1, lexed.into_iter())) {
+        Ok(statements) => {
+            analysis.statements = statements;
+            analysis.parsed = true;
+        }
+        Err(errors) => {
+            analysis.diagnostics = errors.into_iter().map(to_diagnostic).collect();
+            return analysis;
+        }
+    }
+
+    let checked = check(&analysis.statements, dialect);
+    analysis.declarations = checked.declarations;
+    analysis.diagnostics = checked
+        .errors
+        .into_iter()
+        .map(|(error, span)| (span, error.to_string()))
+        .collect();
+    analysis
+}
+
+/// Converts a char offset, which is what chumsky spans count in, to an LSP (UTF-16) position.
+pub fn position(source: &str, offset: usize) -> Position {
+    let mut position = Position::new(0, 0);
+    for char in source.chars().take(offset) {
+        if char == '\n' {
+            position.line += 1;
+            position.character = 0;
+        } else {
+            position.character += char.len_utf16() as u32;
+        }
+    }
+    position
+}
+
+/// Converts an LSP position back to a char offset, clamping one past the end of a line or the
+/// source to that end.
+pub fn offset(source: &str, position: Position) -> usize {
+    let mut current = Position::new(0, 0);
+    for (offset, char) in source.chars().enumerate() {
+        if current.line == position.line && current.character >= position.character
+            || current.line > position.line
+        {
+            return offset;
+        }
+        if char == '\n' {
+            if current.line == position.line {
+                return offset;
+            }
+            current.line += 1;
+            current.character = 0;
+        } else {
+            current.character += char.len_utf16() as u32;
+        }
+    }
+    source.chars().count()
+}
+
+fn range(source: &str, span: &Span) -> Range {
+    Range::new(position(source, span.start), position(source, span.end))
+}
+
+/// The identifier or keyword that the char at `offset`, or just before it, is part of.
+pub fn word_at(source: &str, offset: usize) -> Option<String> {
+    let chars: Vec<char> = source.chars().collect();
+    let is_word = |char: &char| char.is_alphanumeric() || *char == '_';
+    let start = chars[..offset.min(chars.len())]
+        .iter()
+        .rposition(|char| !is_word(char))
+        .map_or(0, |index| index + 1);
+    let word: String = chars[start..]
+        .iter()
+        .take_while(|char| is_word(char))
+        .collect();
+    (!word.is_empty()).then_some(word)
+}
+
+impl Analysis {
+    /// Keeps the declarations and statements of `previous` when this document does not parse, so
+    /// completion, hover and the outline still work while a line is half typed. Their spans may
+    /// be a little out until it parses again.
+    pub fn keeping(mut self, previous: Option<Analysis>) -> Analysis {
+        if let (false, Some(previous)) = (self.parsed, previous) {
+            self.declarations = previous.declarations;
+            self.statements = previous.statements;
+        }
+        self
+    }
+
+    /// The declarations visible at `offset`: the globals, and the parameters and locals of the
+    /// subroutine `offset` is in.
+    fn visible(&self, offset: usize) -> impl Iterator<Item = &Declaration> {
+        self.declarations.iter().filter(move |declaration| {
+            declaration
+                .subroutine
+                .as_ref()
+                .is_none_or(|subroutine| subroutine.contains(&offset))
+        })
+    }
+
+    /// The declaration `name` refers to at `offset`, where a subroutine's own variables hide
+    /// globals of the same name.
+    fn declaration(&self, name: &str, offset: usize) -> Option<&Declaration> {
+        let mut matching = self
+            .visible(offset)
+            .filter(|declaration| declaration.declare.identifier() == name);
+        let first = matching.next()?;
+        Some(matching.find(|declaration| declaration.subroutine.is_some()).unwrap_or(first))
+    }
+
+    /// The names of the functions the dialect provides, `LEN` included.
+    fn functions(&self) -> impl Iterator<Item = &'static str> {
+        self.dialect
+            .library()
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(["LEN"])
+    }
+
+    fn publish(&self, uri: Url) -> PublishDiagnosticsParams {
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .map(|(span, message)| Diagnostic {
+                range: range(&self.source, span),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some(String::from("psps")),
+                message: message.clone(),
+                ..Diagnostic::default()
+            })
+            .collect();
+        PublishDiagnosticsParams::new(uri, diagnostics, None)
+    }
+
+    pub fn hover(&self, position: Position) -> Option<Hover> {
+        let offset = offset(&self.source, position);
+        let word = word_at(&self.source, offset)?;
+        let contents = match self.declaration(&word, offset) {
+            Some(declaration) => declaration.declare.to_string(),
+            None => self.dialect.signature(&word)?.written_as(&word),
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```psps\n{}\n```", contents),
+            }),
+            range: None,
+        })
+    }
+
+    pub fn definition(&self, uri: Url, position: Position) -> Option<Location> {
+        let offset = offset(&self.source, position);
+        let declaration = self.declaration(&word_at(&self.source, offset)?, offset)?;
+        Some(Location::new(uri, range(&self.source, &declaration.span)))
+    }
+
+    /// The dialect's keywords and functions, and the variables visible at `position`.
+    pub fn completion(&self, position: Position) -> Vec<CompletionItem> {
+        let mut keywords: Vec<&str> = self.dialect.keywords().into_keys().collect();
+        keywords.sort_unstable();
+        let keywords = keywords.into_iter().map(|keyword| CompletionItem {
+            label: String::from(keyword),
+            kind: Some(CompletionItemKind::KEYWORD),
+            ..CompletionItem::default()
+        });
+        let built_ins = self.functions().map(|name| CompletionItem {
+            label: String::from(name),
+            kind: Some(CompletionItemKind::FUNCTION),
+            detail: self.dialect.signature(name).map(|signature| signature.written_as(name)),
+            ..CompletionItem::default()
+        });
+        let offset = offset(&self.source, position);
+        let mut declared: HashMap<&str, &Declaration> = HashMap::new();
+        for declaration in self.visible(offset) {
+            let name = declaration.declare.identifier();
+            if let Some(declaration) = self.declaration(name, offset) {
+                declared.insert(name, declaration);
+            }
+        }
+        let variables = declared.into_values().map(|declaration| CompletionItem {
+            label: String::from(declaration.declare.identifier()),
+            kind: Some(CompletionItemKind::VARIABLE),
+            detail: Some(declaration.declare.to_string()),
+            ..CompletionItem::default()
+        });
+        keywords.chain(built_ins).chain(variables).collect()
+    }
+
+    #[allow(deprecated)]
+    pub fn outline(&self) -> Vec<DocumentSymbol> {
+        self.statements
+            .iter()
+            .flat_map(|(statement, span)| match statement {
+                Statement::Declare(declares) => declares
+                    .iter()
+                    .map(|declare| DocumentSymbol {
+                        name: String::from(declare.identifier()),
+                        detail: Some(declare.to_string()),
+                        kind: match declare {
+                            Declare::Literal(..) => SymbolKind::VARIABLE,
+                            Declare::Array(..) => SymbolKind::ARRAY,
+                        },
+                        tags: None,
+                        deprecated: None,
+                        range: range(&self.source, span),
+                        selection_range: range(&self.source, span),
+                        children: None,
+                    })
+                    .collect(),
+                Statement::Subroutine(subroutine) => vec![DocumentSymbol {
+                    name: subroutine.name.clone(),
+                    detail: Some(subroutine.to_string()),
+                    kind: SymbolKind::FUNCTION,
+                    tags: None,
+                    deprecated: None,
+                    range: range(&self.source, span),
+                    selection_range: range(&self.source, span),
+                    children: Some(
+                        subroutine
+                            .parameters
+                            .iter()
+                            .map(|(parameter, span)| DocumentSymbol {
+                                name: parameter.name.clone(),
+                                detail: Some(parameter.data_type()),
+                                kind: SymbolKind::VARIABLE,
+                                tags: None,
+                                deprecated: None,
+                                range: range(&self.source, span),
+                                selection_range: range(&self.source, span),
+                                children: None,
+                            })
+                            .collect(),
+                    ),
+                }],
+                _ => vec![],
+            })
+            .collect()
+    }
+}
+
+/// Answers `request` with what `handler` makes of its parameters, or an `InvalidParams` error if
+/// they are not what the method takes.
+fn answer<R: lsp_types::request::Request>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    match serde_json::from_value(request.params) {
+        Ok(params) => Response::new_ok(request.id, handler(params)),
+        Err(error) => Response::new_err(
+            request.id,
+            ErrorCode::InvalidParams as i32,
+            error.to_string(),
+        ),
+    }
+}
+
+pub fn handle_request(documents: &HashMap<Url, Analysis>, request: Request) -> Response {
+    match request.method.as_str() {
+        HoverRequest::METHOD => answer::<HoverRequest>(request, |params| {
+            let document = params.text_document_position_params;
+            documents
+                .get(&document.text_document.uri)
+                .and_then(|analysis| analysis.hover(document.position))
+        }),
+        GotoDefinition::METHOD => answer::<GotoDefinition>(request, |params| {
+            let document = params.text_document_position_params;
+            documents
+                .get(&document.text_document.uri)
+                .and_then(|analysis| {
+                    analysis.definition(document.text_document.uri.clone(), document.position)
+                })
+                .map(GotoDefinitionResponse::Scalar)
+        }),
+        Completion::METHOD => answer::<Completion>(request, |params| {
+            let document = params.text_document_position;
+            documents
+                .get(&document.text_document.uri)
+                .map(|analysis| CompletionResponse::Array(analysis.completion(document.position)))
+        }),
+        DocumentSymbolRequest::METHOD => answer::<DocumentSymbolRequest>(request, |params| {
+            documents
+                .get(&params.text_document.uri)
+                .map(|analysis| DocumentSymbolResponse::Nested(analysis.outline()))
+        }),
+        method => Response::new_err(
+            request.id,
+            ErrorCode::MethodNotFound as i32,
+            format!("unknown method {}", method),
+        ),
+    }
+}
+
+fn update(
+    connection: &Connection,
+    documents: &mut HashMap<Url, Analysis>,
+    dialect: Dialect,
+    uri: Url,
+    source: String,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let analysis = analyse(source, dialect).keeping(documents.remove(&uri));
+    let params = analysis.publish(uri.clone());
+    documents.insert(uri, analysis);
+    connection
+        .sender
+        .send(Message::Notification(Notification::new(
+            PublishDiagnostics::METHOD.to_owned(),
+            params,
+        )))?;
+    Ok(())
+}
+
+/// Keeps `documents` in step with the client. Notifications get no reply, so one whose
+/// parameters can not be read is ignored.
+fn handle_notification(
+    connection: &Connection,
+    documents: &mut HashMap<Url, Analysis>,
+    dialect: Dialect,
+    notification: Notification,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let Ok(params) = serde_json::from_value::<DidOpenTextDocumentParams>(notification.params) else {
+                return Ok(());
+            };
+            update(
+                connection,
+                documents,
+                dialect,
+                params.text_document.uri,
+                params.text_document.text,
+            )
+        }
+        DidChangeTextDocument::METHOD => {
+            let Ok(params) = serde_json::from_value::<DidChangeTextDocumentParams>(notification.params) else {
+                return Ok(());
+            };
+            match params.content_changes.into_iter().last() {
+                Some(change) => update(
+                    connection,
+                    documents,
+                    dialect,
+                    params.text_document.uri,
+                    change.text,
+                ),
+                None => Ok(()),
+            }
+        }
+        DidCloseTextDocument::METHOD => {
+            if let Ok(params) = serde_json::from_value::<DidCloseTextDocumentParams>(notification.params) {
+                documents.remove(&params.text_document.uri);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Serves the language server protocol over stdin and stdout until the client shuts it down,
+/// reading documents as `dialect`.
+pub fn run(dialect: Dialect) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        definition_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut documents = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                handle_notification(&connection, &mut documents, dialect, notification)?
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
@@ -1,22 +1,38 @@
-mod appendix;
-mod ast;
-mod evaluate;
-mod lexer;
-mod parser;
+mod c;
+mod flowchart;
+mod format;
+mod golden;
+mod grade;
+mod import;
+mod java;
+mod lint;
+mod lower;
+mod lsp;
+mod python;
+mod structure;
+#[cfg(test)]
 mod test;
+mod vb;
 
-use std::io::{stdin, Read};
+use pseudo_pseudo_code::{ast, check, dialect, error_message, evaluate, lexer, parser, resolve, snippet};
+use std::collections::BTreeMap;
+use std::io::{stdin, Write};
+use std::process::{Command, Stdio};
 use std::hash::Hash;
 use std::fmt::Debug;
-use appendix::*;
+use std::path::Path;
 use evaluate::*;
 use lexer::*;
 use parser::*;
 
 use ariadne::*;
 use chumsky::{prelude::*, Stream};
+use rand::Rng;
 
-use crate::ast::Execution;
+use crate::ast::{Execution, Literal, Spanned, Statement, Token};
+use crate::dialect::Dialect;
+use crate::import::Sources;
+use crate::snippet::Initial;
 
 fn print_error<Error: std::fmt::Debug>(error: Error) -> String {
     format!("error: {:?}", error)
@@ -41,67 +57,502 @@ fn validate_file_arg(arg: Option<&String>) -> Result<(String, String), String> {
     ))
 }
 
-fn display_error<T: Hash + Eq + Debug>(errors: Vec<Simple<T>>, file_name: &String, source: &String) {
-    for error in errors {
-        let span = error.span();
-        let report = Report::build(ReportKind::Error, file_name.clone(), 10)
-            .with_label(Label::new((file_name.clone(), span)))
-            .with_message(format!("{:?}", error.reason()));
-        
-        let report = match error.reason() {
-            chumsky::error::SimpleReason::Unexpected => {
-                report.with_message(format!("found {:?} but expected {:?}", error.found(), error.expected().filter_map(|value| value.as_ref()).collect::<Vec<&T>>()))
-            },
-            chumsky::error::SimpleReason::Unclosed { span: _, delimiter: _ } => report.with_message("unclosed"),
-            chumsky::error::SimpleReason::Custom(error) => report.with_message(error),
-        };
+/// The value given to a `--name=value` option, if there is one.
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .find_map(|arg| arg.strip_prefix("--")?.strip_prefix(name)?.strip_prefix('='))
+}
 
-        report.finish()
-            .print(sources(vec![(file_name.clone(), source.as_str())]))
+/// Prints each error against the file its span falls in.
+fn display_error<T: Hash + Eq + Debug>(errors: Vec<Simple<T>>, sources: &Sources) {
+    for error in errors {
+        let (file_name, _, span) = sources.locate(&error.span());
+        Report::build(ReportKind::Error, file_name.to_owned(), span.start)
+            .with_label(Label::new((file_name.to_owned(), span)))
+            .with_message(error_message(&error))
+            .finish()
+            .print(sources.cache())
             .unwrap();
     }
 }
 
-pub fn parse_and_run(source: String, file_name: String) -> Result<(), ()> {
+type Parsed = (Vec<Spanned<Token>>, Vec<Spanned<Statement>>);
+
+fn parse_tokens(tokens: Vec<Spanned<Token>>, source: &str, file_name: &str, dialect: Dialect) -> Result<Vec<Spanned<Statement>>, ()> {
+    let end = source.chars().count();
+    parser_for(dialect)
+        .parse(Stream::from_iter(
+            end..end + 1,
+            strip_trivia(tokens).into_iter(),
+        ))
+        .map_err(|errors| display_error(errors, &Sources::single(file_name, source)))
+}
+
+/// Lexes and parses `source`, printing any errors. The tokens still include the comments kept as
+/// trivia, for tools that need to reproduce them.
+fn lex_and_parse(source: &str, file_name: &str, dialect: Dialect) -> Result<Parsed, ()> {
+    let tokens = lexer_for(dialect).parse(source)
+    .map_err(|errors| display_error(errors, &Sources::single(file_name, source)))?;
+
+    let parsed = parse_tokens(tokens.clone(), source, file_name, dialect)?;
+
+    Ok((tokens, parsed))
+}
+
+/// How a program should be run, as chosen on the command line.
+#[derive(Clone, Debug, Default)]
+struct Settings {
+    dialect: Dialect,
+    limits: Limits,
+    /// Seeds the random built-ins, picked at random when not given.
+    seed: Option<u64>,
+    invalid_input: InputPolicy,
+}
+
+impl Settings {
+    fn state(&self, seed: u64, io: Io) -> State {
+        State {
+            invalid_input: self.invalid_input,
+            ..State::new(self.dialect, self.limits.clone(), seed, io)
+        }
+    }
+}
+
+/// Reads `--dialect=NAME`, `--max-steps=N`, `--timeout=SECONDS`, `--max-depth=N`,
+/// `--max-array=N`, `--seed=N` and `--invalid-input=error|reprompt`, keeping the defaults for any
+/// not given.
+fn settings(args: &[String]) -> Result<Settings, String> {
+    fn number<T: std::str::FromStr>(args: &[String], name: &str) -> Result<Option<T>, String> {
+        option(args, name)
+            .map(|value| value.parse().map_err(|_| print_error(format!("--{} expects a number, found {}", name, value))))
+            .transpose()
+    }
+
+    let mut settings = Settings::default();
+    if let Some(name) = option(args, "dialect") {
+        settings.dialect = Dialect::find(name).ok_or_else(|| {
+            let names: Vec<&str> = Dialect::ALL.iter().map(Dialect::name).collect();
+            print_error(format!("unknown dialect {}, expected one of {}", name, names.join(", ")))
+        })?;
+    }
+    if let Some(steps) = number(args, "max-steps")? {
+        settings.limits.steps = Some(steps);
+    }
+    if let Some(seconds) = number(args, "timeout")? {
+        settings.limits.time = Some(time_limit(seconds).map_err(|error| print_error(format!("--timeout is invalid, {}", error)))?);
+    }
+    if let Some(depth) = number(args, "max-depth")? {
+        settings.limits.call_depth = Some(depth);
+        settings.limits.fit_stack().map_err(|error| print_error(format!("--max-depth is too deep, {}", error)))?;
+    }
+    if let Some(size) = number(args, "max-array")? {
+        settings.limits.array_size = Some(size);
+    }
+    settings.seed = number(args, "seed")?;
+    settings.invalid_input = match option(args, "invalid-input") {
+        None | Some("error") => InputPolicy::Error,
+        Some("reprompt") => InputPolicy::Reprompt,
+        Some(other) => {
+            return Err(print_error(format!(
+                "unknown --invalid-input {}, expected error or reprompt",
+                other
+            )))
+        }
+    };
+    Ok(settings)
+}
+
+/// Runs the program read from `path`, or with `snippet` a fragment that starts from those values
+/// and ends by showing the main program's variables.
+fn parse_and_run(
+    source: String,
+    path: &Path,
+    settings: &Settings,
+    snippet: Option<&BTreeMap<String, Initial>>,
+) -> Result<(), ()> {
     check_empty!(source.trim());
 
-    let lexed = lexer().parse(source.clone())
-    .map_err(|errors| display_error(errors, &file_name, &source))?;
+    let (sources, parsed) = import::load(path, &source, settings.dialect);
+    let (_, parsed) = parsed.map_err(|errors| display_error(errors, &sources))?;
 
-    check_empty!(lexed);
+    check_empty!(parsed);
 
-    let parsed = parser()
-        .parse(Stream::from_iter(
-            source.len()..source.len() + 1,
-            lexed.into_iter(),
-        ))
-        .map_err(|errors| display_error(errors, &file_name, &source))?;
+    // the program never starts with these, so there is no seed to repeat the run with
+    let errors: Vec<Simple<Execution>> = resolve::resolve(&parsed)
+        .into_iter()
+        .map(|(error, span)| Simple::custom(span, error))
+        .collect();
+    if !errors.is_empty() {
+        display_error(errors, &sources);
+        return Err(());
+    }
+
+    let seed = settings.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut start_state = settings.state(seed, Io::Console);
+    if let Some(initial) = snippet {
+        let globals = snippet::globals(&parsed, initial).map_err(|error| eprintln!("Error: {}", error))?;
+        start_state.scopes.push(globals);
+    }
 
-    let start_state = State {
-        functions: built_ins(),
-        scopes: vec![],
+    match evaluate(&parsed, start_state, false) {
+        Err(error) => {
+            let error: Simple<Execution> = Simple::custom(error.1, error.0);
+            display_error(vec![error], &sources);
+            println!("Random numbers were seeded with {}, run with --seed={} to repeat this run.", seed, seed);
+            Err(())
+        }
+        Ok(state) => {
+            if let (Some(_), Some(globals)) = (snippet, state.scopes.first()) {
+                println!("Final values:");
+                print!("{}", snippet::summary(globals));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The values given by each `--init NAME=VALUE`, or each name in an `--init FILE` of JSON or TOML,
+/// with the arguments that are not part of one. A name can give the index of an array's first
+/// value, as in `list[0]`, which is otherwise 1.
+fn initial_values(args: &[String]) -> Result<(BTreeMap<String, Initial>, Vec<String>), String> {
+    let mut initial = BTreeMap::new();
+    let mut rest = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let given = match arg.strip_prefix("--init") {
+            Some("") => args.next().ok_or_else(|| print_error("--init expects NAME=VALUE or a file"))?,
+            Some(given) if given.starts_with('=') => &given[1..],
+            _ => {
+                rest.push(arg.clone());
+                continue;
+            }
+        };
+        let values: Vec<(String, serde_json::Value)> = match given.split_once('=') {
+            // text that is not JSON is a string, so names need no quotes
+            Some((name, value)) => vec![(
+                String::from(name),
+                serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(String::from(value))),
+            )],
+            None => {
+                let text = std::fs::read_to_string(given).map_err(print_error)?;
+                let table: serde_json::Map<String, serde_json::Value> = if given.ends_with(".toml") {
+                    toml::from_str(&text).map_err(print_error)?
+                } else {
+                    serde_json::from_str(&text).map_err(print_error)?
+                };
+                table.into_iter().collect()
+            }
+        };
+        for (name, value) in values {
+            let (name, value) = initial_value(&name, &value).map_err(print_error)?;
+            initial.insert(name, value);
+        }
+    }
+    Ok((initial, rest))
+}
+
+/// The variable `name` gives a value to, and the value.
+fn initial_value(name: &str, value: &serde_json::Value) -> Result<(String, Initial), String> {
+    let (name, lower) = match name.trim().split_once('[') {
+        Some((name, lower)) => {
+            let lower = lower.strip_suffix(']').and_then(|lower| lower.trim().parse().ok());
+            (name.trim(), Some(lower.ok_or_else(|| format!("{} should name an array's first index, as in list[0]", name))?))
+        }
+        None => (name.trim(), None),
     };
+    let literal = |value: &serde_json::Value| match value {
+        serde_json::Value::Bool(value) => Some(Literal::Bool(*value)),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.try_into().ok().map(Literal::Integer),
+            None => number.as_f64().map(Literal::Real),
+        },
+        serde_json::Value::String(text) => Some(Literal::String(text.clone())),
+        _ => None,
+    };
+    let initial = match (value, lower) {
+        (serde_json::Value::Array(values), lower) => Initial::Array(
+            lower.unwrap_or(1),
+            values.iter().map(literal).collect::<Option<_>>().ok_or_else(|| format!("{} can only hold single values", name))?,
+        ),
+        (_, Some(_)) => return Err(format!("{} is given an index, so needs a list of values", name)),
+        (value, None) => Initial::Value(literal(value).ok_or_else(|| format!("{} can not hold {}", name, value))?),
+    };
+    Ok((String::from(name), initial))
+}
 
-    if let Err(error) = evaluate(&parsed, start_state, false){
-        let error: Simple<Execution> = Simple::custom(error.1, error.0);
-        return Err(display_error(vec![error], &file_name, &source))
+/// `fmt [--check] [--ascii] [--dialect=NAME] FILES...` rewrites each file in the guide's layout.
+/// With `--check` nothing is written, and it fails if any file would change.
+fn format_files(args: &[String]) -> Result<(), String> {
+    let dialect = settings(args)?.dialect;
+    let check = args.iter().any(|arg| arg == "--check");
+    let options = format::Options {
+        arrow: if args.iter().any(|arg| arg == "--ascii") {
+            format::Arrow::Ascii
+        } else {
+            format::Arrow::Unicode
+        },
     };
 
+    let mut unformatted = vec![];
+    for path in args.iter().filter(|arg| !arg.starts_with("--")) {
+        let (source, file_name) = validate_file_arg(Some(path))?;
+        let (tokens, parsed) = lex_and_parse(&source, &file_name, dialect)
+            .map_err(|_| print_error(format!("could not parse {}", path)))?;
+        let formatted = format::format(&source, &tokens, &parsed, &options);
+
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            unformatted.push(path);
+        } else {
+            std::fs::write(path, formatted).map_err(print_error)?;
+        }
+    }
+
+    if unformatted.is_empty() {
+        Ok(())
+    } else {
+        Err(print_error(format!("{} file(s) not formatted", unformatted.len())))
+    }
+}
+
+/// `lint [--dialect=NAME] FILES...` reports what the guide discourages, failing if anything was
+/// found. Words the dialect does not have as keywords are parse errors.
+fn lint_files(args: &[String]) -> Result<(), String> {
+    let dialect = settings(args)?.dialect;
+    let mut found = 0;
+    for path in args.iter().filter(|arg| !arg.starts_with("--")) {
+        let (source, file_name) = validate_file_arg(Some(path))?;
+        let tokens = match lexer_for(dialect).parse(source.as_str()) {
+            Ok(tokens) => tokens,
+            Err(errors) => {
+                display_error(errors, &Sources::single(&file_name, &source));
+                found += 1;
+                continue;
+            }
+        };
+        let parsed = parse_tokens(tokens.clone(), &source, &file_name, dialect).ok();
+        if parsed.is_none() {
+            found += 1;
+        }
+
+        let lints = lint::lint(&tokens, parsed.as_deref(), dialect);
+        found += lints.len();
+        for lint in lints {
+            Report::build(ReportKind::Warning, file_name.clone(), lint.span.start)
+                .with_code(lint.rule.id())
+                .with_message(lint.message)
+                .with_label(Label::new((file_name.clone(), lint.span)).with_message(lint.rule.name()))
+                .finish()
+                .print(sources(vec![(file_name.clone(), source.as_str())]))
+                .unwrap();
+        }
+    }
+
+    if found == 0 {
+        Ok(())
+    } else {
+        Err(print_error(format!("{} problem(s) found", found)))
+    }
+}
+
+/// `test [OPTIONS] PATHS...` runs every golden case and `TEST` block in the given files and
+/// directories, printing a summary and failing if anything failed.
+fn test_files(args: &[String]) -> Result<(), String> {
+    let settings = settings(args)?;
+    let (mut passed, mut failed) = (0, 0);
+    for path in args.iter().filter(|arg| !arg.starts_with("--")) {
+        for case in golden::cases(std::path::Path::new(path)).map_err(print_error)? {
+            let report = golden::run_case(&case, &settings).map_err(print_error)?;
+            match report.outcome {
+                Some(golden::Outcome::Passed) => {
+                    println!("\x1b[92mPASS\x1b[0m {}", case.display());
+                    passed += 1;
+                }
+                Some(golden::Outcome::Failed(reason)) => {
+                    println!("\x1b[91mFAIL\x1b[0m {}\n{}", case.display(), reason);
+                    failed += 1;
+                }
+                None => {}
+            }
+
+            for test in report.tests {
+                match test.failure {
+                    None => {
+                        println!("\x1b[92mPASS\x1b[0m {} \"{}\"", case.display(), test.name);
+                        passed += 1;
+                    }
+                    Some((error, span)) => {
+                        println!("\x1b[91mFAIL\x1b[0m {} \"{}\"", case.display(), test.name);
+                        let error: Simple<Execution> = Simple::custom(span, error);
+                        display_error(vec![error], &report.sources);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(print_error(format!("{} case(s) failed", failed)))
+    }
+}
+
+/// `grade RUBRIC DIRECTORY [--out=DIRECTORY]` marks every `.psps` file in a directory against a
+/// rubric, writing a JSON report for each student and `grades.csv` for the class.
+fn grade_files(args: &[String]) -> Result<(), String> {
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let [rubric, submissions] = paths[..] else {
+        return Err(print_error("expected a rubric and a directory of submissions"));
+    };
+    let rubric = std::fs::read_to_string(rubric).map_err(print_error)?;
+    let rubric = grade::Rubric::parse(&rubric).map_err(print_error)?;
+    let out = std::path::Path::new(option(args, "out").unwrap_or("grades"));
+    std::fs::create_dir_all(out).map_err(print_error)?;
+
+    let mut grades = vec![];
+    for path in golden::cases(std::path::Path::new(submissions)).map_err(print_error)? {
+        let source = std::fs::read_to_string(&path).map_err(print_error)?;
+        let student = path.file_stem().unwrap().to_string_lossy();
+        let grade = grade::grade(&rubric, &student, &source);
+        println!("{}: {}/{}", grade.student, grade.score, grade.total);
+
+        let json = serde_json::to_string_pretty(&grade).map_err(print_error)?;
+        std::fs::write(out.join(format!("{}.json", student)), json).map_err(print_error)?;
+        grades.push(grade);
+    }
+
+    std::fs::write(out.join("grades.csv"), grade::csv(&rubric, &grades)).map_err(print_error)
+}
+
+/// `flowchart [--format=dot|mermaid] [--dialect=NAME] FILE` prints a flowchart of the program, as
+/// Graphviz DOT unless Mermaid is asked for.
+fn flowchart_file(args: &[String]) -> Result<(), String> {
+    let dialect = settings(args)?.dialect;
+    let (source, file_name) = validate_file_arg(args.iter().find(|arg| !arg.starts_with("--")))?;
+    let (_, parsed) = lex_and_parse(&source, &file_name, dialect)
+        .map_err(|_| print_error(format!("could not parse {}", file_name)))?;
+    let chart = flowchart::flowchart(&parsed);
+    match option(args, "format") {
+        None | Some("dot") => print!("{}", chart.dot()),
+        Some("mermaid") => print!("{}", chart.mermaid()),
+        Some(format) => return Err(print_error(format!("unknown format {}, expected dot or mermaid", format))),
+    }
+    Ok(())
+}
+
+/// Renders DOT as SVG with Graphviz's `dot`, which has to be installed separately.
+fn svg(dot: &str) -> Result<String, String> {
+    let mut child = Command::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("could not run Graphviz's dot, {}", error))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(dot.as_bytes())
+        .map_err(|error| error.to_string())?;
+    let output = child.wait_with_output().map_err(|error| error.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn structure_file(args: &[String]) -> Result<(), String> {
+    let dialect = settings(args)?.dialect;
+    let (source, file_name) = validate_file_arg(args.iter().find(|arg| !arg.starts_with("--")))?;
+    let (_, parsed) = lex_and_parse(&source, &file_name, dialect)
+        .map_err(|_| print_error(format!("could not parse {}", file_name)))?;
+    let name = std::path::Path::new(&file_name)
+        .file_stem()
+        .map_or(file_name.clone(), |stem| stem.to_string_lossy().into_owned());
+    let chart = structure::structure_chart(&name, &parsed);
+    match option(args, "format") {
+        None | Some("dot") => print!("{}", chart.dot()),
+        Some("svg") => print!("{}", svg(&chart.dot()).map_err(print_error)?),
+        Some(format) => return Err(print_error(format!("unknown format {}, expected dot or svg", format))),
+    }
+    Ok(())
+}
+
+/// `transpile [--to=python|java|vb] FILE` prints the program translated into another language.
+/// Java's class and Visual Basic's module are named after the file.
+fn transpile_file(args: &[String]) -> Result<(), String> {
+    let path = args.iter().find(|arg| !arg.starts_with("--"));
+    let (source, file_name) = validate_file_arg(path)?;
+    let dialect = settings(args)?.dialect;
+    let (sources, parsed) = import::load(Path::new(path.unwrap()), &source, dialect);
+    let (tokens, parsed) = parsed.map_err(|errors| {
+        display_error(errors, &sources);
+        print_error(format!("could not parse {}", file_name))
+    })?;
+    let errors = lower::by_reference_errors(&parsed, dialect);
+    if !errors.is_empty() {
+        let errors = errors
+            .into_iter()
+            .map(|(error, span)| Simple::custom(span, error))
+            .collect();
+        display_error::<Execution>(errors, &sources);
+        return Err(print_error(format!("could not transpile {}", file_name)));
+    }
+    let program = lower::lower(&source, &tokens, &parsed);
+    let name = std::path::Path::new(&file_name)
+        .file_stem()
+        .map_or(file_name.clone(), |stem| lower::type_name(&stem.to_string_lossy()));
+    match option(args, "to") {
+        None | Some("python") => print!("{}", python::python(&program)),
+        Some("java") => print!("{}", java::java(&name, &program)),
+        Some("vb") => print!("{}", vb::vb(&name, &program)),
+        Some("c") => print!("{}", c::c(&program).map_err(|error| print_error(format!("can not write {} in C: {}", file_name, error)))?),
+        Some(language) => return Err(print_error(format!("unknown language {}, expected python, java, vb or c", language))),
+    }
     Ok(())
 }
 
 fn main() -> Result<(), String> {
+    with_stack(command)
+}
+
+fn command() -> Result<(), String> {
     let args: Vec<String> = std::env::args().collect();
 
-    let (source, file_name) = validate_file_arg(args.get(1))?;
+    match args.get(1).map(String::as_str) {
+        Some("lsp") => return lsp::run(settings(&args[2..])?.dialect).map_err(print_error),
+        Some("fmt") => return format_files(&args[2..]),
+        Some("lint") => return lint_files(&args[2..]),
+        Some("test") => return test_files(&args[2..]),
+        Some("grade") => return grade_files(&args[2..]),
+        Some("flowchart") => return flowchart_file(&args[2..]),
+        Some("structure") => return structure_file(&args[2..]),
+        Some("transpile") => return transpile_file(&args[2..]),
+        _ => {}
+    }
+
+    let (initial, args) = initial_values(&args[1..])?;
+    let snippet = args.iter().any(|arg| arg == "--snippet").then_some(&initial);
+    if snippet.is_none() && !initial.is_empty() {
+        return Err(print_error("--init only gives values to a --snippet"));
+    }
+    let settings = settings(&args)?;
+    let path = args.iter().find(|arg| !arg.starts_with("--"));
+    let (source, _) = validate_file_arg(path)?;
 
-    let result = if parse_and_run(source, file_name).is_err() {
+    let result = if parse_and_run(source, Path::new(path.unwrap()), &settings, snippet).is_err() {
         "encountered errors"
     }else{
         "has run successfully"
     };
 
     println!("\x1b[93mThe Program {}. Press enter to exit.\x1b[0m", result);
-    stdin().read(&mut [0]).unwrap();
+    stdin().read_line(&mut String::new()).unwrap();
     Ok(())
 }
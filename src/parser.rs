@@ -1,6 +1,8 @@
 use std::ops::Range;
+use std::rc::Rc;
 
 use crate::ast::*;
+use crate::dialect::Dialect;
 use chumsky::prelude::*;
 
 macro_rules! operator {
@@ -29,29 +31,54 @@ macro_rules! function_call {
 }
 
 pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<Token>> {
+    parser_for(Dialect::Lenient)
+}
+
+#[allow(clippy::result_large_err)]
+pub fn parser_for(dialect: Dialect) -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<Token>> {
     let identifier = select! {Token::Identifier(name) => name}.labelled("identifier");
 
     let literal_type =
         select! {Token::DataType(DataTypes::Literal(literal_type)) => literal_type.clone()}
             .labelled("data type");
 
-    let int = select! {Token::Integer(int) => int.parse().unwrap()};
+    // literals too large to hold are reported but parsed as zero, so that parsing carries on
+    let integer = select! {Token::Integer(int) => int}.validate(|int, span, emit| {
+        int.parse().unwrap_or_else(|_| {
+            emit(Simple::custom(span, format!("{} is too large for an integer, the largest is {}", int, isize::MAX)));
+            0
+        })
+    });
+
+    let real = select! {Token::Real(float) => float}.validate(|float, span, emit| {
+        match float.parse::<f64>() {
+            Ok(real) if real.is_finite() => real,
+            _ => {
+                emit(Simple::custom(span, format!("{} is too large for a real", float)));
+                0.0
+            }
+        }
+    });
 
-    let literal = select! {
-        Token::Integer(int) => Expression::Value(Literal::Integer(int.parse().unwrap())),
-        Token::Real(float) => Expression::Value(Literal::Real(float.parse().unwrap())),
-        Token::String(string) => Expression::Value(Literal::String(string)),
-        Token::Boolean(boolean) => Expression::Value(Literal::Bool(boolean))
-    }
-    .boxed()
-    .map_with_span(|expression, span: Range<usize>| (expression, span))
-    .labelled("literal");
+    let literal = integer
+        .map(Literal::Integer)
+        .or(real.map(Literal::Real))
+        .or(select! {
+            Token::String(string) => Literal::String(string),
+            Token::Char(char) => Literal::Char(char),
+            Token::Boolean(boolean) => Literal::Bool(boolean)
+        })
+        .map(Expression::Value)
+        .boxed()
+        .map_with_span(|expression, span: Range<usize>| (expression, span))
+        .labelled("literal");
 
     let built_in = select! {Token::BuiltIn(name) => name};
 
+    let string = select! {Token::String(string) => string}.labelled("string literal");
+
     let expression = recursive(|expr| {
         let index_array = identifier
-            .clone()
             .then(
                 expr.clone()
                     .delimited_by(just(Token::OpenSquare), just(Token::CloseSquare)),
@@ -69,7 +96,6 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<To
             .or(function_call!(identifier.or(built_in), expr.clone())
                 .map_with_span(|(name, args), span| (Expression::FunctionCall(name, args), span)))
             .or(identifier
-                .clone()
                 .map_with_span(|name, span: Range<usize>| (Expression::Variable(name), span)))
             .or(expr
                 .clone()
@@ -82,76 +108,83 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<To
             .then(atom.clone())
             .foldr(|left, right| {
                 let span = left.1.start..right.1.end;
-                return (Expression::Negative(Box::new(right)), span);
+                (Expression::Negative(Box::new(right)), span)
             })
-            .boxed()
-            .or(just(Token::Operator(Ops::Not))
-                .map_with_span(|token, span: Range<usize>| (token, span))
-                .repeated()
-                .then(expr)
-                .foldr(|left, right_side| {
-                    let span = left.1.start..right_side.1.end;
-                    (Expression::Not(Box::new(right_side)), span)
-                })
-                .boxed());
+            .boxed();
 
         let products = operator!(&PRODUCTS, unary);
 
         let sums = operator!(&SUMS, products);
 
-        operator!(&COMPARE, sums)
+        let comparison = operator!(&COMPARE, sums);
+
+        // NOT binds looser than a comparison, so `NOT a = b` negates the whole comparison
+        let not = just(Token::Operator(Ops::Not))
+            .map_with_span(|token, span: Range<usize>| (token, span))
+            .repeated()
+            .then(comparison)
+            .foldr(|left, right| {
+                let span = left.1.start..right.1.end;
+                (Expression::Not(Box::new(right)), span)
+            })
+            .boxed();
+
+        let and = operator!(&[Ops::And], not);
+
+        operator!(&[Ops::Or], and)
     });
 
     let newline = |at_least| just(Token::NewLine).repeated().at_least(at_least).ignored();
 
     let statement = recursive(|stat| {
         let declare = just(Token::Declare)
-            .ignore_then(identifier)
+            .ignore_then(identifier.separated_by(just(Token::Comma)).at_least(1))
             .then_ignore(just(Token::Colon))
             .boxed();
 
         let declare_literal = declare
             .clone()
-            .then(literal_type.clone())
-            .map(|(identifier, literal_type)| Declare::Literal(identifier, literal_type))
+            .then(literal_type)
+            .map(|(identifiers, literal_type)| {
+                identifiers
+                    .into_iter()
+                    .map(|identifier| Declare::Literal(identifier, literal_type.clone()))
+                    .collect()
+            })
             .boxed();
 
         let declare_array = declare
             .then_ignore(just(Token::DataType(DataTypes::Array)))
             .then(
-                int.clone()
-                    .separated_by(just(Token::Colon))
-                    .exactly(2)
+                expression
+                    .clone()
+                    .then_ignore(just(Token::Colon))
+                    .then(expression.clone())
                     .delimited_by(just(Token::OpenSquare), just(Token::CloseSquare)),
             )
             .then_ignore(just(Token::Of))
             .then(literal_type)
-            .map(|((identifier, bounds), literal_type)| {
-                Declare::Array(
-                    identifier,
-                    Bounds {
-                        lower: *bounds.get(0).unwrap(),
-                        upper: *bounds.get(1).unwrap(),
-                    },
-                    literal_type,
-                )
+            .map(|((identifiers, (lower, upper)), literal_type)| {
+                let bounds = ArrayBounds { lower, upper };
+                identifiers
+                    .into_iter()
+                    .map(|identifier| Declare::Array(identifier, bounds.clone(), literal_type.clone()))
+                    .collect()
             })
             .boxed();
 
         let declare = declare_array
             .or(declare_literal)
-            .map(|declare| Statement::Declare(declare))
+            .map(Statement::Declare)
             .boxed();
 
         let assign_literal = identifier
-            .clone()
             .then_ignore(just(Token::Arrow))
             .then(expression.clone())
             .map(|(identifier, expression)| Assign::Literal(identifier, expression))
             .boxed();
 
         let assign_array = identifier
-            .clone()
             .then(
                 expression
                     .clone()
@@ -164,7 +197,7 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<To
 
         let assign = assign_array
             .or(assign_literal)
-            .map(|assign| Statement::Assign(assign));
+            .map(Statement::Assign);
 
         let out = just(Token::Out)
             .ignore_then(
@@ -173,12 +206,24 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<To
                     .separated_by(just(Token::Comma))
                     .at_least(1),
             )
-            .map(|expressions| Statement::Out(expressions))
+            .then(just(Token::Comma).or_not().map(|comma| comma.is_none()))
+            .map(|(expressions, newline)| Statement::Out(expressions, newline))
             .boxed();
 
-        let in_ = just(Token::In)
-            .ignore_then(identifier)
-            .map(|name| Statement::In(name));
+        let target = identifier.then(
+            expression
+                .clone()
+                .delimited_by(just(Token::OpenSquare), just(Token::CloseSquare))
+                .or_not(),
+        );
+        let in_ = just(Token::In).ignore_then(target.clone()).boxed();
+        let in_ = if dialect.assigns_input() {
+            in_.or(target.then_ignore(just(Token::Arrow)).then_ignore(just(Token::In)))
+                .boxed()
+        } else {
+            in_
+        }
+        .map(|(identifier, index)| Statement::In(identifier, index));
 
         let if_ = just(Token::If)
             .ignore_then(expression.clone())
@@ -196,12 +241,34 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<To
             .then_ignore(just(Token::EndIf))
             .boxed();
 
-        let procedure = function_call!(identifier.clone(), expression.clone())
+        let call = just(Token::Call).ignore_then(
+            identifier.then(
+                expression
+                    .clone()
+                    .separated_by(just(Token::Comma))
+                    .delimited_by(just(Token::OpenBracket), just(Token::CloseBracket))
+                    .or_not()
+                    .map(Option::unwrap_or_default),
+            ),
+        );
+
+        let procedure = call
+            .or(function_call!(identifier.clone(), expression.clone()))
             .map(|(name, args)| Statement::ProcedureCall(name, args));
 
         let return_ = just(Token::Return)
             .ignore_then(expression.clone().or_not())
-            .map(|expression| Statement::Return(expression));
+            .map(Statement::Return);
+
+        let next = if dialect.next_needs_identifier() {
+            just(Token::Next).ignore_then(identifier).ignored().boxed()
+        } else {
+            just(Token::Next).ignore_then(identifier.or_not()).ignored().boxed()
+        };
+        let next = just(Token::EndFor)
+            .ignore_then(identifier.or_not())
+            .ignored()
+            .or(next);
 
         let for_ = just(Token::For)
             .ignore_then(identifier)
@@ -214,13 +281,16 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<To
             .map(|(((identifier, start), end), statements)| {
                 Statement::For(identifier, start, end, statements)
             })
-            .then_ignore(just(Token::EndFor).or(just(Token::Next)))
-            .then_ignore(identifier.or_not())
+            .then_ignore(next)
             .boxed();
 
         let while_ = just(Token::While)
             .ignore_then(expression.clone())
-            .then_ignore(just(Token::Do))
+            .then_ignore(if dialect.while_needs_do() {
+                just(Token::Do).ignored().boxed()
+            } else {
+                just(Token::Do).or_not().ignored().boxed()
+            })
             .then_ignore(newline(1))
             .then(stat.clone().repeated().at_least(1))
             .map(|(expression, statements)| Statement::While(expression, statements))
@@ -235,12 +305,112 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Statement>>, Error = Simple<To
             .map(|(statements, expression)| Statement::Repeat(statements, expression))
             .boxed();
 
+        let assert = just(Token::Assert)
+            .ignore_then(expression.clone())
+            .then(just(Token::Comma).ignore_then(string).or_not())
+            .map(|(conditional, message)| Statement::Assert(conditional, message));
+
         choice((
-            declare, assign, out, in_, if_, procedure, return_, for_, while_, repeat,
+            declare, assign, out, in_, if_, procedure, return_, for_, while_, repeat, assert,
         ))
         .map_with_span(|statement, span| (statement, span))
         .then_ignore(newline(1).or(end().rewind()))
     });
 
-    statement.repeated().then_ignore(end())
+    let test = just(Token::Test)
+        .ignore_then(string)
+        .then_ignore(newline(1))
+        .then(statement.clone().repeated().at_least(1))
+        .then_ignore(just(Token::EndTest))
+        .map(|(name, statements)| Statement::Test(name, statements))
+        .map_with_span(|statement, span| (statement, span))
+        .then_ignore(newline(1).or(end().rewind()));
+
+    let import = just(Token::Import)
+        .ignore_then(string)
+        .map(Statement::Import)
+        .map_with_span(|statement, span| (statement, span))
+        .then_ignore(newline(1).or(end().rewind()));
+
+    let passing = just(Token::ByVal)
+        .to(Passing::ByValue)
+        .or(just(Token::ByRef).to(Passing::ByReference));
+
+    let array_of = just(Token::DataType(DataTypes::Array))
+        .then(just(Token::Of))
+        .or_not()
+        .map(|array| array.is_some());
+
+    let parameters = passing
+        .or_not()
+        .then(identifier)
+        .then_ignore(just(Token::Colon))
+        .then(array_of.then(literal_type))
+        .map_with_span(|parameter, span| (parameter, span))
+        .separated_by(just(Token::Comma))
+        .delimited_by(just(Token::OpenBracket), just(Token::CloseBracket))
+        .or_not()
+        .map(|parameters| {
+            // `BYVAL` and `BYREF` carry on to the parameters after them until the other is given.
+            let mut passing = Passing::ByValue;
+            parameters
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(((given, name), (array, literal_type)), span)| {
+                    passing = given.unwrap_or(passing);
+                    let parameter = Parameter {
+                        name,
+                        passing,
+                        literal_type,
+                        array,
+                    };
+                    (parameter, span)
+                })
+                .collect::<Vec<_>>()
+        })
+        .boxed();
+
+    let body = |end| {
+        newline(1)
+            .ignore_then(statement.clone().repeated().at_least(1))
+            .then_ignore(just(end))
+    };
+
+    let procedure = just(Token::Procedure)
+        .ignore_then(identifier)
+        .then(parameters.clone())
+        .then(body(Token::EndProcedure))
+        .map(|((name, parameters), body)| Subroutine {
+            name,
+            parameters,
+            returns: None,
+            body,
+        })
+        .boxed();
+
+    let function = just(Token::Function)
+        .ignore_then(identifier)
+        .then(parameters)
+        .then_ignore(just(Token::Returns))
+        .then(literal_type)
+        .then(body(Token::EndFunction))
+        .map(|(((name, parameters), returns), body)| Subroutine {
+            name,
+            parameters,
+            returns: Some(returns),
+            body,
+        })
+        .boxed();
+
+    let subroutine = procedure
+        .or(function)
+        .map(|subroutine| Statement::Subroutine(Rc::new(subroutine)))
+        .map_with_span(|statement, span| (statement, span))
+        .then_ignore(newline(1).or(end().rewind()));
+
+    import
+        .or(test)
+        .or(subroutine)
+        .or(statement)
+        .repeated().then_ignore(end())
 }
@@ -0,0 +1,136 @@
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use chumsky::{prelude::*, Stream};
+use serde::{Deserialize, Serialize};
+
+use crate::ast::*;
+use crate::check::check as check_types;
+use crate::dialect::Dialect;
+use crate::error_message;
+use crate::evaluate::{evaluate, Io, Limits, State};
+use crate::lexer::{lexer_for, strip_trivia};
+use crate::parser::parser_for;
+
+/// Statements a playground run may execute unless told otherwise, so that an endless loop stops
+/// instead of freezing the page.
+pub const DEFAULT_STEPS: u64 = 10_000_000;
+
+/// How to run a program, every field optional when coming from JavaScript.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Options {
+    /// A dialect name as `--dialect` takes it, lenient when not given.
+    pub dialect: Option<String>,
+    /// Seeds the random built-ins, since there is no randomness to pick one from.
+    pub seed: u64,
+    pub max_steps: Option<u64>,
+}
+
+/// A problem with the program, located by character offsets and by 1-based line and column.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// `Syntax` for lexing and parsing errors, otherwise the `Execution` variant's name.
+    pub kind: String,
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// What a run printed, and why it stopped early if it did.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Run {
+    pub output: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    fn new(source: &str, kind: &str, message: String, span: &Span) -> Diagnostic {
+        let before: Vec<char> = source.chars().take(span.start).collect();
+        let line_start = before
+            .iter()
+            .rposition(|char| *char == '\n')
+            .map_or(0, |index| index + 1);
+        Diagnostic {
+            kind: String::from(kind),
+            message,
+            start: span.start,
+            end: span.end,
+            line: before.iter().filter(|char| **char == '\n').count() + 1,
+            column: before.len() - line_start + 1,
+        }
+    }
+
+    fn syntax<T: Hash + Eq + Debug>(source: &str, errors: Vec<Simple<T>>) -> Vec<Diagnostic> {
+        errors
+            .into_iter()
+            .map(|error| Diagnostic::new(source, "Syntax", error_message(&error), &error.span()))
+            .collect()
+    }
+
+    fn execution(source: &str, (error, span): Spanned<Execution>) -> Diagnostic {
+        Diagnostic::new(source, error.kind(), error.to_string(), &span)
+    }
+}
+
+fn dialect(options: &Options) -> Result<Dialect, String> {
+    match &options.dialect {
+        None => Ok(Dialect::Lenient),
+        Some(name) => Dialect::find(name).ok_or_else(|| format!("unknown dialect {}", name)),
+    }
+}
+
+fn parse(source: &str, dialect: Dialect) -> Result<Vec<Spanned<Statement>>, Vec<Diagnostic>> {
+    let tokens = lexer_for(dialect)
+        .parse(source)
+        .map_err(|errors| Diagnostic::syntax(source, errors))?;
+    let end = source.chars().count();
+    parser_for(dialect)
+        .parse(Stream::from_iter(
+            end..end + 1,
+            strip_trivia(tokens).into_iter(),
+        ))
+        .map_err(|errors| Diagnostic::syntax(source, errors))
+}
+
+/// Runs `source` with `input` as the lines `INPUT` reads, collecting everything it outputs.
+pub fn run(source: &str, input: &str, options: &Options) -> Result<Run, String> {
+    let dialect = dialect(options)?;
+    let output = Rc::new(RefCell::new(String::new()));
+    let diagnostics = match parse(source, dialect) {
+        Err(diagnostics) => diagnostics,
+        Ok(statements) => {
+            let limits = Limits {
+                steps: Some(options.max_steps.unwrap_or(DEFAULT_STEPS)),
+                ..Limits::default()
+            };
+            let state = State::new(dialect, limits, options.seed, Io::scripted(input, &output));
+            match evaluate(&statements, state, false) {
+                Ok(_) => vec![],
+                Err(error) => vec![Diagnostic::execution(source, error)],
+            }
+        }
+    };
+    let output = output.borrow().clone();
+    Ok(Run {
+        output,
+        diagnostics,
+    })
+}
+
+/// The syntax errors in `source`, or the type errors running it would raise, without running it.
+pub fn check(source: &str, options: &Options) -> Result<Vec<Diagnostic>, String> {
+    let dialect = dialect(options)?;
+    Ok(match parse(source, dialect) {
+        Err(diagnostics) => diagnostics,
+        Ok(statements) => check_types(&statements, dialect)
+            .errors
+            .into_iter()
+            .map(|error| Diagnostic::execution(source, error))
+            .collect(),
+    })
+}
@@ -0,0 +1,624 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::lower::*;
+
+/// Python's keywords and the built-ins the generated code relies on. Pseudocode identifiers that
+/// match one get an underscore after them.
+const RESERVED: [&str; 50] = [
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield", "print", "input", "int", "str", "float", "bool", "object", "len", "range",
+    "random", "Array", "datetime", "div", "mod", "show",
+];
+
+/// Gives pseudocode arrays their declared bounds, which Python's lists can not have.
+const ARRAY: &str = r#"class Array:
+    """An ARRAY[lower:upper], indexed from lower to upper inclusive."""
+
+    def __init__(self, lower, upper):
+        if lower > upper:
+            raise ValueError(f"invalid bounds {lower}:{upper}, the lower bound is above the upper bound")
+        self.lower = lower
+        self.values = [None] * (upper - lower + 1)
+
+    def _offset(self, index):
+        if not 0 <= index - self.lower < len(self.values):
+            upper = self.lower + len(self.values) - 1
+            raise IndexError(f"index {index} is out of bounds, the array goes from {self.lower} to {upper}")
+        return index - self.lower
+
+    def __getitem__(self, index):
+        value = self.values[self._offset(index)]
+        if value is None:
+            raise ValueError(f"index {index} is not assigned")
+        return value
+
+    def __setitem__(self, index, value):
+        self.values[self._offset(index)] = value
+
+    def __len__(self):
+        return len(self.values)
+
+    def _matches(self, other):
+        if self.lower != other.lower or len(self) != len(other):
+            raise ValueError("arrays do not have the same bounds")
+
+    def assign(self, other):
+        self._matches(other)
+        self.values[:] = other.values
+
+    def copy(self):
+        copy = Array(self.lower, self.lower + len(self) - 1)
+        copy.values[:] = self.values
+        return copy
+
+    def __eq__(self, other):
+        self._matches(other)
+        return [self[index] for index in range(self.lower, self.lower + len(self))] == [
+            other[index] for index in range(other.lower, other.lower + len(other))
+        ]
+"#;
+
+/// Functions for what Python's operators and `print` do differently, each by its name. Only those
+/// the program calls are written.
+const HELPERS: [(&str, &str); 3] = [
+    (
+        "div",
+        r#"def div(a, b):
+    """a DIV b, which rounds towards zero where // rounds down, and is an integer for reals too."""
+    quotient = int(abs(a) // abs(b))
+    return quotient if (a < 0) == (b < 0) else -quotient
+"#,
+    ),
+    (
+        "mod",
+        r#"def mod(a, b):
+    """a MOD b, which takes the sign of a where % takes that of b."""
+    remainder = abs(a) % abs(b)
+    return remainder if a >= 0 else -remainder
+"#,
+    ),
+    (
+        "show",
+        r#"def show(value):
+    """Writes a value as OUTPUT does: booleans as TRUE or FALSE, and whole reals with a decimal
+    point."""
+    if isinstance(value, bool):
+        return "TRUE" if value else "FALSE"
+    if isinstance(value, float) and value.is_integer():
+        return f"{value:.1f}"
+    return str(value)
+"#,
+    ),
+];
+
+/// Writes a lowered program as Python 3.
+///
+/// Procedures hand their `BYREF` parameters back as return values, which the caller assigns to
+/// its variables, and each `TEST` block becomes a pytest-style `test_` function.
+pub fn python(program: &Program) -> String {
+    let mut python = Python {
+        writer: Writer::new("    "),
+        program,
+        current: None,
+        types: HashMap::new(),
+    };
+    for routine in &program.subroutines {
+        python.blank_lines();
+        python.routine(routine, &name(&routine.name));
+    }
+    python.blank_lines();
+    python.types = python.globals();
+    python.block(&program.main);
+    for test in &program.tests {
+        python.blank_lines();
+        python.routine(test, &test_name(&test.name));
+    }
+    for comment in &program.footer {
+        python.writer.line(&format!("#{}", comment));
+    }
+    let code = std::mem::take(&mut python.writer.output);
+
+    for comment in &program.header {
+        python.writer.line(&format!("#{}", comment));
+    }
+    if program.uses_dates || program.uses_random {
+        python.blank_lines();
+    }
+    if program.uses_dates {
+        python.writer.line("import datetime");
+    }
+    if program.uses_random {
+        python.writer.line("import random");
+    }
+    if program.uses_arrays {
+        python.blank_lines();
+        python.writer.output.push_str(ARRAY);
+    }
+    for (helper, definition) in HELPERS {
+        if invokes(&code, helper) {
+            python.blank_lines();
+            python.writer.output.push_str(definition);
+        }
+    }
+    python.blank_lines();
+
+    let mut output = python.writer.output + &code;
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+    output
+}
+
+fn name(identifier: &str) -> String {
+    if RESERVED.contains(&identifier) {
+        format!("{}_", identifier)
+    } else {
+        String::from(identifier)
+    }
+}
+
+fn python_type(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer => "int",
+        LiteralType::Real => "float",
+        LiteralType::String => "str",
+        LiteralType::Boolean => "bool",
+        LiteralType::Char => "str",
+        LiteralType::Date => "datetime.date",
+        LiteralType::Any => "object",
+    }
+}
+
+/// `TEST "adds up"` becomes `test_adds_up`.
+fn test_name(name: &str) -> String {
+    let words = name
+        .split(|char: char| !char.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase);
+    std::iter::once(String::from("test"))
+        .chain(words)
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Binding strength in Python, higher binding tighter.
+fn precedence(expression: &Expr) -> u8 {
+    match expression {
+        Expr::Operate(Ops::Or, ..) => 1,
+        Expr::Operate(Ops::And, ..) => 2,
+        Expr::Not(_) => 3,
+        Expr::Operate(op, ..) if COMPARE.contains(op) => 4,
+        Expr::ArraysEqual(..) => 4,
+        Expr::Operate(op, ..) if SUMS.contains(op) => 5,
+        Expr::Operate(..) => 6,
+        Expr::Negative(_) => 7,
+        _ => 8,
+    }
+}
+
+fn operator(op: &Ops) -> &'static str {
+    match op {
+        Ops::Plus | Ops::Concatenate => "+",
+        Ops::Minus => "-",
+        Ops::Divide => "/",
+        Ops::Multiply => "*",
+        Ops::GreaterThan => ">",
+        Ops::LessThan => "<",
+        Ops::GreaterThanEqual => ">=",
+        Ops::LessThanEqual => "<=",
+        Ops::Equal => "==",
+        Ops::NotEqual => "!=",
+        Ops::Mod => "%",
+        Ops::Div => "//",
+        Ops::And => "and",
+        Ops::Or => "or",
+        Ops::Not => "not",
+    }
+}
+
+fn literal(literal: &Literal) -> String {
+    match literal {
+        Literal::String(value) => format!("\"{}\"", value.replace('\\', "\\\\")),
+        Literal::Bool(true) => String::from("True"),
+        Literal::Bool(false) => String::from("False"),
+        literal => literal.source(),
+    }
+}
+
+struct Python<'a> {
+    writer: Writer,
+    program: &'a Program,
+    /// The routine being written, if any.
+    current: Option<&'a Routine>,
+    /// The type of each variable in scope, since Python's are only hints.
+    types: HashMap<String, LiteralType>,
+}
+
+impl<'a> Python<'a> {
+    /// The main program's variables and their types.
+    fn globals(&self) -> HashMap<String, LiteralType> {
+        self.program
+            .variables
+            .iter()
+            .map(|declared| match declared {
+                Declared::Literal(identifier, literal_type)
+                | Declared::Array(identifier, .., literal_type) => {
+                    (identifier.clone(), literal_type.clone())
+                }
+            })
+            .collect()
+    }
+
+    fn arguments(&self, args: &[Typed]) -> String {
+        args.iter()
+            .map(|arg| self.expression(&arg.expression))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The arguments to a subroutine, widened to its parameters' types.
+    fn call_arguments(&self, function: &str, args: &[Typed]) -> String {
+        let parameters = self
+            .program
+            .subroutines
+            .iter()
+            .find(|routine| routine.name == function)
+            .map_or(&[][..], |routine| &routine.parameters);
+        args.iter()
+            .enumerate()
+            .map(|(index, arg)| match parameters.get(index) {
+                Some(parameter) if !parameter.array => self.widened(arg, &parameter.literal_type),
+                _ => self.expression(&arg.expression),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// `value` stored where a `target` is expected, an INTEGER made a float for a REAL so that it
+    /// is written with its decimal point.
+    fn widened(&self, value: &Typed, target: &LiteralType) -> String {
+        match (&value.literal_type, target) {
+            (LiteralType::Integer, LiteralType::Real) => match value.integer() {
+                Some(integer) => format!("{}.0", integer),
+                None => format!("float({})", self.expression(&value.expression)),
+            },
+            _ => self.expression(&value.expression),
+        }
+    }
+
+    /// A value to `print`, through `show` where Python would write it differently.
+    fn shown(&self, value: &Typed) -> String {
+        match value.literal_type {
+            LiteralType::Integer | LiteralType::String | LiteralType::Char => {
+                self.expression(&value.expression)
+            }
+            LiteralType::Date => format!(
+                "{}.strftime(\"%d/%m/%Y\")",
+                self.operand(&value.expression, 8, false)
+            ),
+            LiteralType::Real | LiteralType::Boolean | LiteralType::Any => {
+                format!("show({})", self.expression(&value.expression))
+            }
+        }
+    }
+
+    /// The type of a variable in scope.
+    fn type_of(&self, identifier: &str) -> LiteralType {
+        self.types
+            .get(identifier)
+            .cloned()
+            .unwrap_or(LiteralType::Any)
+    }
+
+    /// Prints `inner` under a parent binding with strength `outer`, bracketed where Python would
+    /// otherwise group it differently or chain comparisons.
+    fn operand(&self, inner: &Expr, outer: u8, right: bool) -> String {
+        if needs_brackets(precedence(inner), outer, right, 4..5) {
+            format!("({})", self.expression(inner))
+        } else {
+            self.expression(inner)
+        }
+    }
+
+    fn expression(&self, expression: &Expr) -> String {
+        match expression {
+            Expr::Value(value) => literal(value),
+            Expr::Variable(identifier) => name(identifier),
+            Expr::Index(identifier, index, _) => {
+                format!(
+                    "{}[{}]",
+                    name(identifier),
+                    self.expression(&index.expression)
+                )
+            }
+            Expr::Length(identifier, _) => format!("len({})", name(identifier)),
+            Expr::ArraysEqual(a, b) => format!(
+                "{} == {}",
+                self.expression(&a.expression),
+                self.expression(&b.expression)
+            ),
+            Expr::ArrayCopy(identifier) => format!("{}.copy()", name(identifier)),
+            Expr::BuiltIn(BuiltIn::StrToNum, args) => format!("int({})", self.arguments(args)),
+            Expr::BuiltIn(BuiltIn::NumToStr, args) => format!("str({})", self.arguments(args)),
+            Expr::BuiltIn(BuiltIn::RandomBetween, args) => {
+                format!("random.randrange({})", self.arguments(args))
+            }
+            Expr::Call(function, args) => {
+                format!(
+                    "{}({})",
+                    name(function),
+                    self.call_arguments(function, args)
+                )
+            }
+            Expr::Negative(inner) => format!("-{}", self.operand(&inner.expression, 7, false)),
+            Expr::Not(inner) => format!("not {}", self.operand(&inner.expression, 3, false)),
+            Expr::Input(literal_type) => String::from(match literal_type {
+                LiteralType::Integer => "int(input())",
+                LiteralType::Real => "float(input())",
+                LiteralType::Boolean => "{\"TRUE\": True, \"FALSE\": False}[input().upper()]",
+                LiteralType::Date => "datetime.datetime.strptime(input(), \"%d/%m/%Y\").date()",
+                LiteralType::Char | LiteralType::String | LiteralType::Any => "input()",
+            }),
+            Expr::Operate(op @ (Ops::Div | Ops::Mod), a, b) => format!(
+                "{}({}, {})",
+                match op {
+                    Ops::Div => "div",
+                    _ => "mod",
+                },
+                self.expression(&a.expression),
+                self.expression(&b.expression)
+            ),
+            Expr::Operate(op, a, b) => {
+                let outer = precedence(expression);
+                format!(
+                    "{} {} {}",
+                    self.operand(&a.expression, outer, false),
+                    operator(op),
+                    self.operand(&b.expression, outer, true)
+                )
+            }
+        }
+    }
+
+    /// Separates top-level definitions from what is around them by two blank lines.
+    fn blank_lines(&mut self) {
+        let output = &mut self.writer.output;
+        if !output.is_empty() {
+            while !output.ends_with("\n\n\n") {
+                output.push('\n');
+            }
+        }
+    }
+
+    fn routine(&mut self, routine: &'a Routine, name: &str) {
+        for comment in &routine.comments {
+            self.writer.line(&format!("#{}", comment));
+        }
+        let parameters = routine
+            .parameters
+            .iter()
+            .map(|parameter| match parameter.array {
+                true => format!("{}: Array", self::name(&parameter.name)),
+                false => format!(
+                    "{}: {}",
+                    self::name(&parameter.name),
+                    python_type(&parameter.literal_type)
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let returns = routine.returns.as_ref().map_or(String::new(), |returns| {
+            format!(" -> {}", python_type(returns))
+        });
+        self.writer
+            .line(&format!("def {}({}){}:", name, parameters, returns));
+        self.writer.indent();
+        if !routine.globals.is_empty() {
+            let globals: Vec<String> = routine
+                .globals
+                .iter()
+                .map(|global| self::name(global))
+                .collect();
+            self.writer.line(&format!("global {}", globals.join(", ")));
+        }
+        if routine.returns.is_some()
+            && routine
+                .parameters
+                .iter()
+                .any(|parameter| parameter.passing == Passing::ByReference)
+        {
+            self.writer.line(
+                "# BYREF parameters are passed by value, since a function only hands back its result",
+            );
+        }
+
+        self.current = Some(routine);
+        self.types = self.globals();
+        for parameter in &routine.parameters {
+            self.types
+                .insert(parameter.name.clone(), parameter.literal_type.clone());
+        }
+        self.block(&routine.body);
+        if !routine.by_reference().is_empty() && !routine.ends_in_return() {
+            self.statement(&Lowered::Return(None));
+        }
+        self.current = None;
+        self.writer.dedent();
+        if let Some(comment) = &routine.trailing {
+            self.writer.trailing(&format!("#{}", comment));
+        }
+    }
+
+    /// Writes an indented block, with `pass` when it has no statements to keep Python happy.
+    fn body(&mut self, block: &Block) {
+        self.writer.indent();
+        self.block(block);
+        if !block.iter().any(|line| matches!(line, Line::Statement(..))) {
+            self.writer.line("pass");
+        }
+        self.writer.dedent();
+    }
+
+    fn block(&mut self, block: &Block) {
+        for line in block {
+            match line {
+                Line::Comment(text) => self.writer.line(&format!("#{}", text)),
+                Line::Blank => self.writer.blank(),
+                Line::Statement(statement, trailing) => {
+                    self.statement(statement);
+                    if let Some(comment) = trailing {
+                        self.writer.trailing(&format!("#{}", comment));
+                    }
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Lowered) {
+        match statement {
+            Lowered::Declare(declares) => {
+                for declare in declares {
+                    let (Declared::Literal(identifier, literal_type)
+                    | Declared::Array(identifier, .., literal_type)) = declare;
+                    self.types.insert(identifier.clone(), literal_type.clone());
+                    self.writer.line(&match declare {
+                        Declared::Literal(identifier, literal_type) => {
+                            format!("{}: {}", name(identifier), python_type(literal_type))
+                        }
+                        Declared::Array(identifier, lower, upper, _) => format!(
+                            "{} = Array({}, {})",
+                            name(identifier),
+                            self.expression(&lower.expression),
+                            self.expression(&upper.expression)
+                        ),
+                    })
+                }
+            }
+            Lowered::CopyArray(to, from) => self.writer.line(&format!(
+                "{}.assign({})",
+                name(to),
+                self.expression(&from.expression)
+            )),
+            Lowered::Assign(identifier, value) => self.writer.line(&format!(
+                "{} = {}",
+                name(identifier),
+                self.widened(value, &self.type_of(identifier))
+            )),
+            Lowered::AssignIndex(identifier, index, value, _) => self.writer.line(&format!(
+                "{}[{}] = {}",
+                name(identifier),
+                self.expression(&index.expression),
+                self.widened(value, &self.type_of(identifier))
+            )),
+            Lowered::Output(values, newline) => {
+                let separator = if values.len() > 1 { ", sep=\"\"" } else { "" };
+                let end = if *newline { "" } else { ", end=\"\"" };
+                let values: Vec<String> = values.iter().map(|value| self.shown(value)).collect();
+                self.writer
+                    .line(&format!("print({}{}{})", values.join(", "), separator, end))
+            }
+            Lowered::If(branches, otherwise) => {
+                for (index, (conditional, block)) in branches.iter().enumerate() {
+                    let keyword = if index == 0 { "if" } else { "elif" };
+                    self.writer.line(&format!(
+                        "{} {}:",
+                        keyword,
+                        self.expression(&conditional.expression)
+                    ));
+                    self.body(block);
+                }
+                if let Some(block) = otherwise {
+                    self.writer.line("else:");
+                    self.body(block);
+                }
+            }
+            Lowered::Call(function, args, assigned) => {
+                let call = format!(
+                    "{}({})",
+                    name(function),
+                    self.call_arguments(function, args)
+                );
+                if assigned.is_empty() {
+                    self.writer.line(&call)
+                } else {
+                    let assigned: Vec<String> =
+                        assigned.iter().map(|variable| name(variable)).collect();
+                    self.writer
+                        .line(&format!("{} = {}", assigned.join(", "), call))
+                }
+            }
+            Lowered::Return(Some(value)) => {
+                let returns = self
+                    .current
+                    .and_then(|routine| routine.returns.clone())
+                    .unwrap_or(LiteralType::Any);
+                self.writer
+                    .line(&format!("return {}", self.widened(value, &returns)))
+            }
+            Lowered::Return(None) => {
+                let by_reference: Vec<String> = self
+                    .current
+                    .map(Routine::by_reference)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|parameter| name(&parameter.name))
+                    .collect();
+                if by_reference.is_empty() {
+                    self.writer.line("return")
+                } else {
+                    self.writer
+                        .line(&format!("return {}", by_reference.join(", ")))
+                }
+            }
+            Lowered::Assert(conditional, message) => {
+                let message = message.as_ref().map_or(String::new(), |message| {
+                    format!(", {}", literal(&Literal::String(message.clone())))
+                });
+                self.writer.line(&format!(
+                    "assert {}{}",
+                    self.expression(&conditional.expression),
+                    message
+                ))
+            }
+            Lowered::For(identifier, start, end, block) => {
+                let end = match &end.expression {
+                    Expr::Value(Literal::Integer(end)) => (end + 1).to_string(),
+                    Expr::Operate(Ops::Minus, end, one)
+                        if one.expression == Expr::Value(Literal::Integer(1)) =>
+                    {
+                        self.expression(&end.expression)
+                    }
+                    end => format!("{} + 1", self.operand(end, 5, false)),
+                };
+                self.writer.line(&format!(
+                    "for {} in range({}, {}):",
+                    name(identifier),
+                    self.expression(&start.expression),
+                    end
+                ));
+                self.body(block);
+            }
+            Lowered::While(conditional, block) => {
+                self.writer.line(&format!(
+                    "while {}:",
+                    self.expression(&conditional.expression)
+                ));
+                self.body(block);
+            }
+            Lowered::Repeat(block, conditional) => {
+                self.writer.line("while True:");
+                self.writer.indent();
+                self.block(block);
+                self.writer
+                    .line(&format!("if {}:", self.expression(&conditional.expression)));
+                self.writer.indent();
+                self.writer.line("break");
+                self.writer.dedent();
+                self.writer.dedent();
+            }
+        }
+    }
+}
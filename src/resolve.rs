@@ -0,0 +1,70 @@
+//! Works out which scope each declaration belongs to before a program runs. As in the exam
+//! boards' guides, a variable declared anywhere in the main program is global, one declared
+//! anywhere in a subroutine, like its parameters, is local to each call and hides a global of the
+//! same name, and the bodies of `IF`, `FOR`, `WHILE` and `REPEAT` open no scope of their own. A
+//! `TEST` block is a program of its own.
+
+use std::collections::HashSet;
+
+use crate::ast::*;
+
+/// Every declaration belonging to the scope `statements` make up, including those in nested
+/// blocks but not those in subroutines or `TEST` blocks.
+pub fn declarations(statements: &[Spanned<Statement>]) -> Vec<(&Declare, &Span)> {
+    let mut found = vec![];
+    for (statement, span) in statements {
+        match statement {
+            Statement::Declare(declares) => {
+                found.extend(declares.iter().map(|declare| (declare, span)))
+            }
+            Statement::If(_, if_branch, else_branch) => {
+                found.extend(declarations(if_branch));
+                if let Some(else_branch) = else_branch {
+                    found.extend(declarations(else_branch));
+                }
+            }
+            Statement::For(.., statements)
+            | Statement::While(_, statements)
+            | Statement::Repeat(statements, _) => found.extend(declarations(statements)),
+            _ => {}
+        }
+    }
+    found
+}
+
+/// A name declared twice in one scope, which would otherwise only be noticed if both
+/// declarations ran. A declaration in a loop body runs once per iteration, so `evaluate` relies on
+/// this to tell that apart from a second declaration.
+pub fn resolve(statements: &[Spanned<Statement>]) -> Vec<Spanned<Execution>> {
+    let mut errors = vec![];
+    scope(&[], statements, &mut errors);
+    for (statement, _) in statements {
+        match statement {
+            Statement::Subroutine(subroutine) => {
+                scope(&subroutine.parameters, &subroutine.body, &mut errors)
+            }
+            Statement::Test(_, statements) => scope(&[], statements, &mut errors),
+            _ => {}
+        }
+    }
+    errors
+}
+
+fn scope(
+    parameters: &[Spanned<Parameter>],
+    statements: &[Spanned<Statement>],
+    errors: &mut Vec<Spanned<Execution>>,
+) {
+    let mut names = HashSet::new();
+    let parameters = parameters
+        .iter()
+        .map(|(parameter, span)| (parameter.name.as_str(), span));
+    let declared = declarations(statements)
+        .into_iter()
+        .map(|(declare, span)| (declare.identifier(), span));
+    for (name, span) in parameters.chain(declared) {
+        if !names.insert(name) {
+            errors.push((Execution::AlreadyDeclared(String::from(name)), span.clone()));
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Runs fragments of programs as exam questions give them, using variables they never declare.
+//! Whether each undeclared variable is an array, and its type, are worked out from how the
+//! fragment uses it, and it can be given a value before the fragment runs.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ast::*;
+use crate::evaluate::Scope;
+use crate::resolve::declarations;
+
+/// A value given to a variable before the fragment runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Initial {
+    Value(Literal),
+    /// The elements of an array, the first of them at the index given.
+    Array(isize, Vec<Literal>),
+}
+
+/// What the fragment's use of an undeclared variable says about it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Inferred {
+    pub array: bool,
+    /// `None` when nothing says, which is taken as an `INTEGER` unless a value is given.
+    pub literal_type: Option<LiteralType>,
+}
+
+/// The declared variables a part of the program can see, with the undeclared ones it uses.
+struct Inference<'a> {
+    subroutines: HashMap<&'a str, &'a Subroutine>,
+    globals: HashMap<&'a str, LiteralType>,
+    /// The parameters and locals of the subroutine being walked.
+    locals: HashMap<&'a str, LiteralType>,
+    /// What a `RETURN` in the subroutine being walked gives back.
+    returns: Option<LiteralType>,
+    found: BTreeMap<String, Inferred>,
+}
+
+/// Every variable `statements` use without declaring, by name, starting from the types of the
+/// values in `initial`.
+pub fn infer(
+    statements: &[Spanned<Statement>],
+    initial: &BTreeMap<String, Initial>,
+) -> BTreeMap<String, Inferred> {
+    let mut inference = Inference {
+        subroutines: statements
+            .iter()
+            .filter_map(|(statement, _)| match statement {
+                Statement::Subroutine(subroutine) => {
+                    Some((subroutine.name.as_str(), &**subroutine))
+                }
+                _ => None,
+            })
+            .collect(),
+        globals: declared(statements),
+        locals: HashMap::new(),
+        returns: None,
+        found: BTreeMap::new(),
+    };
+    for (name, given) in initial {
+        let literal_type = match given {
+            Initial::Value(value) => LiteralType::from(value),
+            Initial::Array(_, values) => type_of_values(values),
+        };
+        // text given may be meant for a CHAR or a DATE, which `convert` works out later
+        if literal_type != LiteralType::String {
+            inference.note(name, false, Some(literal_type));
+        }
+    }
+    inference.block(statements);
+    inference.found
+}
+
+/// The type of each variable declared in the scope `statements` make up.
+fn declared(statements: &[Spanned<Statement>]) -> HashMap<&str, LiteralType> {
+    declarations(statements)
+        .into_iter()
+        .map(|(declare, _)| match declare {
+            Declare::Literal(identifier, literal_type)
+            | Declare::Array(identifier, _, literal_type) => {
+                (identifier.as_str(), literal_type.clone())
+            }
+        })
+        .collect()
+}
+
+impl<'a> Inference<'a> {
+    /// The type of a declared variable, or what is known so far of an undeclared one.
+    fn type_of(&self, identifier: &str) -> Option<LiteralType> {
+        match self
+            .locals
+            .get(identifier)
+            .or_else(|| self.globals.get(identifier))
+        {
+            Some(literal_type) => Some(literal_type.clone()),
+            None => self.found.get(identifier)?.literal_type.clone(),
+        }
+    }
+
+    /// Notes a use of `identifier` where a value of type `expected` belongs, if it is undeclared.
+    fn note(&mut self, identifier: &str, array: bool, expected: Option<LiteralType>) {
+        if self.locals.contains_key(identifier)
+            || self.globals.contains_key(identifier)
+            || self.subroutines.contains_key(identifier)
+        {
+            return;
+        }
+        let inferred = self.found.entry(String::from(identifier)).or_default();
+        inferred.array |= array;
+        if inferred.literal_type.is_none() {
+            inferred.literal_type = expected;
+        }
+    }
+
+    fn block(&mut self, statements: &'a [Spanned<Statement>]) {
+        for (statement, _) in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &'a Statement) {
+        let boolean = || Some(LiteralType::Boolean);
+        match statement {
+            Statement::Declare(declares) => {
+                for declare in declares {
+                    if let Declare::Array(_, bounds, _) = declare {
+                        self.expression(&bounds.lower.0, Some(LiteralType::Integer));
+                        self.expression(&bounds.upper.0, Some(LiteralType::Integer));
+                    }
+                }
+            }
+            Statement::Assign(Assign::Literal(identifier, (value, _))) => {
+                self.assign(identifier, false, value)
+            }
+            Statement::Assign(Assign::Array(identifier, (index, _), (value, _))) => {
+                self.expression(index, Some(LiteralType::Integer));
+                self.assign(identifier, true, value);
+            }
+            Statement::In(identifier, index) => {
+                if let Some((index, _)) = index {
+                    self.expression(index, Some(LiteralType::Integer));
+                }
+                self.note(identifier, index.is_some(), None);
+            }
+            Statement::Out(values, _) => {
+                for (value, _) in values {
+                    self.expression(value, None);
+                }
+            }
+            Statement::If((conditional, _), if_branch, else_branch) => {
+                self.expression(conditional, boolean());
+                self.block(if_branch);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch);
+                }
+            }
+            Statement::For(identifier, (start, _), (end, _), statements) => {
+                self.note(identifier, false, Some(LiteralType::Integer));
+                self.expression(start, Some(LiteralType::Integer));
+                self.expression(end, Some(LiteralType::Integer));
+                self.block(statements);
+            }
+            Statement::While((conditional, _), statements)
+            | Statement::Repeat(statements, (conditional, _)) => {
+                self.expression(conditional, boolean());
+                self.block(statements);
+            }
+            Statement::Assert((conditional, _), _) => {
+                self.expression(conditional, boolean());
+            }
+            Statement::ProcedureCall(function, args) => {
+                self.call(function, args);
+            }
+            Statement::Return(value) => {
+                if let Some((value, _)) = value {
+                    self.expression(value, self.returns.clone());
+                }
+            }
+            Statement::Subroutine(subroutine) => {
+                self.locals = declared(&subroutine.body);
+                for (parameter, _) in &subroutine.parameters {
+                    self.locals
+                        .insert(&parameter.name, parameter.literal_type.clone());
+                }
+                self.returns = subroutine.returns.clone();
+                self.block(&subroutine.body);
+                self.locals.clear();
+                self.returns = None;
+            }
+            // a test is a program of its own, so runs without the fragment's values
+            Statement::Test(..) | Statement::Import(_) => {}
+        }
+    }
+
+    /// Notes an assignment to `identifier`, or to one of its elements, which makes whichever of
+    /// the variable and the value is typed give the other its type.
+    fn assign(&mut self, identifier: &str, array: bool, value: &Expression) {
+        let target = self.type_of(identifier);
+        let found = self.expression(value, target);
+        self.note(identifier, array, found.clone());
+        // one first taken for an INTEGER, such as by the whole number it was given, holds a REAL
+        // once one is assigned to it
+        if let (Some(inferred), Some(LiteralType::Real)) = (self.found.get_mut(identifier), found) {
+            if inferred.literal_type == Some(LiteralType::Integer) {
+                inferred.literal_type = Some(LiteralType::Real);
+            }
+        }
+    }
+
+    fn call(&mut self, function: &str, args: &[Spanned<Expression>]) -> Option<LiteralType> {
+        let Some(subroutine) = self.subroutines.get(function).copied() else {
+            let array = function == "LEN";
+            for (arg, _) in args {
+                match arg {
+                    Expression::Variable(identifier) if array => self.note(identifier, true, None),
+                    arg => {
+                        self.expression(arg, None);
+                    }
+                }
+            }
+            return array.then_some(LiteralType::Integer);
+        };
+        for ((parameter, _), (arg, _)) in subroutine.parameters.iter().zip(args) {
+            match arg {
+                Expression::Variable(identifier) => {
+                    let literal_type = Some(parameter.literal_type.clone());
+                    self.note(identifier, parameter.array, literal_type)
+                }
+                arg => {
+                    self.expression(arg, Some(parameter.literal_type.clone()));
+                }
+            }
+        }
+        subroutine.returns.clone()
+    }
+
+    /// Walks `expression`, where a value of type `expected` belongs, giving back its type when it
+    /// can be told.
+    fn expression(
+        &mut self,
+        expression: &Expression,
+        expected: Option<LiteralType>,
+    ) -> Option<LiteralType> {
+        use LiteralType::*;
+        match expression {
+            Expression::Value(value) => Some(LiteralType::from(value)),
+            Expression::Variable(identifier) => {
+                self.note(identifier, false, expected);
+                self.type_of(identifier)
+            }
+            Expression::ArrayIndex(identifier, index) => {
+                self.expression(&index.0, Some(Integer));
+                self.note(identifier, true, expected);
+                self.type_of(identifier)
+            }
+            Expression::FunctionCall(function, args) => self.call(function, args),
+            Expression::Negative(inner) => self.expression(&inner.0, expected),
+            Expression::Not(inner) => {
+                self.expression(&inner.0, Some(Boolean));
+                Some(Boolean)
+            }
+            Expression::Operate(op, a, b) => {
+                let operand = match op {
+                    Ops::And | Ops::Or => Some(Boolean),
+                    Ops::Concatenate => Some(String),
+                    Ops::Mod | Ops::Div => Some(Integer),
+                    op if COMPARE.contains(op) => None,
+                    _ => expected.filter(|literal_type| matches!(literal_type, Integer | Real)),
+                };
+                // either side being typed tells the other's type
+                let mut a_type = self.expression(&a.0, operand.clone());
+                let b_type = self.expression(&b.0, operand.clone().or(a_type.clone()));
+                if a_type.is_none() && b_type.is_some() {
+                    a_type = self.expression(&a.0, b_type.clone());
+                }
+                match op {
+                    Ops::Divide => Some(Real),
+                    op if COMPARE.contains(op) => Some(Boolean),
+                    Ops::Plus | Ops::Minus | Ops::Multiply => match (a_type, b_type) {
+                        (Some(Real), _) | (_, Some(Real)) => Some(Real),
+                        (Some(Integer), Some(Integer)) => Some(Integer),
+                        _ => None,
+                    },
+                    _ => operand,
+                }
+            }
+        }
+    }
+}
+
+/// The value `literal` gives a variable of type `literal_type`, unless it is the wrong type.
+fn convert(literal: &Literal, literal_type: &LiteralType) -> Result<Literal, Execution> {
+    let converted = match (literal, literal_type) {
+        (Literal::String(text), LiteralType::Char) if text.chars().count() == 1 => {
+            text.chars().next().map(Literal::Char)
+        }
+        (Literal::String(text), LiteralType::Date) => Date::parse(text).map(Literal::Date),
+        (literal, literal_type) => Some(literal.clone().widen(literal_type)),
+    };
+    match converted {
+        Some(converted) if &LiteralType::from(&converted) == literal_type => Ok(converted),
+        _ => Err(Execution::IncorrectType(
+            literal_type.into(),
+            LiteralType::from(literal).into(),
+        )),
+    }
+}
+
+/// The type of a variable given `values` when nothing else says: that of the values, a `REAL`
+/// if some are and the rest are `INTEGER`s.
+fn type_of_values(values: &[Literal]) -> LiteralType {
+    let types: Vec<LiteralType> = values.iter().map(LiteralType::from).collect();
+    if types.contains(&LiteralType::Real)
+        && types
+            .iter()
+            .all(|literal_type| matches!(literal_type, LiteralType::Integer | LiteralType::Real))
+    {
+        return LiteralType::Real;
+    }
+    types.into_iter().next().unwrap_or(LiteralType::Integer)
+}
+
+/// The main program's scope before `statements` run: a variable for each one they use without
+/// declaring, and for each given a value in `initial`, which a later `DECLARE` of the same name
+/// leaves as it is. Errors name the variable they are about.
+pub fn globals(
+    statements: &[Spanned<Statement>],
+    initial: &BTreeMap<String, Initial>,
+) -> Result<Scope, String> {
+    let inferred = infer(statements, initial);
+    let mut declared: HashMap<&str, &Declare> = HashMap::new();
+    for (declare, _) in declarations(statements) {
+        declared.entry(declare.identifier()).or_insert(declare);
+    }
+    let mut names: Vec<&String> = inferred.keys().chain(initial.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    let mut scope = Scope::default();
+    for name in names {
+        let fail = |error: Execution| format!("{}: {}", name, error);
+        let (array, known) = match (declared.get(name.as_str()), inferred.get(name)) {
+            (Some(Declare::Literal(_, literal_type)), _) => (false, Some(literal_type.clone())),
+            (Some(Declare::Array(_, _, literal_type)), _) => (true, Some(literal_type.clone())),
+            (None, Some(inferred)) => (inferred.array, inferred.literal_type.clone()),
+            (None, None) => (matches!(initial.get(name), Some(Initial::Array(..))), None),
+        };
+        let variable = match (initial.get(name), array) {
+            (None, false) => Variable::from(&known.unwrap_or(LiteralType::Integer)),
+            (None, true) => {
+                return Err(format!(
+                    "{} is used as an array but never declared, so its values have to be given",
+                    name
+                ))
+            }
+            (Some(Initial::Value(value)), false) => {
+                let literal_type = known.unwrap_or_else(|| LiteralType::from(value));
+                Variable::Literal {
+                    value: Some(convert(value, &literal_type).map_err(fail)?),
+                    literal_type,
+                    is_mutable: true,
+                }
+            }
+            (Some(Initial::Array(lower, values)), true) => {
+                let literal_type = known.unwrap_or_else(|| type_of_values(values));
+                let bounds = Bounds {
+                    lower: *lower,
+                    upper: lower + values.len() as isize - 1,
+                };
+                if values.is_empty() {
+                    return Err(fail(Execution::InvalidBounds(bounds)));
+                }
+                if let Some(Declare::Array(_, declared, _)) = declared.get(name.as_str()) {
+                    match declared.constant() {
+                        Some(declared) if declared != bounds => {
+                            return Err(format!(
+                                "{} is declared with bounds {}, but was given values for {}",
+                                name, declared, bounds
+                            ))
+                        }
+                        _ => {}
+                    }
+                }
+                let values = values
+                    .iter()
+                    .map(|value| convert(value, &literal_type).map(Some))
+                    .collect::<Result<_, _>>()
+                    .map_err(fail)?;
+                Variable::Array {
+                    literal_type,
+                    bounds,
+                    values,
+                }
+            }
+            (Some(Initial::Value(value)), true) => {
+                return Err(fail(Execution::IncorrectType(
+                    DataTypes::Array,
+                    LiteralType::from(value).into(),
+                )))
+            }
+            (Some(Initial::Array(..)), false) => {
+                let expected = known.unwrap_or(LiteralType::Integer);
+                return Err(fail(Execution::IncorrectType(
+                    expected.into(),
+                    DataTypes::Array,
+                )));
+            }
+        };
+        scope.variables.insert(name.clone(), variable);
+    }
+    Ok(scope)
+}
+
+/// Each variable of `scope` with its value, a line each in order of name, for showing where a
+/// fragment left off.
+pub fn summary(scope: &Scope) -> String {
+    let mut names: Vec<&String> = scope.variables.keys().collect();
+    names.sort();
+    let mut summary = String::new();
+    for name in names {
+        let value = match &scope.variables[name] {
+            Variable::Literal { value: None, .. } => String::from("not assigned"),
+            Variable::Literal {
+                value: Some(value), ..
+            } => value.source(),
+            Variable::Array { values, .. } => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|value| value.as_ref().map_or(String::from("?"), Literal::source))
+                    .collect();
+                format!("[{}]", values.join(", "))
+            }
+        };
+        summary += &format!("{} = {}\n", name, value);
+    }
+    summary
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+
+/// Which way a data couple carries its value between a module and the one it calls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// A parameter passed by value.
+    Down,
+    /// A function's return value.
+    Up,
+    /// A parameter passed by reference, which the called module can change.
+    Both,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Couple {
+    pub name: String,
+    pub direction: Direction,
+}
+
+/// A module calling another, with the data passed between them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Call {
+    pub from: usize,
+    pub to: usize,
+    pub couples: Vec<Couple>,
+    /// Whether the call is only made under an `IF`.
+    pub selection: bool,
+    /// Whether the call is made inside a loop.
+    pub iteration: bool,
+}
+
+/// The program's modules, indexed by position with the main program first, and their calls.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StructureChart {
+    pub modules: Vec<String>,
+    pub calls: Vec<Call>,
+}
+
+/// Charts which modules call which. `name` labels the main program, and calls to built-in
+/// functions are left out.
+pub fn structure_chart(name: &str, statements: &[Spanned<Statement>]) -> StructureChart {
+    let subroutines: Vec<&Subroutine> = statements
+        .iter()
+        .filter_map(|(statement, _)| match statement {
+            Statement::Subroutine(subroutine) => Some(&**subroutine),
+            _ => None,
+        })
+        .collect();
+    let mut chart = Charter {
+        chart: StructureChart {
+            modules: vec![String::from(name)],
+            calls: vec![],
+        },
+        modules: HashMap::new(),
+    };
+    for subroutine in &subroutines {
+        chart.modules.insert(
+            subroutine.name.clone(),
+            (chart.chart.modules.len(), *subroutine),
+        );
+        chart.chart.modules.push(subroutine.name.clone());
+    }
+
+    chart.block(0, statements, false, false);
+    for (index, subroutine) in subroutines.iter().enumerate() {
+        chart.block(index + 1, &subroutine.body, false, false);
+    }
+    chart.chart
+}
+
+struct Charter<'a> {
+    chart: StructureChart,
+    modules: HashMap<String, (usize, &'a Subroutine)>,
+}
+
+impl Charter<'_> {
+    fn call(&mut self, from: usize, name: &str, selection: bool, iteration: bool) {
+        let Some((to, subroutine)) = self.modules.get(name) else {
+            return;
+        };
+        let mut couples: Vec<Couple> = subroutine
+            .parameters
+            .iter()
+            .map(|(parameter, _)| Couple {
+                name: parameter.name.clone(),
+                direction: match parameter.passing {
+                    Passing::ByValue => Direction::Down,
+                    Passing::ByReference => Direction::Both,
+                },
+            })
+            .collect();
+        if subroutine.returns.is_some() {
+            couples.push(Couple {
+                name: subroutine.name.clone(),
+                direction: Direction::Up,
+            });
+        }
+        let call = Call {
+            from,
+            to: *to,
+            couples,
+            selection,
+            iteration,
+        };
+        if !self.chart.calls.contains(&call) {
+            self.chart.calls.push(call);
+        }
+    }
+
+    fn expression(
+        &mut self,
+        from: usize,
+        (expression, _): &Spanned<Expression>,
+        selection: bool,
+        iteration: bool,
+    ) {
+        match expression {
+            Expression::Value(_) | Expression::Variable(_) => {}
+            Expression::ArrayIndex(_, index) => self.expression(from, index, selection, iteration),
+            Expression::FunctionCall(name, args) => {
+                for arg in args {
+                    self.expression(from, arg, selection, iteration);
+                }
+                self.call(from, name, selection, iteration);
+            }
+            Expression::Negative(inner) | Expression::Not(inner) => {
+                self.expression(from, inner, selection, iteration)
+            }
+            Expression::Operate(_, a, b) => {
+                self.expression(from, a, selection, iteration);
+                self.expression(from, b, selection, iteration);
+            }
+        }
+    }
+
+    /// Records the calls `from` makes in `statements`. Subroutine definitions and `TEST` blocks
+    /// are skipped, since they are not part of the module they are written in.
+    fn block(
+        &mut self,
+        from: usize,
+        statements: &[Spanned<Statement>],
+        selection: bool,
+        iteration: bool,
+    ) {
+        for (statement, _) in statements {
+            let mut expressions = |expressions: &[&Spanned<Expression>], iteration: bool| {
+                for expression in expressions {
+                    self.expression(from, expression, selection, iteration);
+                }
+            };
+            match statement {
+                Statement::Declare(_)
+                | Statement::In(_, None)
+                | Statement::Subroutine(_)
+                | Statement::Test(..)
+                | Statement::Import(_) => {}
+                Statement::Assign(Assign::Literal(_, value)) => expressions(&[value], iteration),
+                Statement::Assign(Assign::Array(_, index, value)) => {
+                    expressions(&[index, value], iteration)
+                }
+                Statement::In(_, Some(index)) => expressions(&[index], iteration),
+                Statement::Out(values, _) => {
+                    expressions(&values.iter().collect::<Vec<_>>(), iteration)
+                }
+                Statement::ProcedureCall(name, args) => {
+                    expressions(&args.iter().collect::<Vec<_>>(), iteration);
+                    self.call(from, name, selection, iteration);
+                }
+                Statement::Return(value) => {
+                    expressions(&value.iter().collect::<Vec<_>>(), iteration)
+                }
+                Statement::Assert(conditional, _) => expressions(&[conditional], iteration),
+                Statement::If(conditional, if_branch, else_branch) => {
+                    expressions(&[conditional], iteration);
+                    self.block(from, if_branch, true, iteration);
+                    if let Some(else_branch) = else_branch {
+                        self.block(from, else_branch, true, iteration);
+                    }
+                }
+                Statement::For(_, start, end, statements) => {
+                    expressions(&[start, end], iteration);
+                    self.block(from, statements, selection, true);
+                }
+                Statement::While(conditional, statements)
+                | Statement::Repeat(statements, conditional) => {
+                    expressions(&[conditional], true);
+                    self.block(from, statements, selection, true);
+                }
+            }
+        }
+    }
+}
+
+impl StructureChart {
+    /// Graphviz DOT, with each call's couples as its label. A diamond at the caller's end marks a
+    /// call under selection and a ↻ marks one inside a loop.
+    pub fn dot(&self) -> String {
+        let escape = |label: &str| label.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut output = String::from(
+            "digraph structure {\n    node [shape=box, fontname=\"Helvetica\"];\n    edge [fontname=\"Helvetica\", fontsize=10];\n",
+        );
+        for (index, module) in self.modules.iter().enumerate() {
+            output.push_str(&format!("    m{} [label=\"{}\"];\n", index, escape(module)));
+        }
+        for call in &self.calls {
+            let mut attributes = vec![];
+            if !call.couples.is_empty() {
+                let couples: Vec<String> = call
+                    .couples
+                    .iter()
+                    .map(|couple| {
+                        let arrow = match couple.direction {
+                            Direction::Down => "↓",
+                            Direction::Up => "↑",
+                            Direction::Both => "↕",
+                        };
+                        format!("{} {}", arrow, escape(&couple.name))
+                    })
+                    .collect();
+                attributes.push(format!("label=\"{}\"", couples.join("\\n")));
+            }
+            if call.selection {
+                attributes.push(String::from("dir=both, arrowtail=diamond"));
+            }
+            if call.iteration {
+                attributes.push(String::from("taillabel=\"↻\""));
+            }
+            let attributes = if attributes.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", attributes.join(", "))
+            };
+            output.push_str(&format!(
+                "    m{} -> m{}{};\n",
+                call.from, call.to, attributes
+            ));
+        }
+        output.push_str("}\n");
+        output
+    }
+}
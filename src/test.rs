@@ -1,44 +1,1078 @@
-#[cfg(test)]
-mod test {
-    use crate::parse_and_run;
-
-    #[cfg(not(windows))]
-    macro_rules! main_separator {
-        () => {
-            "/"
-        };
-    }
-
-    #[cfg(windows)]
-    macro_rules! main_separator {
-        () => {
-            r#"\"#
-        };
-    }
-
-    macro_rules! example {
-        ($file_name: expr) => {
-            parse_and_run(
-                include_str!(concat!(
-                    "..",
-                    main_separator!(),
-                    "examples",
-                    main_separator!(),
-                    $file_name
-                ))
-                .to_owned(),
-                $file_name.to_owned(),
-            )
-            .unwrap()
-        };
-    }
-    #[test]
-    fn examples() {
-        example!("bubbleSort.psps");
-    }
-    #[test]
-    #[should_panic]
-    fn not_assigned(){
-        example!("error.psps");
+use crate::ast::*;
+use crate::c::c;
+use crate::check::check;
+use crate::dialect::Dialect;
+use crate::evaluate::{evaluate, operate, with_stack, InputPolicy, Io, Limits, State, MAX_CALL_DEPTH};
+use crate::flowchart;
+use crate::format::{format, Options};
+use crate::lexer::{lexer, lexer_for, strip_trivia};
+use crate::lint::{lint, Rule};
+use crate::snippet::{globals, infer, summary, Inferred, Initial};
+use crate::{golden, grade, import, initial_values, parse_and_run, settings, Settings};
+use crate::parser::{parser, parser_for};
+use crate::java::java;
+use crate::lower::{by_reference_errors, lower};
+use crate::python::python;
+use crate::structure::{structure_chart, Direction};
+use crate::vb::vb;
+use chumsky::{Parser, Stream};
+use rand::{rngs::StdRng, SeedableRng};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[cfg(not(windows))]
+macro_rules! main_separator {
+    () => {
+        "/"
+    };
+}
+
+#[cfg(windows)]
+macro_rules! main_separator {
+    () => {
+        r#"\"#
+    };
+}
+
+macro_rules! example {
+    ($file_name: expr) => {
+        parse_and_run(
+            include_str!(concat!(
+                "..",
+                main_separator!(),
+                "examples",
+                main_separator!(),
+                $file_name
+            ))
+            .to_owned(),
+            &std::path::Path::new("examples").join($file_name),
+            &Settings::default(),
+            None,
+        )
+        .unwrap()
+    };
+}
+#[test]
+fn examples() {
+    example!("bubbleSort.psps");
+    example!("subroutines.psps");
+}
+#[test]
+#[should_panic]
+fn not_assigned(){
+    example!("error.psps");
+}
+
+fn parse_source(source: &str) -> (Vec<Spanned<Token>>, Vec<Spanned<Statement>>) {
+    let tokens = lexer().parse(source).unwrap();
+    let end = source.chars().count();
+    let parsed = parser()
+        .parse(Stream::from_iter(end..end + 1, strip_trivia(tokens.clone()).into_iter()))
+        .unwrap();
+    (tokens, parsed)
+}
+
+fn check_source(source: &str) -> Vec<Execution> {
+    check(&parse_source(source).1, Dialect::Lenient).errors.into_iter().map(|(error, _)| error).collect()
+}
+
+fn format_source(source: &str) -> String {
+    let (tokens, parsed) = parse_source(source);
+    format(source, &tokens, &parsed, &Options::default())
+}
+
+#[test]
+fn check_examples() {
+    for source in [
+        include_str!("../examples/bubbleSort.psps"),
+        include_str!("../examples/password.psps"),
+        include_str!("../examples/testing.psps"),
+        include_str!("../examples/subroutines.psps"),
+    ] {
+        assert_eq!(check_source(source), vec![]);
     }
 }
+
+#[test]
+fn check_type_errors() {
+    use DataTypes::Literal as L;
+    assert_eq!(
+        check_source("DECLARE x : INTEGER\nx <- \"one\"\nOUTPUT y\nIF x THEN\nOUTPUT LEN(x)\nENDIF"),
+        vec![
+            Execution::IncorrectType(L(LiteralType::String), L(LiteralType::Integer)),
+            Execution::NotFound(String::from("y")),
+            Execution::IncorrectType(L(LiteralType::Boolean), L(LiteralType::Integer)),
+            Execution::IncorrectType(DataTypes::Array, L(LiteralType::Integer)),
+        ]
+    );
+}
+
+#[test]
+fn language_server() {
+    use crate::lsp::{analyse, handle_request, offset, position, word_at};
+    use lsp_server::{ErrorCode, Request, RequestId};
+    use lsp_types::{CompletionResponse, HoverContents, Position, Url};
+    use std::collections::HashMap;
+
+    let source = "DECLARE x : INTEGER\nx ← 1\nPROCEDURE Show(x : STRING)\n    OUTPUT x\nENDPROCEDURE\nOUTPUT x";
+    // `←` is one UTF-16 unit, and a position past a line's end is that line's end
+    assert_eq!(position(source, 22), Position::new(1, 2));
+    assert_eq!(offset(source, Position::new(1, 2)), 22);
+    assert_eq!(offset(source, Position::new(1, 40)), 25);
+    assert_eq!(offset(source, Position::new(9, 0)), source.chars().count());
+    for at in [0, 10, 26, source.chars().count()] {
+        assert_eq!(offset(source, position(source, at)), at);
+    }
+    assert_eq!(word_at(source, 8), Some(String::from("x")));
+    assert_eq!(word_at(source, 4), Some(String::from("DECLARE")));
+    assert_eq!(word_at(source, 9), Some(String::from("x")));
+    assert_eq!(word_at(source, 10), None);
+
+    let analysis = analyse(String::from(source), Dialect::Lenient);
+    let hover = |line, character| match analysis.hover(Position::new(line, character)) {
+        Some(hover) => match hover.contents {
+            HoverContents::Markup(markup) => markup.value,
+            _ => unreachable!(),
+        },
+        None => String::new(),
+    };
+    assert!(hover(3, 11).contains("DECLARE x : STRING"));
+    assert!(hover(5, 7).contains("DECLARE x : INTEGER"));
+    assert!(hover(0, 0).is_empty());
+
+    // a half typed line keeps what was known while it last parsed
+    let typing = analyse(format!("{}\nOUTPUT x +", source), Dialect::Lenient);
+    let typing = typing.keeping(Some(analyse(String::from(source), Dialect::Lenient)));
+    assert!(typing.hover(Position::new(5, 7)).is_some());
+    assert!(typing.completion(Position::new(6, 0)).iter().any(|item| item.label == "x"));
+
+    let uri = Url::parse("file:///program.psps").unwrap();
+    let line = |line, character| {
+        let location = analysis.definition(uri.clone(), Position::new(line, character));
+        location.map(|location| location.range.start.line)
+    };
+    assert_eq!(line(3, 11), Some(2));
+    assert_eq!(line(5, 7), Some(0));
+
+    let labels = |analysis: &crate::lsp::Analysis, position| -> Vec<String> {
+        analysis.completion(position).into_iter().map(|item| item.label).collect()
+    };
+    let labels_at = labels(&analysis, Position::new(5, 0));
+    for label in ["WHILE", "RANDOMBETWEEN", "LEN", "x"] {
+        assert!(labels_at.contains(&String::from(label)), "{} not offered", label);
+    }
+    let aqa = analyse(String::from("OUTPUT 1"), Dialect::Aqa);
+    let labels_at = labels(&aqa, Position::new(0, 0));
+    assert!(labels_at.contains(&String::from("RANDOM_INT")));
+    assert!(labels_at.contains(&String::from("USERINPUT")));
+    assert!(!labels_at.contains(&String::from("RANDOMBETWEEN")));
+
+    let documents = HashMap::from([(uri.clone(), analysis)]);
+    let request = |method: &str, params| Request::new(RequestId::from(1), String::from(method), params);
+    let response = handle_request(
+        &documents,
+        request(
+            "textDocument/completion",
+            serde_json::json!({"textDocument": {"uri": uri}, "position": {"line": 0, "character": 0}}),
+        ),
+    );
+    let items: CompletionResponse = serde_json::from_value(response.result.unwrap()).unwrap();
+    assert!(matches!(items, CompletionResponse::Array(items) if !items.is_empty()));
+    let response = handle_request(&documents, request("textDocument/hover", serde_json::json!({"position": 3})));
+    assert_eq!(response.error.unwrap().code, ErrorCode::InvalidParams as i32);
+    let response = handle_request(&documents, request("textDocument/rename", serde_json::Value::Null));
+    assert_eq!(response.error.unwrap().code, ErrorCode::MethodNotFound as i32);
+}
+
+#[test]
+fn format_idempotent() {
+    for source in [
+        include_str!("../examples/bubbleSort.psps"),
+        include_str!("../examples/password.psps"),
+        include_str!("../examples/testing.psps"),
+        include_str!("../examples/subroutines.psps"),
+    ] {
+        let formatted = format_source(source);
+        assert_eq!(format_source(&formatted), formatted);
+    }
+}
+
+#[test]
+fn format_brackets() {
+    assert_eq!(
+        format_source("DECLARE x:BOOLEAN\n  x<-(NOT x) OR (1 = 2 - (3 - 4)) // keep\n"),
+        "DECLARE x : BOOLEAN\nx ← NOT x OR 1 = 2 - (3 - 4) // keep\n"
+    );
+    assert_eq!(
+        format_source("DECLARE x : BOOLEAN\nx ← NOT (x AND (x OR x)) AND NOT -1 = 2\n"),
+        "DECLARE x : BOOLEAN\nx ← NOT (x AND (x OR x)) AND NOT -1 = 2\n"
+    );
+}
+
+#[test]
+fn lint_rules() {
+    let source = "// lint-disable: L005\nDECLARE n : INTEGER\nDECLARE TOTAL : INTEGER\nFOR n <- 1 TO 3\n    OUTPUT later\nNEXT\nFOR n <- 1 TO 3\n    RETURN n\nENDFOR\nDECLARE later : INTEGER\n";
+    let (tokens, parsed) = parse_source(source);
+    let rules: Vec<Rule> = lint(&tokens, Some(&parsed), Dialect::Lenient).into_iter().map(|lint| lint.rule).collect();
+    assert_eq!(
+        rules,
+        vec![
+            Rule::IdentifierCase,
+            Rule::UsedBeforeDeclare,
+            Rule::NextWithoutIdentifier,
+            Rule::ReturnOutsideSubroutine,
+            Rule::InconsistentForTerminator,
+        ]
+    );
+
+    let tokens = lexer().parse("if x THEN").unwrap();
+    let rules: Vec<Rule> = lint(&tokens, None, Dialect::Lenient).into_iter().map(|lint| lint.rule).collect();
+    assert_eq!(rules, vec![Rule::LowercaseKeyword]);
+
+    // each board's own spelling is the right one
+    let tokens = lexer_for(Dialect::OcrErl).parse("print(x)
+Print(x)").unwrap();
+    let lints = lint(&tokens, None, Dialect::OcrErl);
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].message, "keyword `Print` should be written as `print`");
+}
+
+fn parses_in(dialect: Dialect, source: &str) -> bool {
+    let Ok(tokens) = lexer_for(dialect).parse(source) else {
+        return false;
+    };
+    let end = source.chars().count();
+    parser_for(dialect)
+        .parse(Stream::from_iter(end..end + 1, strip_trivia(tokens).into_iter()))
+        .is_ok()
+}
+
+#[test]
+fn dialects() {
+    let cambridge = "DECLARE i : INTEGER\nFOR i ← 1 TO 3\n    OUTPUT i\nNEXT i\n";
+    let aqa = "DECLARE i : INTEGER\nFOR i ← 1 TO 3\n    OUTPUT i ≠ 2\nENDFOR\ni ← USERINPUT\n";
+    let ocr = "DECLARE i : INTEGER\nfor i = 1 to 3\n    print(i == 2)\nnext i\ndo\n    i = i - 1\nuntil i != 0\n";
+    for (dialect, source) in [
+        (Dialect::Lenient, cambridge),
+        (Dialect::Cambridge9618, cambridge),
+        (Dialect::Igcse0478, cambridge),
+        (Dialect::Aqa, aqa),
+        (Dialect::OcrErl, ocr),
+    ] {
+        assert!(parses_in(dialect, source), "{} rejected\n{}", dialect.name(), source);
+    }
+
+    for source in [
+        "DECLARE i : INTEGER\ni <- 1\n",
+        "DECLARE i : INTEGER\nFOR i ← 1 TO 3\n    OUTPUT i\nNEXT\n",
+        "DECLARE i : INTEGER\nFOR i ← 1 TO 3\n    OUTPUT i\nENDFOR\n",
+        "DECLARE i : INTEGER\nWHILE i < 3 DO\n    i ← i + 1\nENDWHILE\n",
+        "DECLARE i : INTEGER\ni ← INPUT\n",
+    ] {
+        assert!(!parses_in(Dialect::Cambridge9618, source), "9618 accepted\n{}", source);
+        assert!(parses_in(Dialect::Lenient, source), "lenient rejected\n{}", source);
+    }
+    assert!(!parses_in(Dialect::Igcse0478, "DECLARE i : INTEGER\nWHILE i < 3\n    i ← i + 1\nENDWHILE\n"));
+    assert!(!parses_in(Dialect::OcrErl, "DECLARE i : INTEGER\ni ← 1\n"));
+
+    assert!(Dialect::Aqa.built_ins().contains_key("RANDOM_INT"));
+    assert!(!Dialect::Cambridge9618.built_ins().contains_key("RANDOMBETWEEN"));
+    let (_, parsed) = parse_source("DECLARE n : INTEGER\nn ← RANDOMBETWEEN(1, 6)\n");
+    assert_eq!(
+        check(&parsed, Dialect::Igcse0478).errors[0].0,
+        Execution::NotFound(String::from("RANDOMBETWEEN"))
+    );
+}
+
+#[test]
+fn operators() {
+    use Literal::{Bool, Integer, Real};
+    let left = [Integer(7), Real(3.5), Literal::String(String::from("b")), Bool(true)];
+    let right = [Integer(2), Real(0.5), Literal::String(String::from("a")), Bool(false)];
+    let ordered = |a: usize, b: usize| a == b || a + b == 1;
+    let table = [
+        (Ops::Plus, [[Integer(9), Real(7.5)], [Real(5.5), Real(4.0)]]),
+        (Ops::Minus, [[Integer(5), Real(6.5)], [Real(1.5), Real(3.0)]]),
+        (Ops::Multiply, [[Integer(14), Real(3.5)], [Real(7.0), Real(1.75)]]),
+        (Ops::Divide, [[Real(3.5), Real(14.0)], [Real(1.75), Real(7.0)]]),
+        (Ops::Div, [[Integer(3), Integer(14)], [Integer(1), Integer(7)]]),
+    ];
+    let ops = [
+        Ops::Plus, Ops::Minus, Ops::Divide, Ops::Multiply, Ops::Concatenate, Ops::GreaterThan,
+        Ops::LessThan, Ops::GreaterThanEqual, Ops::LessThanEqual, Ops::Equal, Ops::NotEqual,
+        Ops::Mod, Ops::Div, Ops::And, Ops::Or,
+    ];
+    for op in &ops {
+        for (i, a) in left.iter().enumerate() {
+            for (j, b) in right.iter().enumerate() {
+                let expected = match op {
+                    Ops::GreaterThan | Ops::GreaterThanEqual | Ops::NotEqual if ordered(i, j) => Some(Bool(true)),
+                    Ops::LessThan | Ops::LessThanEqual | Ops::Equal if ordered(i, j) => Some(Bool(false)),
+                    Ops::Mod if (i, j) == (0, 0) => Some(Integer(1)),
+                    Ops::Concatenate if (i, j) == (2, 2) => Some(Literal::String(String::from("ba"))),
+                    Ops::And if (i, j) == (3, 3) => Some(Bool(false)),
+                    Ops::Or if (i, j) == (3, 3) => Some(Bool(true)),
+                    _ if i < 2 && j < 2 => table
+                        .iter()
+                        .find(|(arithmetic, _)| arithmetic == op)
+                        .map(|(_, results)| results[i][j].clone()),
+                    _ => None,
+                };
+                let result = operate(op, a, b);
+                match expected {
+                    Some(expected) => assert_eq!(result, Ok(expected), "{:?} {} {}", op, a, b),
+                    None => assert!(
+                        matches!(result, Err(Execution::BinaryNotSupported(..))),
+                        "{:?} {} {} gave {:?}", op, a, b, result
+                    ),
+                }
+            }
+        }
+    }
+
+    for (a, b) in [(Integer(2), Real(2.0)), (Real(2.5), Real(2.5)), (left[2].clone(), left[2].clone())] {
+        assert_eq!(operate(&Ops::Equal, &a, &b), Ok(Bool(true)));
+        assert_eq!(operate(&Ops::GreaterThanEqual, &a, &b), Ok(Bool(true)));
+        assert_eq!(operate(&Ops::LessThanEqual, &b, &a), Ok(Bool(true)));
+        assert_eq!(operate(&Ops::LessThan, &a, &b), Ok(Bool(false)));
+    }
+
+    let widened = "DECLARE r : REAL\nDECLARE a : ARRAY[1:2] OF REAL\nr ← 3\na[1] ← r + 1\nASSERT r / 2 = 1.5\nASSERT a[1] >= 4\n";
+    assert!(run_source(widened, Limits::default()).is_ok());
+    assert_eq!(check_source("DECLARE r : REAL\nDECLARE i : INTEGER\nr ← 3\ni ← 2.5\n").len(), 1);
+}
+
+#[test]
+fn arithmetic_faults() {
+    let run = |expression: &str| {
+        run_source(&format!("DECLARE big : INTEGER\nbig ← 9223372036854775807\nOUTPUT {}\n", expression), Limits::default())
+            .map(|_| ())
+    };
+    let by_zero = |dividend: &str| Err(Execution::DivisionByZero(String::from(dividend)));
+    assert_eq!(run("5 DIV 0"), by_zero("5"));
+    assert_eq!(run("5 MOD 0"), by_zero("5"));
+    assert_eq!(run("5 / 0"), by_zero("5"));
+    assert_eq!(run("2.5 / 0"), by_zero("2.5"));
+    assert_eq!(run("2.5 DIV 0.0"), by_zero("2.5"));
+    assert_eq!(run("big + 1"), Err(Execution::Overflow(Ops::Plus)));
+    assert_eq!(run("big * 2"), Err(Execution::Overflow(Ops::Multiply)));
+    assert_eq!(run("-big - 2"), Err(Execution::Overflow(Ops::Minus)));
+    assert_eq!(run("(-big - 1) DIV -1"), Err(Execution::Overflow(Ops::Div)));
+    assert_eq!(run("RANDOMBETWEEN(3, 3)"), Err(Execution::EmptyRange(String::from("RANDOMBETWEEN"), 3, 3)));
+
+    assert!(parses_in(Dialect::Lenient, "OUTPUT 9223372036854775807\n"));
+    assert!(!parses_in(Dialect::Lenient, "OUTPUT 9223372036854775808\n"));
+    assert!(!parses_in(Dialect::Lenient, &format!("OUTPUT {}.0\n", "9".repeat(400))));
+    assert!(!parses_in(Dialect::Lenient, "DECLARE a : ARRAY[1:99999999999999999999] OF INTEGER\n"));
+}
+
+#[test]
+fn precedence() {
+    let source = "DECLARE a : ARRAY[1:3] OF INTEGER\nDECLARE i : INTEGER\na[1] ← 1\na[2] ← 2\na[3] ← 3\ni ← 4\nASSERT 1 < 2 AND 3 < 4\nASSERT NOT 1 = 2\nASSERT TRUE OR FALSE AND FALSE\nASSERT NOT FALSE AND FALSE = FALSE\nASSERT NOT (i <= 3 AND a[i] > 0)\nASSERT i > 3 OR a[i] > 0\n";
+    assert!(run_source(source, Limits::default()).is_ok());
+    assert_eq!(
+        run_source(&source.replace("i > 3 OR", "i > 4 OR"), Limits::default()).unwrap_err(),
+        Execution::OutOfBounds(String::from("a"), 4, Bounds { lower: 1, upper: 3 })
+    );
+
+    let (_, parsed) = parse_source("OUTPUT NOT x = y AND z\n");
+    let Statement::Out(expressions, _) = &parsed[0].0 else {
+        panic!("expected OUTPUT");
+    };
+    let Expression::Operate(Ops::And, not, _) = &expressions[0].0 else {
+        panic!("expected AND at the top");
+    };
+    assert!(matches!(&not.0, Expression::Not(inner) if matches!(inner.0, Expression::Operate(Ops::Equal, ..))));
+}
+
+fn run_source(source: &str, limits: Limits) -> Result<State, Execution> {
+    let settings = Settings {
+        limits,
+        ..Settings::default()
+    };
+    let state = settings.state(0, Io::Console);
+    evaluate(&parse_source(source).1, state, false).map_err(|(error, _)| error)
+}
+
+#[test]
+fn execution_limits() {
+    let forever = "DECLARE x : INTEGER\nx ← 0\nWHILE TRUE DO\n    x ← x + 1\nENDWHILE\n";
+    let steps = Limits {
+        steps: Some(1000),
+        ..Limits::default()
+    };
+    assert_eq!(run_source(forever, steps).unwrap_err(), Execution::StepLimit(1000));
+
+    let time = Duration::from_millis(50);
+    let timed = Limits {
+        time: Some(time),
+        ..Limits::default()
+    };
+    assert_eq!(run_source(forever, timed).unwrap_err(), Execution::TimeLimit(time));
+    for timeout in ["-1", "0", "NaN", "inf", "1e300"] {
+        assert!(settings(&[format!("--timeout={}", timeout)]).is_err(), "{} accepted", timeout);
+    }
+    assert_eq!(
+        settings(&[String::from("--timeout=0.5")]).unwrap().limits.time,
+        Some(Duration::from_millis(500))
+    );
+
+    // only calls count towards the depth, however deeply the blocks around them nest
+    let countdown = |n: usize| {
+        format!(
+            "FUNCTION Down(n : INTEGER) RETURNS INTEGER\n    DECLARE i : INTEGER\n    FOR i ← 1 TO 1\n        WHILE TRUE DO\n            REPEAT\n                IF n > 0\n                  THEN\n                    IF TRUE\n                      THEN\n                        RETURN Down(n - 1)\n                    ENDIF\n                ENDIF\n            UNTIL TRUE\n            RETURN 0\n        ENDWHILE\n    NEXT i\nENDFUNCTION\nOUTPUT Down({})\n",
+            n
+        )
+    };
+    let shallow = Limits {
+        call_depth: Some(4),
+        ..Limits::default()
+    };
+    assert!(run_source(&countdown(3), shallow.clone()).is_ok());
+    let shallower = Limits {
+        call_depth: Some(3),
+        ..shallow
+    };
+    assert_eq!(run_source(&countdown(3), shallower).unwrap_err(), Execution::CallDepthLimit(3));
+
+    // the deepest allowed recursion fits the stack runs are given, and one call more is an error
+    let deepest = Limits {
+        call_depth: Some(MAX_CALL_DEPTH),
+        ..Limits::default()
+    };
+    assert_eq!(deepest.fit_stack(), Ok(()));
+    let run = |n, limits: &Limits| with_stack(|| run_source(&countdown(n), limits.clone()).map(|_| ()));
+    assert_eq!(run(MAX_CALL_DEPTH - 1, &deepest), Ok(()));
+    assert_eq!(run(MAX_CALL_DEPTH, &deepest), Err(Execution::CallDepthLimit(MAX_CALL_DEPTH)));
+    assert_eq!(run(150, &Limits::default()), Ok(()));
+    let deeper = Limits {
+        call_depth: Some(MAX_CALL_DEPTH + 1),
+        ..Limits::default()
+    };
+    assert!(deeper.fit_stack().is_err());
+
+    let small = Limits {
+        array_size: Some(10),
+        ..Limits::default()
+    };
+    let arrays = "DECLARE a : ARRAY[1:5] OF INTEGER\nDECLARE b : ARRAY[1:5] OF INTEGER\n";
+    assert!(run_source(arrays, small.clone()).is_ok());
+    let too_many = format!("{}DECLARE c : ARRAY[1:2] OF INTEGER\n", arrays);
+    assert_eq!(run_source(&too_many, small).unwrap_err(), Execution::ArraySizeLimit(10));
+    let huge = "DECLARE a : ARRAY[0:100000000000] OF INTEGER\n";
+    assert_eq!(
+        run_source(huge, Limits::default()).unwrap_err(),
+        Execution::ArraySizeLimit(10_000_000)
+    );
+}
+
+#[test]
+fn seeded_random() {
+    let Some(Function::Random(randombetween)) = Dialect::Lenient.built_ins().remove("RANDOMBETWEEN")
+    else {
+        panic!("RANDOMBETWEEN should use the seeded generator");
+    };
+    let draw = |seed| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..20)
+            .map(|_| randombetween(vec![Literal::Integer(0), Literal::Integer(1000)], &mut rng).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(draw(42), draw(42));
+    assert_ne!(draw(42), draw(43));
+}
+
+#[test]
+fn golden_examples() {
+    let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    for case in golden::cases(&examples).unwrap() {
+        let report = golden::run_case(&case, &Settings::default()).unwrap();
+        assert!(
+            !matches!(report.outcome, Some(golden::Outcome::Failed(_))),
+            "{}: {:?}",
+            case.display(),
+            report.outcome
+        );
+        assert!(report.tests.iter().all(|test| test.failure.is_none()), "{}", case.display());
+    }
+
+    assert_eq!(golden::diff("a\nb\n", "a\r\nb\r\n"), "");
+    assert_eq!(golden::diff("a\nb\n", "a\nc\n"), "  line 2\n    expected: \"b\"\n    found:    \"c\"");
+    assert_eq!(
+        golden::diff("no break", "no break\n"),
+        "  end of output\n    expected: no line ending\n    found:    a line ending"
+    );
+}
+
+#[test]
+fn test_blocks() {
+    let source = "DECLARE x : INTEGER\nx ← 1\nTEST \"sees nothing outside\"\n    DECLARE x : INTEGER\n    x ← 2\n    ASSERT x = 2\nENDTEST\nTEST \"fails\"\n    ASSERT 1 = 2, \"one is not two\"\nENDTEST\n";
+    let (_, parsed) = parse_source(source);
+    let results = golden::run_tests(&parsed, &Settings::default());
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].failure, None);
+    let (error, span) = results[1].failure.as_ref().unwrap();
+    assert_eq!(error, &Execution::AssertionFailed(Some(String::from("one is not two"))));
+    assert_eq!(golden::line_of(source, span.start), 9);
+
+    assert!(run_source(source, Limits::default()).is_ok());
+    assert_eq!(
+        run_source("ASSERT FALSE\n", Limits::default()).unwrap_err(),
+        Execution::AssertionFailed(None)
+    );
+}
+
+#[test]
+fn grading() {
+    let rubric = grade::Rubric::parse(
+        "[limits]\nsteps = 1000\n\n[[tests]]\nname = \"echo\"\ninput = \"hi\"\noutput = \"hi  \\n\"\nmarks = 2\n\n[[checks]]\nname = \"repeat\"\nrequires = \"REPEAT\"\nmarks = 1\n\n[[checks]]\nname = \"no mod\"\nforbids = \"MOD\"\nmarks = 1\n",
+    )
+    .unwrap();
+    assert_eq!(rubric.total(), 4);
+
+    let echo = grade::grade(&rubric, "echo", "DECLARE s : STRING\nINPUT s\nOUTPUT s\n");
+    let marks: Vec<u32> = echo.tests.iter().chain(&echo.checks).map(|mark| mark.awarded).collect();
+    assert_eq!((marks, echo.score), (vec![2, 0, 1], 3));
+
+    let stuck = grade::grade(&rubric, "stuck", "REPEAT\n    OUTPUT 1 MOD 2\nUNTIL FALSE\n");
+    let marks: Vec<u32> = stuck.tests.iter().chain(&stuck.checks).map(|mark| mark.awarded).collect();
+    assert_eq!((marks, stuck.score), (vec![0, 1, 0], 1));
+
+    assert!(grade::grade(&rubric, "broken", "DECLARE\n").error.is_some());
+    assert_eq!(
+        grade::csv(&rubric, &[echo]).lines().collect::<Vec<_>>(),
+        vec!["student,echo,repeat,no mod,score,total", "echo,2,0,1,3,4"]
+    );
+    assert!(grade::Rubric::parse("[[checks]]\nname = \"both\"\nmarks = 1\n").is_err());
+    for timeout in ["-1.0", "0.0", "nan", "inf", "1e300"] {
+        let rubric = format!("[limits]\ntimeout = {}\n", timeout);
+        assert!(grade::Rubric::parse(&rubric).unwrap_err().starts_with("timeout is invalid"), "{}", timeout);
+    }
+    assert!(grade::Rubric::parse(&format!("[limits]\ncall_depth = {}\n", MAX_CALL_DEPTH + 1)).is_err());
+}
+
+#[test]
+fn flowcharts() {
+    let (_, parsed) = parse_source(
+        "DECLARE n : INTEGER\nINPUT n\nIF n > 0\n  THEN\n    OUTPUT n\nENDIF\nREPEAT\n    n ← n - 1\nUNTIL n = 0\n",
+    );
+    let chart = flowchart::flowchart(&parsed);
+    let shapes: Vec<flowchart::Shape> = chart.nodes.iter().map(|node| node.shape).collect();
+    use flowchart::Shape::*;
+    assert_eq!(shapes, vec![Terminator, InputOutput, Decision, InputOutput, Process, Decision, Terminator]);
+    let edges: Vec<(usize, usize, Option<&str>)> =
+        chart.edges.iter().map(|edge| (edge.from, edge.to, edge.label)).collect();
+    assert_eq!(
+        edges,
+        vec![
+            (0, 1, None),
+            (1, 2, None),
+            (2, 3, Some("Yes")),
+            (3, 4, None),
+            (2, 4, Some("No")),
+            (4, 5, None),
+            (5, 4, Some("No")),
+            (5, 6, Some("Yes")),
+        ]
+    );
+    assert!(chart.dot().contains("    n2 [shape=diamond, label=\"n > 0?\"];\n"));
+    assert!(chart.mermaid().contains("    n3[/\"OUTPUT n\"/]\n"));
+}
+
+#[test]
+fn subroutines() {
+    let program = |body: &str| {
+        format!(
+            "PROCEDURE Swap(BYREF x : INTEGER, y : INTEGER)\n    DECLARE t : INTEGER\n    t ← x\n    x ← y\n    y ← t\nENDPROCEDURE\nFUNCTION FirstOver(limit : INTEGER) RETURNS INTEGER\n    DECLARE i : INTEGER\n    FOR i ← 1 TO 100\n        IF i > limit\n          THEN\n            RETURN i\n        ENDIF\n    NEXT i\n    RETURN 0\nENDFUNCTION\nDECLARE a : INTEGER\nDECLARE b : INTEGER\na ← 1\nb ← 2\n{}",
+            body
+        )
+    };
+    let run = |body: &str| run_source(&program(body), Limits::default()).map(|_| ());
+    assert_eq!(run("CALL Swap(a, b)\nASSERT (a = 2) AND (b = 1)\nASSERT FirstOver(a + 3) = 6\n"), Ok(()));
+    assert_eq!(run("CALL Swap(a, 3)\n"), Err(Execution::ByReference(String::from("y"))));
+    assert_eq!(run("a ← Swap(a, b)\n"), Err(Execution::NoValue(String::from("Swap"))));
+    assert_eq!(
+        run("CALL Swap(a)\n"),
+        Err(Execution::IncorrectNumberArguments(String::from("Swap"), 2, 1))
+    );
+    assert_eq!(run("OUTPUT t\nCALL Swap(a, b)\n"), Err(Execution::NotFound(String::from("t"))));
+    assert_eq!(run("RETURN a\n"), Err(Execution::CanNotCallReturn));
+
+    let forever = "PROCEDURE Forever\n    CALL Forever\nENDPROCEDURE\nCALL Forever\n";
+    let shallow = Limits {
+        call_depth: Some(50),
+        ..Limits::default()
+    };
+    assert_eq!(run_source(forever, shallow).unwrap_err(), Execution::CallDepthLimit(50));
+
+    use DataTypes::Literal as L;
+    assert_eq!(
+        check_source(&program("CALL Swap(a, 3)\nb ← FirstOver(TRUE)\nCALL Missing\n")),
+        vec![
+            Execution::ByReference(String::from("y")),
+            Execution::IncorrectType(L(LiteralType::Integer), L(LiteralType::Boolean)),
+            Execution::NotFound(String::from("Missing")),
+        ]
+    );
+    assert_eq!(
+        check_source("FUNCTION F RETURNS INTEGER\n    OUTPUT 1\nENDFUNCTION\n"),
+        vec![Execution::NoValue(String::from("F"))]
+    );
+
+    let tested = "FUNCTION Twice(n : INTEGER) RETURNS INTEGER\n    RETURN n * 2\nENDFUNCTION\nTEST \"twice\"\n    ASSERT Twice(2) = 4\nENDTEST\n";
+    let results = golden::run_tests(&parse_source(tested).1, &Settings::default());
+    assert_eq!(results[0].failure, None);
+
+    assert!(parses_in(Dialect::Cambridge9618, &program("CALL Swap(a, b)\n")));
+    assert!(!parses_in(Dialect::Aqa, "PROCEDURE Beep\n    OUTPUT 1\nENDPROCEDURE\n"));
+    assert_eq!(
+        format_source("PROCEDURE P(BYREF a:INTEGER,b:INTEGER,BYVAL c:REAL)\nP(a,b,c)\nENDPROCEDURE\n"),
+        "PROCEDURE P(BYREF a : INTEGER, b : INTEGER, BYVAL c : REAL)\n    CALL P(a, b, c)\nENDPROCEDURE\n"
+    );
+}
+
+#[test]
+fn scoping() {
+    let source = "DECLARE x : INTEGER\nDECLARE i : INTEGER\nPROCEDURE Shadow(n : INTEGER)\n    IF n > 0\n      THEN\n        DECLARE x : INTEGER\n    ENDIF\n    x ← n\n    found ← found + x\nENDPROCEDURE\nx ← 1\nIF x = 1\n  THEN\n    DECLARE found : INTEGER\n    found ← 0\nENDIF\nFOR i ← 1 TO 3\n    DECLARE total : INTEGER\n    IF i = 1\n      THEN\n        total ← 0\n    ENDIF\n    total ← total + i\nNEXT i\nCALL Shadow(5)\nASSERT x = 1\nASSERT found = 5\nASSERT total = 6\n";
+    assert!(run_source(source, Limits::default()).is_ok());
+    assert_eq!(check_source(source), vec![]);
+    let (tokens, parsed) = parse_source(source);
+    let java = java("Scoping", &lower(source, &tokens, &parsed));
+    assert!(java.contains("    static void Shadow(int n) {\n        int x = 0;\n        if (n > 0) {\n        }\n        x = n;\n"));
+
+    // a variable belongs to its scope even when its declaration never runs
+    let skipped = source.replace("IF x = 1", "IF x = 2").replace("ASSERT found = 5", "");
+    assert!(run_source(&skipped.replace("CALL Shadow(5)", "found ← 3"), Limits::default()).is_ok());
+    let shadowed = source.replace("CALL Shadow(5)", "CALL Shadow(0)").replace("ASSERT found = 5", "ASSERT found = 0");
+    assert!(run_source(&shadowed, Limits::default()).is_ok());
+    let array = "IF FALSE\n  THEN\n    DECLARE a : ARRAY[1:3] OF INTEGER\nENDIF\na[1] ← 3\n";
+    assert_eq!(run_source(array, Limits::default()).unwrap_err(), Execution::NotAssigned(String::from("a")));
+
+    let twice = "DECLARE x : INTEGER\nOUTPUT 1\nREPEAT\n    DECLARE x : REAL\nUNTIL TRUE\n";
+    let duplicated = || Execution::AlreadyDeclared(String::from("x"));
+    assert_eq!(run_source(twice, Limits::default()).unwrap_err(), duplicated());
+    assert_eq!(check_source(twice), vec![duplicated()]);
+    let parameter = "PROCEDURE P(x : INTEGER)\n    DECLARE x : INTEGER\nENDPROCEDURE\n";
+    assert_eq!(run_source(parameter, Limits::default()).unwrap_err(), duplicated());
+    assert_eq!(check_source(parameter), vec![duplicated()]);
+}
+
+/// Runs `source` on the lines of `input`, giving what it wrote even when it failed.
+fn run_scripted(source: &str, input: &str, invalid_input: InputPolicy) -> (Result<(), Execution>, String) {
+    let output = Rc::new(RefCell::new(String::new()));
+    let settings = Settings {
+        invalid_input,
+        ..Settings::default()
+    };
+    let state = settings.state(0, Io::scripted(input, &output));
+    let result = evaluate(&parse_source(source).1, state, false).map(|_| ());
+    (result.map_err(|(error, _)| error), output.take())
+}
+
+#[test]
+fn typed_input() {
+    let source = "DECLARE n : INTEGER\nDECLARE r : REAL\nDECLARE b : BOOLEAN\nDECLARE c : CHAR\nDECLARE d : DATE\nDECLARE a : ARRAY[1:3] OF INTEGER\nINPUT n\nINPUT r\nINPUT b\nINPUT c\nINPUT d\nINPUT a[n]\nOUTPUT n, \" \", r, \" \", b, \" \", c, \" \", d, \" \", a[2]\n";
+    let run = |input: &str, invalid_input| run_scripted(source, input, invalid_input);
+    let valid = "2\n2.5\nFalse\nx\n29/2/2024\n7\n";
+    assert_eq!(run(valid, InputPolicy::Error), (Ok(()), String::from("2 2.5 FALSE x 29/02/2024 7\n")));
+    let (result, _) = run("2\n2.5\nyes\n", InputPolicy::Error);
+    assert_eq!(result, Err(Execution::InvalidInput(String::from("yes"), LiteralType::Boolean)));
+    let (result, output) = run(&format!("two\n{}", valid.replacen("x", "xy\nx", 1)), InputPolicy::Reprompt);
+    assert_eq!(result, Ok(()));
+    assert!(output.starts_with("\"two\" is not a valid integer, try again\n\"xy\" is not a valid char, try again\n"));
+    assert_eq!(run("two\n", InputPolicy::Reprompt).0, Err(Execution::EndOfInput));
+    // only the line ending is dropped, as at the console
+    let spaced = "DECLARE c : CHAR\nDECLARE s : STRING\nINPUT c\nINPUT s\nOUTPUT c, s, \"|\"\n";
+    assert_eq!(run_scripted(spaced, " \r\n  padded  \n", InputPolicy::Error), (Ok(()), String::from("   padded  |\n")));
+    assert_eq!(check_source(source), vec![]);
+    assert_eq!(check_source("DECLARE a : ARRAY[1:3] OF INTEGER\nINPUT a[TRUE]\n").len(), 1);
+
+    for (text, valid) in [("31/12/2024", true), ("1/2/2025", true), ("29/02/2000", true), ("29/02/1900", false), ("31/04/2024", false), ("1/13/2024", false), ("1/2/25", false), ("1-2-2025", false)] {
+        assert_eq!(Date::parse(text).is_some(), valid, "{}", text);
+    }
+    assert_eq!(format_source("INPUT a[i + 1]\n"), "INPUT a[i + 1]\n");
+    let (tokens, parsed) = parse_source(source);
+    let program = lower(source, &tokens, &parsed);
+    assert!(python(&program).contains("a[n] = int(input())\n"));
+    assert!(java("Input", &program).contains("d = LocalDate.parse(input.nextLine(), DATE);"));
+    assert_eq!(c(&program), Err(String::from("C has no type for DATE")));
+    // C trims input and rejects it in the interpreter's words, unlike STR_TO_NUM
+    let source = source.replace("DECLARE d : DATE\n", "").replace("INPUT d\n", "").replace(", \" \", d", "");
+    let (tokens, parsed) = parse_source(&source);
+    let transpiled = c(&lower(&source, &tokens, &parsed)).unwrap();
+    assert!(transpiled.contains("    SET(n, input_integer(read_line()));\n    SET(r, input_real(read_line()));\n"));
+    assert!(transpiled.contains("    const char *string = trim(line);\n"));
+    assert!(transpiled.contains("    fail(\"\\\"%s\\\" is not a valid boolean\", line);\n"));
+    assert!(!transpiled.contains("parse_integer"));
+}
+
+#[test]
+fn output_display() {
+    let source = "OUTPUT 3.0, \" \", 2.5, \" \", 1 / 4, \" \", 6 / 2, \" \", -0.5 * 4\nOUTPUT TRUE, \" \", 1 = 2, \" \", NUM_TO_STR(4.0)\nOUTPUT \"no \",\nOUTPUT \"break\"\n";
+    let (result, output) = run_scripted(source, "", InputPolicy::Error);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "3.0 2.5 0.25 3.0 -2.0\nTRUE FALSE 4.0\nno break\n");
+    assert_eq!(format_source("OUTPUT \"a\" ,\n"), "OUTPUT \"a\",\n");
+
+    let source = "DECLARE b : BOOLEAN\nb ← TRUE\nOUTPUT \"prompt: \",\nOUTPUT b, 2.0\n";
+    let (tokens, parsed) = parse_source(source);
+    let program = lower(source, &tokens, &parsed);
+    assert!(python(&program).contains("print(\"prompt: \", end=\"\")\n"));
+    assert!(java("Display", &program).contains("System.out.print(\"prompt: \");\n"));
+    let transpiled = vb("Display", &program);
+    assert!(transpiled.contains("Console.Write(\"prompt: \")\n        Console.WriteLine(Show(b) & Show(2.0))\n"));
+    assert!(transpiled.contains("        Return If(CBool(value), \"TRUE\", \"FALSE\")\n"));
+    let transpiled = c(&program).unwrap();
+    assert!(transpiled.contains("printf(\"prompt: \");\n    printf(\"%s2.0\\n\", VALUE(b) ? \"TRUE\" : \"FALSE\");\n"));
+}
+
+#[test]
+fn arrays() {
+    let source = "DECLARE a, b : ARRAY[1:3] OF INTEGER\nDECLARE total, first : INTEGER\nPROCEDURE Fill(BYREF values : ARRAY OF INTEGER, BYVAL start : INTEGER)\n    DECLARE i : INTEGER\n    FOR i ← 1 TO LEN(values)\n        values[i] ← start + i\n    NEXT i\nENDPROCEDURE\nFUNCTION Total(values : ARRAY OF INTEGER) RETURNS INTEGER\n    DECLARE i, sum : INTEGER\n    sum ← 0\n    FOR i ← 1 TO LEN(values)\n        sum ← sum + values[i]\n    NEXT i\n    values[1] ← 0\n    RETURN sum\nENDFUNCTION\nCALL Fill(a, 10)\nb ← a\nASSERT a = b\nb[2] ← 0\nASSERT a <> b\ntotal ← Total(a)\nfirst ← a[1]\nOUTPUT total, \" \", first\n";
+    let (result, output) = run_scripted(source, "", InputPolicy::Error);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "36 11\n");
+    assert_eq!(check_source(source), vec![]);
+    assert_eq!(format_source("DECLARE x,y:INTEGER\n"), "DECLARE x, y : INTEGER\n");
+
+    let mismatched = "DECLARE a : ARRAY[1:3] OF INTEGER\nDECLARE b : ARRAY[0:2] OF INTEGER\na ← b\n";
+    let mismatch = Execution::ArrayMismatch(String::from("a"), String::from("b"));
+    assert_eq!(run_source(mismatched, Limits::default()).unwrap_err(), mismatch);
+    assert_eq!(check_source(mismatched), vec![mismatch]);
+    let unassigned = "DECLARE a, b : ARRAY[1:2] OF INTEGER\nOUTPUT a = b\n";
+    assert_eq!(run_source(unassigned, Limits::default()).unwrap_err(), Execution::IndexNotAssigned(String::from("a"), 1));
+    let not_array = "PROCEDURE P(values : ARRAY OF REAL)\n    OUTPUT LEN(values)\nENDPROCEDURE\nDECLARE x : ARRAY[1:2] OF INTEGER\nCALL P(x)\nCALL P(1.5)\n";
+    use DataTypes::Literal as L;
+    assert_eq!(
+        check_source(not_array),
+        vec![
+            Execution::IncorrectType(L(LiteralType::Real), L(LiteralType::Integer)),
+            Execution::IncorrectType(DataTypes::Array, L(LiteralType::Real)),
+        ]
+    );
+
+    let (tokens, parsed) = parse_source(source);
+    let program = lower(source, &tokens, &parsed);
+    let transpiled = python(&program);
+    assert!(transpiled.contains("def Fill(values: Array, start: int):\n"));
+    assert!(transpiled.contains("b.assign(a)\nassert a == b\n"));
+    assert!(transpiled.contains("total = Total(a.copy())\n"));
+    let transpiled = java("Arrays", &program);
+    assert!(transpiled.contains("    static void Fill(int[] values, int start) {\n"));
+    assert!(transpiled.contains("for (i = 1; i <= values.length - 1; i++) {"));
+    assert!(transpiled.contains("System.arraycopy(a, 0, b, 0, b.length);\n        assert java.util.Arrays.equals(a, b);\n"));
+    let transpiled = vb("Arrays", &program);
+    assert!(transpiled.contains("    Function Total(ByVal values() As Integer) As Integer\n"));
+    assert!(transpiled.contains("        Array.Copy(a, b, b.Length)\n"));
+    let transpiled = c(&program).unwrap();
+    assert!(transpiled.contains("static void Fill(IntegerArray values, long long start) {\n"));
+    assert!(transpiled.contains("    integer_copy(b, a);\n    assert_that(integer_equal(a, b), NULL);\n"));
+    assert!(transpiled.contains("SET(total, Total(integer_clone(a)));"));
+}
+
+#[test]
+fn array_bounds() {
+    let source = "DECLARE size : INTEGER\nsize ← 3\nDECLARE offsets : ARRAY[-2:2] OF INTEGER\nDECLARE one : ARRAY[1:1] OF INTEGER\nDECLARE sized : ARRAY[1:size * 2] OF INTEGER\noffsets[-2] ← 7\none[1] ← 8\nsized[size * 2] ← 9\nOUTPUT offsets[-2], one[1], sized[6], LEN(offsets), LEN(sized)\n";
+    let (result, output) = run_scripted(source, "", InputPolicy::Error);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "78956\n");
+    assert_eq!(check_source(source), vec![]);
+
+    let out_of_bounds = Execution::OutOfBounds(String::from("offsets"), -3, Bounds { lower: -2, upper: 2 });
+    assert_eq!(out_of_bounds.to_string(), "Index -3 is out of bounds for array offsets, which goes from -2 to 2");
+    let read = source.replace("offsets[-2], one", "offsets[-3], one");
+    assert_eq!(run_source(&read, Limits::default()).unwrap_err(), out_of_bounds);
+    let empty = "DECLARE n : INTEGER\nn ← 0\nDECLARE a : ARRAY[1:n] OF INTEGER\n";
+    assert_eq!(run_source(empty, Limits::default()).unwrap_err(), Execution::InvalidBounds(Bounds { lower: 1, upper: 0 }));
+    assert_eq!(check_source("DECLARE a : ARRAY[3:-3] OF INTEGER\n"), vec![Execution::InvalidBounds(Bounds { lower: 3, upper: -3 })]);
+    use DataTypes::Literal as L;
+    assert_eq!(
+        check_source("DECLARE a : ARRAY[1:2.5] OF INTEGER\n"),
+        vec![Execution::IncorrectType(L(LiteralType::Integer), L(LiteralType::Real))]
+    );
+    assert_eq!(format_source("DECLARE a:ARRAY[-5:n+1] OF INTEGER\n"), "DECLARE a : ARRAY[-5:n + 1] OF INTEGER\n");
+
+    let (tokens, parsed) = parse_source(source);
+    let program = lower(source, &tokens, &parsed);
+    assert!(python(&program).contains("offsets = Array(-2, 2)\none = Array(1, 1)\nsized = Array(1, size * 2)\n"));
+    let transpiled = java("Bounds", &program);
+    assert!(transpiled.contains("    static int[] offsets = new int[5];\n"));
+    assert!(transpiled.contains("    static int[] sized;\n"));
+    assert!(transpiled.contains("        sized = new int[size * 2 + 1];\n        offsets[0] = 7;\n"));
+    assert!(transpiled.contains("System.out.println(\"\" + offsets[0] + one[1] + sized[6] + offsets.length + (sized.length - 1));"));
+    let transpiled = vb("Bounds", &program);
+    assert!(transpiled.contains("    Dim offsets(4) As Integer\n    Dim one(1) As Integer\n    Dim sized() As Integer\n"));
+    assert!(transpiled.contains("        ReDim sized(size * 2)\n"));
+    let transpiled = c(&program).unwrap();
+    assert!(transpiled.contains("    offsets = new_integer_array(\"offsets\", -2, 2);\n"));
+    assert!(transpiled.contains("    sized = new_integer_array(\"sized\", 1, integer_multiply(VALUE(size), 2));\n"));
+}
+
+#[test]
+fn snippets() {
+    let source = "FOR index ← 1 TO LEN(list) - 1\n    IF list[index] > list[index + 1] THEN\n        temp ← list[index]\n        list[index] ← list[index + 1]\n        list[index + 1] ← temp\n    ENDIF\nNEXT index\nname ← name & \"!\"\nhalf ← total / 2\n";
+    let (_, parsed) = parse_source(source);
+    let inferred = infer(&parsed, &BTreeMap::new());
+    let typed = |array, literal_type| Inferred { array, literal_type: Some(literal_type) };
+    assert_eq!(inferred["index"], typed(false, LiteralType::Integer));
+    assert_eq!(inferred["list"], Inferred { array: true, literal_type: None });
+    assert_eq!(inferred["name"], typed(false, LiteralType::String));
+    assert_eq!(inferred["half"], typed(false, LiteralType::Real));
+    assert_eq!(inferred["temp"], Inferred::default());
+
+    let args: Vec<String> = ["--init", "list=[3, 1, 2]", "--snippet", "--init=name=Ada", "--init", "total=5", "sort.psps"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let (initial, rest) = initial_values(&args).unwrap();
+    assert_eq!(rest, vec!["--snippet", "sort.psps"]);
+    assert_eq!(initial["list"], Initial::Array(1, vec![Literal::Integer(3), Literal::Integer(1), Literal::Integer(2)]));
+    assert_eq!(initial["name"], Initial::Value(Literal::String(String::from("Ada"))));
+
+    let mut state = Settings::default().state(0, Io::Console);
+    state.scopes.push(globals(&parsed, &initial).unwrap());
+    let state = evaluate(&parsed, state, false).unwrap();
+    assert_eq!(
+        summary(&state.scopes[0]),
+        "half = 2.5\nindex = 2\nlist = [1, 2, 3]\nname = \"Ada!\"\ntemp = 3\ntotal = 5\n"
+    );
+
+    let mut missing = initial.clone();
+    missing.remove("list");
+    assert_eq!(
+        globals(&parsed, &missing).unwrap_err(),
+        "list is used as an array but never declared, so its values have to be given"
+    );
+    let declared = "DECLARE list : ARRAY[0:1] OF INTEGER\nOUTPUT list[0]\n";
+    let (_, parsed) = parse_source(declared);
+    assert_eq!(
+        globals(&parsed, &initial).unwrap_err(),
+        "list is declared with bounds 0:1, but was given values for 1:3"
+    );
+    let zero = BTreeMap::from([(String::from("list"), Initial::Array(0, vec![Literal::Integer(4), Literal::Real(0.5)]))]);
+    assert_eq!(
+        globals(&parsed, &zero).unwrap_err(),
+        "list: Incorrect type, expected integer literal but received real literal"
+    );
+
+    // the values given say what type a variable is before any assignment does
+    let (_, parsed) = parse_source("total ← total + x\n");
+    let (initial, _) = initial_values(&[String::from("--init=total=1"), String::from("--init=x=2.5")]).unwrap();
+    assert_eq!(infer(&parsed, &initial)["total"], Inferred { array: false, literal_type: Some(LiteralType::Real) });
+    let mut state = Settings::default().state(0, Io::Console);
+    state.scopes.push(globals(&parsed, &initial).unwrap());
+    let state = evaluate(&parsed, state, false).unwrap();
+    assert_eq!(summary(&state.scopes[0]), "total = 3.5\nx = 2.5\n");
+}
+
+#[test]
+fn imports() {
+    let directory = std::env::temp_dir().join(format!("psps-imports-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("lib")).unwrap();
+    let write = |name: &str, source: &str| {
+        let path = directory.join(name);
+        std::fs::write(&path, source).unwrap();
+        path
+    };
+    let load = |path: &std::path::Path| {
+        let source = std::fs::read_to_string(path).unwrap();
+        let (sources, parsed) = import::load(path, &source, Dialect::Lenient);
+        let errors = parsed.map(|(_, statements)| statements).map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| (sources.locate(&error.span()).0.to_owned(), crate::error_message(&error)))
+                .collect::<Vec<_>>()
+        });
+        (sources, errors)
+    };
+
+    write("lib/maths.psps", "IMPORT \"greet.psps\"\nDECLARE counter : INTEGER\ncounter ← 0\nFUNCTION Square(n : INTEGER) RETURNS INTEGER\n    RETURN n * n\nENDFUNCTION\nPROCEDURE Bump()\n    counter ← counter + 1\nENDPROCEDURE\nTEST \"squares\"\n    ASSERT Square(2) = 4\nENDTEST\n");
+    write("lib/greet.psps", "PROCEDURE Greet(name : STRING)\n    OUTPUT \"Hello \", name\nENDPROCEDURE\n");
+    write("lib/divide.psps", "FUNCTION Half(n : INTEGER) RETURNS INTEGER\n    RETURN n DIV 0\nENDFUNCTION\n");
+    let main = write("main.psps", "IMPORT \"lib/maths.psps\"\nIMPORT \"lib/greet.psps\"\nIMPORT \"lib/divide.psps\"\nOUTPUT Square(4)\nCALL Greet(\"Ada\")\nCALL Bump()\nCALL Bump()\nOUTPUT counter\nOUTPUT Half(2)\n");
+    let (sources, parsed) = load(&main);
+    let parsed = parsed.unwrap();
+    assert_eq!(parsed.iter().filter(|(statement, _)| matches!(statement, Statement::Subroutine(_))).count(), 4);
+    let output = Rc::new(RefCell::new(String::new()));
+    let state = Settings::default().state(0, Io::scripted("", &output));
+    let (error, span) = evaluate(&parsed, state, false).unwrap_err();
+    assert_eq!(output.take(), "16\nHello Ada\n2\n");
+    assert_eq!(error, Execution::DivisionByZero(String::from("2")));
+    let (file, text, span) = sources.locate(&span);
+    assert_eq!((file, golden::line_of(text, span.start)), ("lib/divide.psps", 2));
+    let source = std::fs::read_to_string(&main).unwrap();
+    let (tokens, parsed) = import::load(&main, &source, Dialect::Lenient).1.unwrap();
+    assert!(python(&lower(&source, &tokens, &parsed)).contains("\ncounter: int\ncounter = 0\nprint(Square(4))\n"));
+
+    write("a.psps", "IMPORT \"b.psps\"\n");
+    write("b.psps", "IMPORT \"a.psps\"\n");
+    let cycle = Execution::ImportCycle(vec![String::from("a.psps"), String::from("b.psps"), String::from("a.psps")]);
+    assert_eq!(load(&directory.join("a.psps")).1.unwrap_err(), vec![(String::from("b.psps"), cycle.to_string())]);
+
+    // statements that would never run are reported in the file they are in
+    write("lib/noisy.psps", "DECLARE shown : BOOLEAN\nOUTPUT \"not run\"\nshown ← TRUE\nhidden ← 1\n");
+    let noisy = write("noisy.psps", "IMPORT \"lib/noisy.psps\"\n");
+    let not_importable = (String::from("lib/noisy.psps"), Execution::NotImportable.to_string());
+    assert_eq!(load(&noisy).1.unwrap_err(), vec![not_importable.clone(), not_importable]);
+
+    let missing = write("missing.psps", "IMPORT \"nowhere.psps\"\n");
+    let (file, message) = &load(&missing).1.unwrap_err()[0];
+    assert_eq!(file, "missing.psps");
+    assert!(message.starts_with("Can not import \"nowhere.psps\""));
+
+    assert!(matches!(run_source("IMPORT \"lib/maths.psps\"\n", Limits::default()), Err(Execution::CanNotImport(..))));
+    assert_eq!(format_source("IMPORT  \"lib/maths.psps\"\n"), "IMPORT \"lib/maths.psps\"\n");
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn structure_charts() {
+    let (_, parsed) = parse_source(include_str!("../examples/subroutines.psps"));
+    let chart = structure_chart("main", &parsed);
+    assert_eq!(chart.modules, vec!["main", "Swap", "Order", "Factorial"]);
+    let calls: Vec<(usize, usize, bool, bool)> = chart
+        .calls
+        .iter()
+        .map(|call| (call.from, call.to, call.selection, call.iteration))
+        .collect();
+    assert_eq!(
+        calls,
+        vec![(0, 2, false, false), (0, 3, false, true), (2, 1, true, false), (3, 3, false, false)]
+    );
+    let couples: Vec<Direction> = chart.calls[1].couples.iter().map(|couple| couple.direction).collect();
+    assert_eq!(couples, vec![Direction::Down, Direction::Up]);
+    let dot = chart.dot();
+    assert!(dot.contains("    m2 -> m1 [label=\"↕ x\\n↕ y\", dir=both, arrowtail=diamond];\n"));
+    assert!(dot.contains("    m0 -> m3 [label=\"↓ n\\n↑ Factorial\", taillabel=\"↻\"];\n"));
+}
+
+#[test]
+fn transpile_python() {
+    let source = "DECLARE count : INTEGER\nDECLARE range : ARRAY[1:3] OF INTEGER\ncount ← 0\nREPEAT\n    count ← count + 1 // step\n    range[count] ← count * -(2 - 1)\nUNTIL count = 3 OR NOT TRUE\nOUTPUT count, \" \", NUM_TO_STR(range[3])\n";
+    let (tokens, parsed) = parse_source(source);
+    let transpiled = python(&lower(source, &tokens, &parsed));
+    assert!(transpiled.starts_with("class Array:\n"));
+    assert!(!transpiled.contains("def show"));
+    assert!(transpiled.ends_with(
+        "count: int\nrange_ = Array(1, 3)\ncount = 0\nwhile True:\n    count = count + 1  # step\n    range_[count] = count * -(2 - 1)\n    if count == 3 or not True:\n        break\nprint(count, \" \", str(range_[3]), sep=\"\")\n"
+    ));
+
+    let (tokens, parsed) = parse_source(include_str!("../examples/subroutines.psps"));
+    let transpiled = python(&lower(include_str!("../examples/subroutines.psps"), &tokens, &parsed));
+    assert!(transpiled.starts_with("# Sorts three numbers"));
+    assert!(transpiled.contains("def Swap(x: int, y: int):\n"));
+    assert!(transpiled.contains("    return x, y\n"));
+    assert!(transpiled.contains("first, second = Order(first, second)\n"));
+    assert!(transpiled.contains("def Factorial(n: int) -> int:\n"));
+    assert!(transpiled.contains("for counter in range(1, 6):\n"));
+
+    let source = "DECLARE total : REAL\nFUNCTION Half(value : REAL) RETURNS REAL\n    RETURN value / 2\nENDFUNCTION\ntotal ← -7 DIV 2\nOUTPUT total, -7 MOD 2, Half(total + 1), TRUE, -7.5 DIV 2\n";
+    let (tokens, parsed) = parse_source(source);
+    let transpiled = python(&lower(source, &tokens, &parsed));
+    assert!(transpiled.contains("    quotient = int(abs(a) // abs(b))\n    return quotient if (a < 0) == (b < 0) else -quotient\n"));
+    assert!(transpiled.contains("    remainder = abs(a) % abs(b)\n    return remainder if a >= 0 else -remainder\n"));
+    assert!(transpiled.contains("total = float(div(-7, 2))\nprint(show(total), mod(-7, 2), show(Half(total + 1)), show(True), div(-7.5, 2), sep=\"\")\n"));
+}
+
+#[test]
+fn transpile_java_and_vb() {
+    let source = "DECLARE name : STRING\nDECLARE scores : ARRAY[1:3] OF INTEGER\nDECLARE first : INTEGER\nDECLARE second : INTEGER\nPROCEDURE Swap(BYREF x : INTEGER, y : INTEGER)\n    DECLARE temp : INTEGER\n    temp ← x\n    x ← y\n    y ← temp\nENDPROCEDURE\nname ← \"bob\"\nIF name = \"alice\"\n  THEN\n    OUTPUT 1 / 2\n  ELSE\n    IF NOT (name < \"carl\")\n      THEN\n        OUTPUT LEN(scores)\n    ENDIF\nENDIF\nCALL Swap(first, second)\nOUTPUT first = second\n";
+    let (tokens, parsed) = parse_source(source);
+    let program = lower(source, &tokens, &parsed);
+
+    let java = java("Example", &program);
+    assert!(java.contains("    static String name;\n    static int[] scores = new int[4];\n"));
+    assert!(java.contains("    static int[] Swap(int x, int y) {\n        int temp = 0;\n"));
+    assert!(java.contains("        return new int[] {x, y};\n"));
+    assert!(java.contains("        if (name.equals(\"alice\")) {\n            System.out.println((double) 1 / 2);\n        } else if (!(name.compareTo(\"carl\") < 0)) {\n            System.out.println(scores.length - 1);\n        }\n"));
+    assert!(java.contains("        {\n            int[] results = Swap(first, second);\n            first = results[0];\n            second = results[1];\n        }\n"));
+    assert!(java.contains("        System.out.println(first == second ? \"TRUE\" : \"FALSE\");\n"));
+
+    let vb = vb("Example", &program);
+    assert!(vb.starts_with("Module Example\n    Dim name As String\n    Dim scores(3) As Integer\n"));
+    assert!(vb.contains("    Sub Swap(ByRef x As Integer, ByRef y As Integer)\n        Dim temp As Integer\n"));
+    assert!(vb.contains("        If name = \"alice\" Then\n            Console.WriteLine(Show(1 / 2))\n        ElseIf Not (name < \"carl\") Then\n            Console.WriteLine(scores.Length - 1)\n        End If\n"));
+    assert!(vb.contains("        Swap(first, second)\n"));
+
+    // nothing could be written back to an array element
+    let source = source.replace("CALL Swap(first, second)", "CALL Swap(scores[1], scores[2])");
+    let (_, parsed) = parse_source(&source);
+    let errors: Vec<Execution> = by_reference_errors(&parsed, Dialect::Lenient)
+        .into_iter()
+        .map(|(error, _)| error)
+        .collect();
+    assert_eq!(errors, vec![Execution::ByReference(String::from("x")), Execution::ByReference(String::from("y"))]);
+
+    // DIV truncates the quotient of reals to an integer too
+    let source = "OUTPUT 7.5 DIV 2\n";
+    let (tokens, parsed) = parse_source(source);
+    let program = lower(source, &tokens, &parsed);
+    assert!(crate::java::java("Example", &program).contains("        System.out.println((int) (7.5 / 2));\n"));
+    assert!(crate::vb::vb("Example", &program).contains("        Console.WriteLine(CInt(Fix(7.5 / 2)))\n"));
+}
+
+#[test]
+fn transpile_c() {
+    let source = "DECLARE total : INTEGER\nDECLARE unused : REAL\nDECLARE words : ARRAY[0:1] OF STRING\nPROCEDURE Add(BYREF value : INTEGER, BYVAL amount : INTEGER)\n    value ← value + amount\nENDPROCEDURE\ntotal ← 7 DIV 2\nCALL Add(total, 1)\nwords[0] ← \"a\" & \"b\"\nIF words[0] = \"ab\"\n  THEN\n    OUTPUT \"total: \", total, \" 100%\"\nENDIF\n";
+    let (tokens, parsed) = parse_source(source);
+    let transpiled = c(&lower(source, &tokens, &parsed)).unwrap();
+    assert!(transpiled.starts_with("#include <stdarg.h>\n#include <stdbool.h>\n#include <stdio.h>\n#include <stdlib.h>\n#include <string.h>\n"));
+    assert!(transpiled.contains("\nstatic long long total;\nstatic bool total_assigned;\nstatic StringArray words;\n"));
+    assert!(!transpiled.contains("unused"));
+    assert!(!transpiled.contains("real_to_string"));
+    assert!(transpiled.contains("static void Add(long long *value, long long amount) {\n    *value = integer_add(*value, amount);\n}\n"));
+    assert!(transpiled.contains("    if (__builtin_add_overflow(a, b, &result)) {\n        fail(\"The result of operator \\\"plus\\\" is too large to hold\");\n"));
+    assert!(!transpiled.contains("integer_multiply"));
+    assert!(transpiled.contains("    SET(total, divide(7, 2));\n    Add(&total, 1);\n    total_assigned = true;\n"));
+    assert!(transpiled.contains("    string_set(words, 0, concat(\"a\", \"b\"));\n    if (strcmp(string_get(words, 0), \"ab\") == 0) {\n        printf(\"total: %lld 100%%\\n\", VALUE(total));\n    }\n"));
+
+    // `amount` is passed by reference too, and there is no variable for it to point to
+    let source = source.replace("BYVAL amount", "amount");
+    let (tokens, parsed) = parse_source(&source);
+    assert_eq!(c(&lower(&source, &tokens, &parsed)), Err(String::from("Parameter amount is passed by reference, so its argument must be a variable")));
+
+    // integers are checked for overflow rather than wrapping, which C leaves undefined
+    let source = "DECLARE x : INTEGER\nx ← 3\nOUTPUT -x * 2 - 1, -1, 1.5 * 2\n";
+    let (tokens, parsed) = parse_source(source);
+    let transpiled = c(&lower(source, &tokens, &parsed)).unwrap();
+    assert!(transpiled.contains("integer_subtract(integer_multiply(integer_negate(VALUE(x)), 2), 1), -1, real_to_string(1.5 * 2));"));
+
+    let source = "OUTPUT 1 / 2, 7.5 DIV 2\n";
+    let (tokens, parsed) = parse_source(source);
+    let transpiled = c(&lower(source, &tokens, &parsed)).unwrap();
+    assert!(transpiled.contains("        fail(\"Can not divide %s by zero\", real_to_string(a));\n"));
+    assert!(transpiled.contains("printf(\"%s%lld\\n\", real_to_string(divide_real(1, 2)), (long long) divide_real(7.5, 2));"));
+
+    let source = "DECLARE x : INTEGER\nx ← y + 1\n";
+    let (tokens, parsed) = parse_source(source);
+    assert_eq!(c(&lower(source, &tokens, &parsed)), Err(String::from("y is never declared")));
+}
+
+#[test]
+fn playground() {
+    use pseudo_pseudo_code::playground::{check, run, Options};
+
+    let source = "DECLARE name : STRING\nINPUT name\nOUTPUT \"hello \", name\nOUTPUT RANDOMBETWEEN(1, 1000)\nINPUT name\n";
+    let options = Options {
+        seed: 7,
+        ..Options::default()
+    };
+    let first = run(source, "ada", &options).unwrap();
+    assert!(first.output.starts_with("hello ada\n"));
+    assert_eq!(first.output, run(source, "ada", &options).unwrap().output);
+    assert_eq!(first.diagnostics.len(), 1);
+    assert_eq!(first.diagnostics[0].kind, "EndOfInput");
+    assert_eq!((first.diagnostics[0].line, first.diagnostics[0].column), (5, 1));
+
+    let endless = run("DECLARE x : INTEGER\nx ← 0\nWHILE x < 1\n    x ← 0\nENDWHILE\n", "", &Options { max_steps: Some(100), ..Options::default() }).unwrap();
+    assert_eq!(endless.diagnostics[0].kind, "StepLimit");
+
+    let diagnostics = check("DECLARE x : INTEGER\nx ← \"a\"\nOUTPUT (", &Options::default()).unwrap();
+    assert_eq!(diagnostics[0].kind, "Syntax");
+    let diagnostics = check("DECLARE x : INTEGER\nx ← \"a\"\n", &Options::default()).unwrap();
+    assert_eq!(diagnostics[0].kind, "IncorrectType");
+    assert_eq!(diagnostics[0].line, 2);
+    assert!(run("", "", &Options { dialect: Some(String::from("cobol")), ..Options::default() }).is_err());
+}
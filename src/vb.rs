@@ -0,0 +1,595 @@
+use crate::ast::*;
+use crate::lower::*;
+
+/// Visual Basic's keywords and the names the generated module relies on, compared without regard
+/// to case as Visual Basic does. Pseudocode identifiers that match one are escaped in brackets.
+const RESERVED: [&str; 105] = [
+    "AddHandler",
+    "AddressOf",
+    "Alias",
+    "And",
+    "AndAlso",
+    "As",
+    "Boolean",
+    "ByRef",
+    "Byte",
+    "ByVal",
+    "Call",
+    "Case",
+    "Catch",
+    "CBool",
+    "CByte",
+    "CChar",
+    "CDate",
+    "CDbl",
+    "CDec",
+    "Char",
+    "CInt",
+    "Class",
+    "CLng",
+    "CObj",
+    "Const",
+    "Continue",
+    "CSByte",
+    "CShort",
+    "CSng",
+    "CStr",
+    "CType",
+    "CUInt",
+    "CULng",
+    "CUShort",
+    "Date",
+    "Decimal",
+    "Declare",
+    "Default",
+    "Delegate",
+    "Dim",
+    "DirectCast",
+    "Do",
+    "Double",
+    "Each",
+    "Else",
+    "ElseIf",
+    "End",
+    "Enum",
+    "Erase",
+    "Error",
+    "Event",
+    "Exit",
+    "False",
+    "Finally",
+    "For",
+    "Friend",
+    "Function",
+    "Get",
+    "GetType",
+    "Global",
+    "GoTo",
+    "Handles",
+    "If",
+    "Implements",
+    "Imports",
+    "In",
+    "Inherits",
+    "Integer",
+    "Interface",
+    "Is",
+    "IsNot",
+    "Let",
+    "Lib",
+    "Like",
+    "Long",
+    "Loop",
+    "Me",
+    "Mod",
+    "Module",
+    "Namespace",
+    "New",
+    "Next",
+    "Not",
+    "Nothing",
+    "Object",
+    "Of",
+    "On",
+    "Operator",
+    "Option",
+    "Or",
+    "OrElse",
+    "Return",
+    "Select",
+    "Set",
+    "Step",
+    "Stop",
+    "String",
+    "Sub",
+    "Then",
+    "To",
+    "True",
+    "Try",
+    "While",
+    "With",
+    "Xor",
+];
+
+/// Names the generated module uses itself, which pseudocode identifiers get renamed away from.
+const USED: [&str; 10] = [
+    "Console",
+    "Random",
+    "generator",
+    "Main",
+    "Globalization",
+    "Array",
+    "System",
+    "Show",
+    "Math",
+    "Convert",
+];
+
+/// Writes a value as `OUTPUT` does: booleans as TRUE or FALSE, whole reals with a decimal point
+/// and dates as dd/mm/yyyy, whatever the culture.
+const SHOW: &str = r#"Function Show(value As Object) As String
+    If TypeOf value Is Boolean Then
+        Return If(CBool(value), "TRUE", "FALSE")
+    ElseIf TypeOf value Is Date Then
+        Return CDate(value).ToString("dd/MM/yyyy", Globalization.CultureInfo.InvariantCulture)
+    ElseIf TypeOf value Is Double AndAlso CDbl(value) = Math.Floor(CDbl(value)) Then
+        Return CDbl(value).ToString("0.0", Globalization.CultureInfo.InvariantCulture)
+    End If
+    Return Convert.ToString(value, Globalization.CultureInfo.InvariantCulture)
+End Function"#;
+
+/// Writes a lowered program as a Visual Basic .NET module named `module`.
+///
+/// The main program's variables become module-level variables so its subroutines can reach them,
+/// and arrays are sized to their upper bound so they are indexed as in the pseudocode. `BYREF`
+/// and `BYVAL` carry over as they are, and `TEST` blocks become subroutines checked with
+/// `Debug.Assert`.
+pub fn vb(module: &str, program: &Program) -> String {
+    let mut vb = Vb {
+        writer: Writer::new("    "),
+    };
+    for comment in &program.header {
+        vb.comment(comment);
+    }
+    if program.uses_assert {
+        vb.writer.line("Imports System.Diagnostics");
+        vb.writer.blank();
+    }
+    vb.writer.line(&format!("Module {}", module));
+    vb.writer.indent();
+    if program.uses_random {
+        vb.writer.line("Dim generator As New Random()");
+    }
+    for variable in &program.variables {
+        vb.writer.line(&dim(variable));
+    }
+
+    for routine in &program.subroutines {
+        vb.writer.blank();
+        vb.routine(routine, &name(&routine.name));
+    }
+    vb.writer.blank();
+    vb.writer.line("Sub Main()");
+    vb.body(&program.main);
+    vb.writer.line("End Sub");
+    for test in &program.tests {
+        vb.writer.blank();
+        vb.routine(test, &test_name(&test.name));
+    }
+    if invokes(&vb.writer.output, "Show") {
+        vb.writer.blank();
+        for line in SHOW.lines() {
+            vb.writer.line(line);
+        }
+    }
+    for comment in &program.footer {
+        vb.comment(comment);
+    }
+    vb.writer.dedent();
+    vb.writer.line("End Module");
+    vb.writer.output
+}
+
+fn name(identifier: &str) -> String {
+    if RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(identifier))
+    {
+        format!("[{}]", identifier)
+    } else if USED
+        .iter()
+        .any(|used| used.eq_ignore_ascii_case(identifier))
+    {
+        format!("{}_", identifier)
+    } else {
+        String::from(identifier)
+    }
+}
+
+fn vb_type(literal_type: &LiteralType) -> &'static str {
+    match literal_type {
+        LiteralType::Integer => "Integer",
+        LiteralType::Real => "Double",
+        LiteralType::String => "String",
+        LiteralType::Boolean => "Boolean",
+        LiteralType::Char => "Char",
+        LiteralType::Date => "Date",
+        LiteralType::Any => "Object",
+    }
+}
+
+/// Declares a variable, leaving an array whose bounds are worked out as the program runs to be
+/// sized by `redim` where it is declared.
+fn dim(declare: &Declared) -> String {
+    match declare {
+        Declared::Literal(identifier, literal_type) => {
+            format!("Dim {} As {}", name(identifier), vb_type(literal_type))
+        }
+        Declared::Array(identifier, .., literal_type) if declare.bounds().is_none() => {
+            format!("Dim {}() As {}", name(identifier), vb_type(literal_type))
+        }
+        Declared::Array(identifier, _, upper, literal_type) => format!(
+            "Dim {}({}) As {}",
+            name(identifier),
+            expression(&offset(upper, declare.shift()).expression),
+            vb_type(literal_type)
+        ),
+    }
+}
+
+fn redim(declare: &Declared) -> Option<String> {
+    match declare {
+        Declared::Array(identifier, _, upper, _) if declare.bounds().is_none() => Some(format!(
+            "ReDim {}({})",
+            name(identifier),
+            expression(&upper.expression)
+        )),
+        _ => None,
+    }
+}
+
+/// `TEST "adds up"` becomes `TestAddsUp`.
+fn test_name(name: &str) -> String {
+    name.split(|char: char| !char.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| {
+                    first
+                        .to_uppercase()
+                        .chain(chars.flat_map(char::to_lowercase))
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .fold(String::from("Test"), |name, word: String| name + &word)
+}
+
+/// Binding strength in Visual Basic, higher binding tighter.
+fn precedence(expression: &Expr) -> u8 {
+    match expression {
+        Expr::Operate(Ops::Or, ..) => 1,
+        Expr::Operate(Ops::And, ..) => 2,
+        Expr::Not(_) => 3,
+        Expr::Operate(op, ..) if COMPARE.contains(op) => 4,
+        Expr::Operate(Ops::Concatenate, ..) => 5,
+        Expr::Operate(Ops::Plus | Ops::Minus, ..) => 6,
+        Expr::Operate(Ops::Mod, ..) => 7,
+        Expr::Operate(Ops::Div, ..) => 8,
+        Expr::Operate(..) => 9,
+        Expr::Length(_, lower) if lower.integer() != Some(0) => 6,
+        Expr::Negative(_) => 10,
+        _ => 11,
+    }
+}
+
+fn operator(op: &Ops) -> &'static str {
+    match op {
+        Ops::Plus => "+",
+        Ops::Concatenate => "&",
+        Ops::Minus => "-",
+        Ops::Divide => "/",
+        Ops::Multiply => "*",
+        Ops::GreaterThan => ">",
+        Ops::LessThan => "<",
+        Ops::GreaterThanEqual => ">=",
+        Ops::LessThanEqual => "<=",
+        Ops::Equal => "=",
+        Ops::NotEqual => "<>",
+        Ops::Mod => "Mod",
+        Ops::Div => "\\",
+        Ops::And => "AndAlso",
+        Ops::Or => "OrElse",
+        Ops::Not => "Not",
+    }
+}
+
+fn literal(literal: &Literal) -> String {
+    match literal {
+        Literal::String(value) => format!("\"{}\"", value),
+        Literal::Bool(true) => String::from("True"),
+        Literal::Bool(false) => String::from("False"),
+        Literal::Char(value) => format!("\"{}\"c", value),
+        literal => literal.source(),
+    }
+}
+
+fn arguments(args: &[Typed]) -> String {
+    args.iter()
+        .map(|arg| expression(&arg.expression))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Prints `inner` under a parent binding with strength `outer`, bracketed where Visual Basic would
+/// otherwise group it differently. Comparisons are bracketed inside each other for clarity.
+fn operand(inner: &Expr, outer: u8, right: bool) -> String {
+    if needs_brackets(precedence(inner), outer, right, 4..5) {
+        format!("({})", expression(inner))
+    } else {
+        expression(inner)
+    }
+}
+
+fn expression(expression: &Expr) -> String {
+    match expression {
+        Expr::Value(value) => literal(value),
+        Expr::Variable(identifier) => name(identifier),
+        Expr::Index(identifier, index, shift) => {
+            format!(
+                "{}({})",
+                name(identifier),
+                self::expression(&offset(index, *shift).expression)
+            )
+        }
+        Expr::Length(identifier, lower) => match lower.integer() {
+            Some(0) => format!("{}.Length", name(identifier)),
+            _ => format!(
+                "{}.Length - {}",
+                name(identifier),
+                operand(&lower.expression, 6, true)
+            ),
+        },
+        Expr::ArraysEqual(a, b) => {
+            format!(
+                "System.Linq.Enumerable.SequenceEqual({}, {})",
+                self::expression(&a.expression),
+                self::expression(&b.expression)
+            )
+        }
+        Expr::ArrayCopy(identifier) => format!("{}.Clone()", name(identifier)),
+        Expr::BuiltIn(BuiltIn::StrToNum, args) => format!("CInt({})", arguments(args)),
+        Expr::BuiltIn(BuiltIn::NumToStr, args) => format!("CStr({})", arguments(args)),
+        Expr::BuiltIn(BuiltIn::RandomBetween, args) => {
+            format!("generator.Next({})", arguments(args))
+        }
+        Expr::Call(function, args) => format!("{}({})", name(function), arguments(args)),
+        Expr::Negative(inner) => format!("-{}", operand(&inner.expression, 10, false)),
+        // `Not` binds looser than a comparison, which would read as negating only its left side.
+        Expr::Not(inner) => format!("Not {}", operand(&inner.expression, 5, false)),
+        // `\` would round real operands to whole numbers before dividing.
+        Expr::Operate(Ops::Div, a, b)
+            if a.literal_type != LiteralType::Integer || b.literal_type != LiteralType::Integer =>
+        {
+            format!(
+                "CInt(Fix({} / {}))",
+                operand(&a.expression, 9, false),
+                operand(&b.expression, 9, true)
+            )
+        }
+        Expr::Input(literal_type) => String::from(match literal_type {
+            LiteralType::Integer => "CInt(Console.ReadLine())",
+            LiteralType::Real => "CDbl(Console.ReadLine())",
+            LiteralType::Boolean => "CBool(Console.ReadLine())",
+            LiteralType::Char => "CChar(Console.ReadLine())",
+            LiteralType::Date => {
+                "Date.ParseExact(Console.ReadLine(), \"d/M/yyyy\", Globalization.CultureInfo.InvariantCulture)"
+            }
+            LiteralType::String | LiteralType::Any => "Console.ReadLine()",
+        }),
+        Expr::Operate(op, a, b) => {
+            let outer = precedence(expression);
+            format!(
+                "{} {} {}",
+                operand(&a.expression, outer, false),
+                operator(op),
+                operand(&b.expression, outer, true)
+            )
+        }
+    }
+}
+
+struct Vb {
+    writer: Writer,
+}
+
+impl Vb {
+    fn comment(&mut self, text: &str) {
+        self.writer.line(&format!("'{}", text));
+    }
+
+    fn routine(&mut self, routine: &Routine, name: &str) {
+        for comment in &routine.comments {
+            self.comment(comment);
+        }
+        let parameters = routine
+            .parameters
+            .iter()
+            .map(|parameter| {
+                let passing = match parameter.passing {
+                    Passing::ByValue => "ByVal",
+                    Passing::ByReference => "ByRef",
+                };
+                format!(
+                    "{} {}{} As {}",
+                    passing,
+                    self::name(&parameter.name),
+                    if parameter.array { "()" } else { "" },
+                    vb_type(&parameter.literal_type)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let keyword = match &routine.returns {
+            Some(returns) => {
+                self.writer.line(&format!(
+                    "Function {}({}) As {}",
+                    name,
+                    parameters,
+                    vb_type(returns)
+                ));
+                "Function"
+            }
+            None => {
+                self.writer.line(&format!("Sub {}({})", name, parameters));
+                "Sub"
+            }
+        };
+        self.writer.indent();
+        for declare in declares(&routine.body) {
+            self.writer.line(&dim(declare));
+        }
+        self.block(&routine.body);
+        self.writer.dedent();
+        self.writer.line(&format!("End {}", keyword));
+        if let Some(comment) = &routine.trailing {
+            self.writer.trailing(&format!("'{}", comment));
+        }
+    }
+
+    fn body(&mut self, block: &Block) {
+        self.writer.indent();
+        self.block(block);
+        self.writer.dedent();
+    }
+
+    fn block(&mut self, block: &Block) {
+        for line in block {
+            match line {
+                Line::Comment(text) => self.comment(text),
+                Line::Blank => self.writer.blank(),
+                // The main program's variables already belong to the module, and routines declare
+                // theirs up front since pseudocode blocks do not scope them.
+                Line::Statement(Lowered::Declare(declares), trailing) => {
+                    for line in declares.iter().filter_map(redim) {
+                        self.writer.line(&line);
+                    }
+                    if let Some(comment) = trailing {
+                        self.comment(comment);
+                    }
+                }
+                Line::Statement(statement, trailing) => {
+                    self.statement(statement);
+                    if let Some(comment) = trailing {
+                        self.writer.trailing(&format!("'{}", comment));
+                    }
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Lowered) {
+        match statement {
+            Lowered::Declare(declares) => {
+                for declare in declares {
+                    self.writer.line(&dim(declare))
+                }
+            }
+            Lowered::CopyArray(to, from) => self.writer.line(&format!(
+                "Array.Copy({}, {}, {}.Length)",
+                expression(&from.expression),
+                name(to),
+                name(to)
+            )),
+            Lowered::Assign(identifier, value) => self.writer.line(&format!(
+                "{} = {}",
+                name(identifier),
+                expression(&value.expression)
+            )),
+            Lowered::AssignIndex(identifier, index, value, shift) => self.writer.line(&format!(
+                "{}({}) = {}",
+                name(identifier),
+                expression(&offset(index, *shift).expression),
+                expression(&value.expression)
+            )),
+            Lowered::Output(values, newline) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|value| match (&value.literal_type, values.len()) {
+                        (LiteralType::Integer | LiteralType::String | LiteralType::Char, 1) => {
+                            expression(&value.expression)
+                        }
+                        (LiteralType::Integer | LiteralType::String | LiteralType::Char, _) => {
+                            operand(&value.expression, 5, true)
+                        }
+                        // Visual Basic would write `True`, `2` and the culture's date format.
+                        _ => format!("Show({})", expression(&value.expression)),
+                    })
+                    .collect();
+                let write = if *newline { "WriteLine" } else { "Write" };
+                self.writer
+                    .line(&format!("Console.{}({})", write, values.join(" & ")))
+            }
+            Lowered::If(branches, otherwise) => {
+                for (index, (conditional, block)) in branches.iter().enumerate() {
+                    let keyword = if index == 0 { "If" } else { "ElseIf" };
+                    self.writer.line(&format!(
+                        "{} {} Then",
+                        keyword,
+                        expression(&conditional.expression)
+                    ));
+                    self.body(block);
+                }
+                if let Some(block) = otherwise {
+                    self.writer.line("Else");
+                    self.body(block);
+                }
+                self.writer.line("End If");
+            }
+            Lowered::Call(function, args, _) => {
+                self.writer
+                    .line(&format!("{}({})", name(function), arguments(args)))
+            }
+            Lowered::Return(Some(value)) => self
+                .writer
+                .line(&format!("Return {}", expression(&value.expression))),
+            Lowered::Return(None) => self.writer.line("Return"),
+            Lowered::Assert(conditional, message) => {
+                let message = message.as_ref().map_or(String::new(), |message| {
+                    format!(", {}", literal(&Literal::String(message.clone())))
+                });
+                self.writer.line(&format!(
+                    "Debug.Assert({}{})",
+                    expression(&conditional.expression),
+                    message
+                ))
+            }
+            Lowered::For(identifier, start, end, block) => {
+                self.writer.line(&format!(
+                    "For {} = {} To {}",
+                    name(identifier),
+                    expression(&start.expression),
+                    expression(&end.expression)
+                ));
+                self.body(block);
+                self.writer.line("Next");
+            }
+            Lowered::While(conditional, block) => {
+                self.writer
+                    .line(&format!("While {}", expression(&conditional.expression)));
+                self.body(block);
+                self.writer.line("End While");
+            }
+            Lowered::Repeat(block, conditional) => {
+                self.writer.line("Do");
+                self.body(block);
+                self.writer.line(&format!(
+                    "Loop Until {}",
+                    expression(&conditional.expression)
+                ));
+            }
+        }
+    }
+}
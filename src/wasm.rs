@@ -0,0 +1,25 @@
+use wasm_bindgen::prelude::*;
+
+use crate::playground::{self, Options};
+
+fn options(options: JsValue) -> Result<Options, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(Options::default());
+    }
+    serde_wasm_bindgen::from_value(options).map_err(JsValue::from)
+}
+
+/// `run(source, input, options?)` returns `{ output, diagnostics }`, where `input` holds the lines
+/// `INPUT` reads and `options` may give a `dialect`, `seed` and `maxSteps`.
+#[wasm_bindgen]
+pub fn run(source: &str, input: &str, options: JsValue) -> Result<JsValue, JsValue> {
+    let run = playground::run(source, input, &self::options(options)?)?;
+    serde_wasm_bindgen::to_value(&run).map_err(JsValue::from)
+}
+
+/// `check(source, options?)` returns the diagnostics for `source` without running it.
+#[wasm_bindgen]
+pub fn check(source: &str, options: JsValue) -> Result<JsValue, JsValue> {
+    let diagnostics = playground::check(source, &self::options(options)?)?;
+    serde_wasm_bindgen::to_value(&diagnostics).map_err(JsValue::from)
+}